@@ -1,1311 +1,3583 @@
 use anyhow::{Context, Result};
-use ignore::WalkBuilder;
+use globset::{GlobBuilder, GlobMatcher, GlobSet, GlobSetBuilder};
+use ignore::{WalkBuilder, WalkState};
+use num_format::{Locale, ToFormattedString};
 use rayon::prelude::*;
-use serde::Serialize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver};
 use std::sync::{Arc, Mutex};
-use std::time::{Duration, Instant};
+use std::thread::JoinHandle;
+use std::time::UNIX_EPOCH;
 use tiktoken_rs::CoreBPE;
 
-#[derive(Clone, Debug)]
+/// Scan settings. Serializable so a scan's parameters can be recorded and replayed;
+/// missing fields take their `Default` values and `on_warning` is never serialized.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Options {
     pub encoding: String,
+    // tiktoken-format vocabulary to use instead of `encoding`, e.g. for a fine-tuned model
+    pub encoding_file: Option<PathBuf>,
+    // HuggingFace `tokenizer.json` to use instead of `encoding` (needs the `hf` feature)
+    pub hf_tokenizer: Option<PathBuf>,
     pub include_hidden: bool,
-    // Optional whitelist of file extensions to include (lowercased, no leading dot)
+    // Optional whitelist of file extensions to include (no leading dot; lowercased unless
+    // `case_sensitive_ext`)
     pub include_exts: Option<std::collections::HashSet<String>>,
+    // Extensions to drop, winning over `include_exts`. Entries in either set may span dots
+    // (`test.rs`, `tar.gz`) to match the end of the file name.
+    pub exclude_exts: Option<std::collections::HashSet<String>>,
+    // Match extensions exactly as on disk, so `.C` and `.c` are different extensions
+    pub case_sensitive_ext: bool,
+    // Optional whitelist / blacklist of languages as named by `language_from_path` (lowercased)
+    pub include_langs: Option<std::collections::HashSet<String>>,
+    pub exclude_langs: Option<std::collections::HashSet<String>>,
+    // Glob patterns force-included even when hidden files are excluded (e.g. ".env.example").
+    // Patterns containing '/' match the path relative to the root; others match the file name.
+    pub always_include: Vec<String>,
+    // Suppress non-fatal warnings (skipped large/unreadable files) on stderr
+    pub quiet: bool,
+    // Receives non-fatal warnings instead of stderr when set (called even if `quiet`)
+    #[serde(skip)]
+    pub on_warning: Option<WarningCallback>,
+    // On-disk count cache keyed by path + mtime + size; `None` disables caching
+    pub cache_path: Option<PathBuf>,
+    // Sniff file contents to disambiguate extensions like `.m` and `.h` (slower)
+    pub smart_language: bool,
+    // Keep files marked `linguist-vendored` / `linguist-generated` in `.gitattributes`
+    pub include_vendored: bool,
+    pub include_generated: bool,
+    // Enumerate only files tracked by git (`git ls-files`) instead of walking the filesystem
+    pub git_only: bool,
+    // Chunk size in bytes for tokenizing large files (defaults to `DEFAULT_CHUNK_BYTES`)
+    pub chunk_bytes: Option<usize>,
+    // Skip BPE and approximate tokens as chars / 4 (much faster, not exact)
+    pub estimate: bool,
+    // Lines longer than this many characters count toward `FileCount::long_lines`
+    pub long_line_threshold: usize,
+    // Skip directories named in `COMMON_EXCLUDED_DIRS` even when they are not gitignored
+    pub exclude_common: bool,
+    // Skip package-manager lock files named in `LOCKFILE_NAMES`
+    pub exclude_lockfiles: bool,
+    // Leave out files with no tokens or no non-empty lines (zero-byte files included);
+    // they are listed as skipped and don't count toward `files.len()` or averages
+    pub exclude_empty: bool,
+    // Count UTF-8 files that look binary (control characters, implausible token density)
+    pub include_binary: bool,
+    // Count only the first N bytes of larger files and scale their counts up by size;
+    // affected `FileCount`s are marked `sampled`
+    pub sample_bytes: Option<usize>,
+    // Count non-empty lines; when off, `FileCount::lines` is always 0
+    pub count_lines: bool,
+    // Files smaller / larger than these many bytes are skipped (the maximum defaults to
+    // `MAX_FILE_BYTES`)
+    pub min_file_bytes: Option<u64>,
+    pub max_file_bytes: Option<u64>,
+    // Honor `DIR_CONFIG_NAME` files in and below the root (and above it, up to the git
+    // root), which narrow the filters for their subtree
+    pub dir_configs: bool,
+    // Count special-token text such as `<|endoftext|>` as one special token; when off it is
+    // encoded as ordinary text, like any other literal in source code
+    pub allow_special: bool,
+    // Tokenize text as if reformatted: runs of spaces and tabs collapse to one space and
+    // trailing whitespace is dropped. Only token counts change; lines, words and chars still
+    // describe the file as it is
+    pub normalize_whitespace: bool,
 }
 
 impl Default for Options {
     fn default() -> Self {
         Self {
             encoding: "cl100k_base".to_string(),
+            encoding_file: None,
+            hf_tokenizer: None,
             include_hidden: false,
             include_exts: None,
+            exclude_exts: None,
+            case_sensitive_ext: false,
+            include_langs: None,
+            exclude_langs: None,
+            always_include: Vec::new(),
+            quiet: false,
+            on_warning: None,
+            cache_path: None,
+            smart_language: false,
+            include_vendored: false,
+            include_generated: false,
+            git_only: false,
+            chunk_bytes: None,
+            estimate: false,
+            long_line_threshold: DEFAULT_LONG_LINE_THRESHOLD,
+            exclude_common: false,
+            exclude_lockfiles: false,
+            exclude_empty: false,
+            include_binary: false,
+            sample_bytes: None,
+            count_lines: true,
+            min_file_bytes: None,
+            max_file_bytes: None,
+            dir_configs: true,
+            allow_special: true,
+            normalize_whitespace: false,
         }
     }
 }
 
+/// A non-fatal issue encountered while scanning; the affected entry is skipped.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Warning {
+    /// The directory walker failed to read an entry, e.g. a directory without read
+    /// permission; the rest of the walk goes on without it.
+    Walk {
+        path: Option<PathBuf>,
+        message: String,
+    },
+    /// File metadata could not be read.
+    Metadata { path: PathBuf, message: String },
+    /// File exceeds the size limit.
+    TooLarge { path: PathBuf, bytes: u64 },
+    /// File is under `Options::min_file_bytes`.
+    TooSmall { path: PathBuf, bytes: u64 },
+    /// File contents could not be read.
+    Read { path: PathBuf, message: String },
+    /// The count cache could not be written.
+    Cache { path: PathBuf, message: String },
+    /// `git_only` was requested but git could not list tracked files.
+    NotGitRepo { path: PathBuf, message: String },
+    /// File is empty or whitespace-only and `exclude_empty` is set.
+    Empty { path: PathBuf },
+    /// File is valid UTF-8 but looks binary and `include_binary` is not set.
+    SuspectedBinary { path: PathBuf },
+    /// File is `linguist-vendored` in `.gitattributes` and `include_vendored` is not set.
+    Vendored { path: PathBuf },
+    /// File is `linguist-generated` in `.gitattributes` and `include_generated` is not set.
+    Generated { path: PathBuf },
+}
+
+impl Warning {
+    /// Whether the warning means an entry was left out of the counts.
+    pub fn is_skip(&self) -> bool {
+        matches!(
+            self,
+            Warning::Walk { .. }
+                | Warning::Metadata { .. }
+                | Warning::TooLarge { .. }
+                | Warning::TooSmall { .. }
+                | Warning::Read { .. }
+                | Warning::Empty { .. }
+                | Warning::SuspectedBinary { .. }
+                | Warning::Vendored { .. }
+                | Warning::Generated { .. }
+        )
+    }
+
+    /// The file the warning refers to, if known.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Warning::Walk { path, .. } => path.as_deref(),
+            Warning::Metadata { path, .. }
+            | Warning::TooLarge { path, .. }
+            | Warning::TooSmall { path, .. }
+            | Warning::Read { path, .. }
+            | Warning::Cache { path, .. }
+            | Warning::NotGitRepo { path, .. }
+            | Warning::Empty { path }
+            | Warning::SuspectedBinary { path }
+            | Warning::Vendored { path }
+            | Warning::Generated { path } => Some(path),
+        }
+    }
+}
+
+impl std::error::Error for Warning {}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Warning::Walk { message, .. } => write!(f, "skipping entry: {message}"),
+            Warning::Metadata { path, message } => {
+                write!(
+                    f,
+                    "failed to get metadata for {}: {message}",
+                    path.display()
+                )
+            }
+            // Whole megabytes for the default limit, bytes for a lower `max_file_bytes`
+            Warning::TooLarge { path, bytes } if *bytes >= 1024 * 1024 => write!(
+                f,
+                "skipping large file ({}MB): {}",
+                bytes / 1024 / 1024,
+                path.display()
+            ),
+            Warning::TooLarge { path, bytes } => {
+                write!(f, "skipping large file ({bytes} bytes): {}", path.display())
+            }
+            Warning::TooSmall { path, bytes } => {
+                write!(f, "skipping small file ({bytes} bytes): {}", path.display())
+            }
+            Warning::Read { path, message } => {
+                write!(f, "failed to read {}: {message}", path.display())
+            }
+            Warning::Cache { path, message } => {
+                write!(f, "failed to write cache {}: {message}", path.display())
+            }
+            Warning::NotGitRepo { path, message } => write!(
+                f,
+                "cannot list git-tracked files in {} ({message}); walking the filesystem instead",
+                path.display()
+            ),
+            Warning::Empty { path } => write!(f, "skipping empty file: {}", path.display()),
+            Warning::SuspectedBinary { path } => {
+                write!(f, "skipping suspected binary file: {}", path.display())
+            }
+            Warning::Vendored { path } => {
+                write!(f, "skipping vendored file: {}", path.display())
+            }
+            Warning::Generated { path } => {
+                write!(f, "skipping generated file: {}", path.display())
+            }
+        }
+    }
+}
+
+/// Shareable warning handler stored in `Options::on_warning`.
+#[derive(Clone)]
+pub struct WarningCallback(pub Arc<dyn Fn(&Warning) + Send + Sync>);
+
+impl WarningCallback {
+    pub fn new<F: Fn(&Warning) + Send + Sync + 'static>(f: F) -> Self {
+        Self(Arc::new(f))
+    }
+}
+
+impl std::fmt::Debug for WarningCallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("WarningCallback(..)")
+    }
+}
+
 #[derive(Debug, Serialize, Clone)]
 pub struct FileCount {
     pub path: PathBuf,
     pub tokens: usize,
     pub lines: usize,
+    // Every line including blank ones, as editors and `wc -l` count them; `lines` plus
+    // `blank_lines` is `total_lines`
+    pub total_lines: usize,
+    pub blank_lines: usize,
+    pub language: String,
+    pub bytes: u64,
+    // Whitespace-separated words and Unicode scalar values, as a cross-check for tokens
+    pub words: usize,
+    pub chars: usize,
+    // Longest line in characters, and lines longer than `Options::long_line_threshold`
+    pub max_line_len: usize,
+    pub long_lines: usize,
+    // Counts were extrapolated from the first `Options::sample_bytes` of the file
+    pub sampled: bool,
+}
+
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct CountResult {
+    pub total: usize,
+    // Sorted by path, so serialized results are stable across runs and diffable
+    pub files: Vec<FileCount>,
+    // Entries left out because they could not be walked, stat'ed or read, sorted by path
+    pub skipped: Vec<Warning>,
+}
+
+impl CountResult {
+    /// Combine results from separate scans, e.g. of several roots counted in parallel.
+    /// Per-language summaries are computed from `files`, so they merge for free.
+    /// `other`'s files follow this result's, keeping each scan's path order.
+    pub fn merge(mut self, other: CountResult) -> CountResult {
+        self.total += other.total;
+        self.files.extend(other.files);
+        self.skipped.extend(other.skipped);
+        self
+    }
+}
+
+/// Enumerate files under `root` honoring ignore rules and `opts` filters, sorted by path.
+/// This is exactly the set of files the counting functions visit, so callers can run
+/// their own per-file logic with identical selection.
+///
+/// ```
+/// use loctok::{filtered_paths, Options};
+///
+/// let paths = filtered_paths("src", &Options::default())?;
+/// assert!(paths.iter().any(|p| p.ends_with("lib.rs")));
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub fn filtered_paths<P: AsRef<Path>>(root: P, opts: &Options) -> Result<Vec<PathBuf>> {
+    Ok(filtered_paths_and_skips(root.as_ref(), opts)?.0)
+}
+
+/// `filtered_paths` plus the vendored/generated files it dropped, as `skipped` entries.
+fn filtered_paths_and_skips(root: &Path, opts: &Options) -> Result<(Vec<PathBuf>, Vec<Warning>)> {
+    let tracked = if opts.git_only {
+        match git_tracked_files(root) {
+            Ok(tracked) => Some(tracked),
+            Err(err) => {
+                warn(
+                    opts,
+                    Warning::NotGitRepo {
+                        path: root.to_path_buf(),
+                        message: format!("{err:#}"),
+                    },
+                );
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let list_files = |skip_hidden: bool| match &tracked {
+        Some(tracked) => tracked
+            .iter()
+            .filter(|p| {
+                (!skip_hidden || !is_hidden_under(root, p))
+                    && (!opts.exclude_common || !in_common_excluded_dir(root, p))
+                    && ext_allowed(p, opts)
+            })
+            .cloned()
+            .collect(),
+        None => walk_files(root, skip_hidden, opts),
+    };
+    let mut paths: Vec<PathBuf> = list_files(!opts.include_hidden);
+
+    // Drop vendored/generated files the way GitHub's language stats do. Like empty files
+    // they are listed as skipped without a stderr line each, since dropping them is the norm
+    let mut linguist_skips = Vec::new();
+    if !(opts.include_vendored && opts.include_generated) {
+        if let Some(attrs) = LinguistAttributes::load(root) {
+            paths.retain(|path| {
+                let rel = path.strip_prefix(root).unwrap_or(path);
+                let (vendored, generated) = attrs.lookup(rel);
+                let skip = if vendored && !opts.include_vendored {
+                    Warning::Vendored { path: path.clone() }
+                } else if generated && !opts.include_generated {
+                    Warning::Generated { path: path.clone() }
+                } else {
+                    return true;
+                };
+                linguist_skips.push(skip);
+                false
+            });
+        }
+    }
+
+    // Re-add hidden files that match `always_include` which the hidden filter dropped
+    if !opts.include_hidden && !opts.always_include.is_empty() {
+        let matcher =
+            PathGlobs::new(&opts.always_include).context("invalid always-include pattern")?;
+        let seen: std::collections::HashSet<PathBuf> = paths.iter().cloned().collect();
+        for path in list_files(false) {
+            if seen.contains(&path) {
+                continue;
+            }
+            let rel = path.strip_prefix(root).unwrap_or(&path);
+            // Never surface git internals even though they are hidden too
+            if rel.components().any(|c| c.as_os_str() == ".git") {
+                continue;
+            }
+            if matcher.is_match(rel) {
+                paths.push(path);
+            }
+        }
+    }
+
+    if opts.dir_configs && root.is_dir() {
+        let mut configs = DirConfigs::for_root(root, opts.case_sensitive_ext);
+        let mut kept = Vec::with_capacity(paths.len());
+        for path in paths {
+            if configs.allows(root, &path)? {
+                kept.push(path);
+            }
+        }
+        paths = kept;
+    }
+    Ok((paths, linguist_skips))
+}
+
+/// Per-directory config file. It applies to everything beneath its directory and can only
+/// narrow what is counted: a file must pass every such config from the git root down, so a
+/// nested `ext` list intersects with its parents' and excludes accumulate.
+pub const DIR_CONFIG_NAME: &str = ".loctok.toml";
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct DirConfig {
+    // Keep only files with these extensions (entries may span dots, like `--ext`)
+    ext: Option<Vec<String>>,
+    // Drop files with these extensions
+    exclude_ext: Option<Vec<String>>,
+    // Drop files matching these globs; patterns with '/' match the path relative to the
+    // config's directory, others the file name
+    exclude: Option<Vec<String>>,
+}
+
+struct DirRules {
+    exts: Option<std::collections::HashSet<String>>,
+    exclude_exts: std::collections::HashSet<String>,
+    exclude: PathGlobs,
+}
+
+/// `DIR_CONFIG_NAME` rules by directory, loaded on first use.
+#[derive(Default)]
+struct DirConfigs {
+    by_dir: HashMap<PathBuf, Option<DirRules>>,
+    // Keep extension case, as with `Options::case_sensitive_ext`
+    case_sensitive: bool,
+    // Directories above the scan root whose configs also apply, outermost first, each with
+    // the root's path relative to it
+    ancestors: Vec<(PathBuf, PathBuf)>,
+}
+
+impl DirConfigs {
+    /// Configs for a scan of `root`. Besides those below it, the configs of its ancestors up
+    /// to the enclosing git repository's root (the filesystem root outside a repository)
+    /// apply, so scanning a subdirectory never loosens its filters.
+    fn for_root(root: &Path, case_sensitive: bool) -> Self {
+        let mut ancestors = Vec::new();
+        if let Ok(root) = fs::canonicalize(root) {
+            if !root.join(".git").exists() {
+                for dir in root.ancestors().skip(1) {
+                    let rel = root.strip_prefix(dir).unwrap_or(&root).to_path_buf();
+                    ancestors.push((dir.to_path_buf(), rel));
+                    if dir.join(".git").exists() {
+                        break;
+                    }
+                }
+            }
+        }
+        ancestors.reverse();
+        Self {
+            case_sensitive,
+            ancestors,
+            ..Self::default()
+        }
+    }
+
+    fn rules(&mut self, dir: &Path) -> Result<Option<&DirRules>> {
+        if !self.by_dir.contains_key(dir) {
+            let path = dir.join(DIR_CONFIG_NAME);
+            let rules = if path.is_file() {
+                let text = fs::read_to_string(&path)
+                    .with_context(|| format!("failed to read config {}", path.display()))?;
+                let config: DirConfig = toml::from_str(&text)
+                    .with_context(|| format!("invalid config {}", path.display()))?;
+                let case_sensitive = self.case_sensitive;
+                let normalize = |exts: Vec<String>| -> std::collections::HashSet<String> {
+                    exts.iter()
+                        .map(|e| e.trim().trim_start_matches('.'))
+                        .map(|e| {
+                            if case_sensitive {
+                                e.to_string()
+                            } else {
+                                e.to_ascii_lowercase()
+                            }
+                        })
+                        .collect()
+                };
+                Some(DirRules {
+                    exts: config.ext.map(normalize),
+                    exclude_exts: normalize(config.exclude_ext.unwrap_or_default()),
+                    exclude: PathGlobs::new(&config.exclude.unwrap_or_default())
+                        .with_context(|| format!("invalid config {}", path.display()))?,
+                })
+            } else {
+                None
+            };
+            self.by_dir.insert(dir.to_path_buf(), rules);
+        }
+        Ok(self.by_dir[dir].as_ref())
+    }
+
+    /// Whether `path` under `root` passes the configs above `root`, of `root` and of every
+    /// directory between them.
+    fn allows(&mut self, root: &Path, path: &Path) -> Result<bool> {
+        let Ok(rel) = path.strip_prefix(root) else {
+            return Ok(true);
+        };
+        let suffixes = ext_suffixes(path, self.case_sensitive);
+        for i in 0..self.ancestors.len() {
+            let (dir, root_rel) = self.ancestors[i].clone();
+            if self.rejects(&dir, &root_rel.join(rel), &suffixes)? {
+                return Ok(false);
+            }
+        }
+        let mut dir = root.to_path_buf();
+        let parents: Vec<_> = rel
+            .parent()
+            .into_iter()
+            .flat_map(Path::components)
+            .collect();
+        for i in 0..=parents.len() {
+            if i > 0 {
+                dir.push(parents[i - 1]);
+            }
+            if self.rejects(&dir, path.strip_prefix(&dir).unwrap_or(path), &suffixes)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Whether the config in `dir` drops the file at `rel` (relative to `dir`) with
+    /// extension `suffixes`.
+    fn rejects(&mut self, dir: &Path, rel: &Path, suffixes: &[String]) -> Result<bool> {
+        let Some(rules) = self.rules(dir)? else {
+            return Ok(false);
+        };
+        let has = |exts: &std::collections::HashSet<String>| {
+            suffixes.iter().any(|s| exts.contains(s.as_str()))
+        };
+        Ok(rules.exts.as_ref().is_some_and(|exts| !has(exts))
+            || has(&rules.exclude_exts)
+            || rules.exclude.is_match(rel))
+    }
+}
+
+/// Walk `root` honoring git ignore rules and return every regular file passing the extension filter.
+fn walk_files(root: &Path, skip_hidden: bool, opts: &Options) -> Vec<PathBuf> {
+    let mut builder = WalkBuilder::new(root);
+    // Honor .gitignore and related git rules explicitly; control hidden files via option
+    builder.hidden(skip_hidden);
+    builder.follow_links(false);
+    // Apply ignore files from ancestor directories too, so scanning `src/` inside a repo
+    // still honors rules from the repo root's .gitignore
+    builder.parents(true);
+    builder.ignore(true); // respect .ignore
+    builder.git_ignore(true); // respect .gitignore
+    builder.git_global(true); // respect global gitignore
+    builder.git_exclude(true); // respect .git/info/exclude
+                               // In environments without a .git directory, also treat .gitignore as a custom ignore file
+    builder.add_custom_ignore_filename(".gitignore");
+    if opts.exclude_common {
+        // Prune whole subtrees rather than filtering their files one by one
+        builder.filter_entry(|dent| {
+            dent.depth() == 0
+                || !dent.file_type().is_some_and(|t| t.is_dir())
+                || !dent
+                    .file_name()
+                    .to_str()
+                    .is_some_and(|name| COMMON_EXCLUDED_DIRS.contains(&name))
+        });
+    }
+
+    // Walk with multiple threads; directory reads dominate on slow (e.g. network) filesystems
+    let collected: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+    builder.build_parallel().run(|| {
+        Box::new(|dent| {
+            let dent = match dent {
+                Ok(d) => d,
+                Err(err) => {
+                    warn(
+                        opts,
+                        Warning::Walk {
+                            path: walk_error_path(&err).map(Path::to_path_buf),
+                            message: err.to_string(),
+                        },
+                    );
+                    return WalkState::Continue;
+                }
+            };
+
+            if dent.file_type().is_some_and(|t| t.is_file()) && ext_allowed(dent.path(), opts) {
+                collected.lock().unwrap().push(dent.into_path());
+            }
+            WalkState::Continue
+        })
+    });
+    let mut paths = collected.into_inner().unwrap();
+    // Parallel traversal order is nondeterministic; sort for stable results
+    paths.sort();
+    paths
+}
+
+/// Regular files tracked by git under `root`, sorted; untracked files are never listed.
+fn git_tracked_files(root: &Path) -> Result<Vec<PathBuf>> {
+    // `git -C` needs a directory; a single-file root is passed as a pathspec instead
+    let (dir, pathspec) = if root.is_file() {
+        let dir = match root.parent() {
+            Some(p) if !p.as_os_str().is_empty() => p,
+            _ => Path::new("."),
+        };
+        (dir, root.file_name().map(PathBuf::from))
+    } else {
+        (root, None)
+    };
+    let mut args: Vec<&std::ffi::OsStr> = ["ls-files", "-z", "--cached", "--"]
+        .iter()
+        .map(std::ffi::OsStr::new)
+        .collect();
+    args.extend(pathspec.as_deref().map(Path::as_os_str));
+    let mut paths: Vec<PathBuf> = run_git(dir, &args)?
+        .split(|b| *b == 0)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| dir.join(String::from_utf8_lossy(entry).as_ref()))
+        // Skips files deleted in the working tree and submodule directories
+        .filter(|p| p.is_file())
+        .collect();
+    paths.sort();
+    paths.dedup();
+    Ok(paths)
+}
+
+/// Run `git -C dir <args>` and return its stdout, failing with git's stderr.
+fn run_git<S: AsRef<std::ffi::OsStr>>(dir: &Path, args: &[S]) -> Result<Vec<u8>> {
+    let out = std::process::Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .context("failed to run git")?;
+    if !out.status.success() {
+        anyhow::bail!("{}", String::from_utf8_lossy(&out.stderr).trim());
+    }
+    Ok(out.stdout)
+}
+
+/// Whether a path argument names a remote git repository (`https://`, `ssh://`, `git@host:`,
+/// ...) rather than a local path.
+pub fn is_git_url(arg: &str) -> bool {
+    const SCHEMES: &[&str] = &["https://", "http://", "ssh://", "git://", "file://"];
+    SCHEMES.iter().any(|s| arg.starts_with(s))
+        || arg
+            .strip_prefix("git@")
+            .is_some_and(|rest| rest.contains(':'))
+}
+
+/// Shallow-clone `url` into `dest`, which must not exist yet, checking out `git_ref` (a
+/// branch or tag) instead of the default branch when given.
+pub fn shallow_clone(url: &str, git_ref: Option<&str>, dest: &Path) -> Result<()> {
+    let parent = dest.parent().unwrap_or(Path::new("."));
+    fs::create_dir_all(parent).with_context(|| format!("failed to create {}", parent.display()))?;
+    let mut args: Vec<std::ffi::OsString> = vec![
+        "clone".into(),
+        "--quiet".into(),
+        "--depth".into(),
+        "1".into(),
+    ];
+    if let Some(git_ref) = git_ref {
+        args.extend(["--branch".into(), git_ref.into()]);
+    }
+    args.extend(["--".into(), url.into(), dest.as_os_str().to_owned()]);
+    run_git(parent, &args).with_context(|| format!("failed to clone {url}"))?;
+    Ok(())
+}
+
+/// Dependency, build-output and tool-cache directories skipped by `Options::exclude_common`.
+pub const COMMON_EXCLUDED_DIRS: &[&str] = &[
+    ".git",
+    ".gradle",
+    ".mypy_cache",
+    ".next",
+    ".nuxt",
+    ".pytest_cache",
+    ".tox",
+    ".venv",
+    "__pycache__",
+    "bower_components",
+    "build",
+    "coverage",
+    "dist",
+    "node_modules",
+    "target",
+    "vendor",
+    "venv",
+];
+
+/// Package-manager lock files skipped by `Options::exclude_lockfiles`, matched by exact
+/// file name.
+pub const LOCKFILE_NAMES: &[&str] = &[
+    "Cargo.lock",
+    "Gemfile.lock",
+    "Package.resolved",
+    "Pipfile.lock",
+    "Podfile.lock",
+    "bun.lock",
+    "composer.lock",
+    "deno.lock",
+    "flake.lock",
+    "go.sum",
+    "gradle.lockfile",
+    "mix.lock",
+    "npm-shrinkwrap.json",
+    "package-lock.json",
+    "packages.lock.json",
+    "pdm.lock",
+    "pnpm-lock.yaml",
+    "poetry.lock",
+    "pubspec.lock",
+    "uv.lock",
+    "yarn.lock",
+];
+
+/// Whether a directory below `root` on `path` is one of `COMMON_EXCLUDED_DIRS`.
+fn in_common_excluded_dir(root: &Path, path: &Path) -> bool {
+    let rel = path.strip_prefix(root).unwrap_or(path);
+    let mut dirs = rel.components();
+    dirs.next_back(); // the file itself
+    dirs.any(|c| {
+        c.as_os_str()
+            .to_str()
+            .is_some_and(|s| COMMON_EXCLUDED_DIRS.contains(&s))
+    })
+}
+
+/// Whether any component of `path` below `root` is a dotfile or dot-directory.
+fn is_hidden_under(root: &Path, path: &Path) -> bool {
+    let rel = path.strip_prefix(root).unwrap_or(path);
+    rel.components().any(|c| {
+        c.as_os_str()
+            .to_str()
+            .is_some_and(|s| s.starts_with('.') && s != "." && s != "..")
+    })
+}
+
+/// Whether `path` passes the optional lockfile, extension and language filters in `opts`.
+fn ext_allowed(path: &Path, opts: &Options) -> bool {
+    if opts.exclude_lockfiles
+        && path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .is_some_and(|n| LOCKFILE_NAMES.contains(&n))
+    {
+        return false;
+    }
+    if opts.include_langs.is_some() || opts.exclude_langs.is_some() {
+        let lang = language_from_path(path).to_lowercase();
+        if opts
+            .include_langs
+            .as_ref()
+            .is_some_and(|l| !l.contains(&lang))
+            || opts
+                .exclude_langs
+                .as_ref()
+                .is_some_and(|l| l.contains(&lang))
+        {
+            return false;
+        }
+    }
+    if opts.include_exts.is_none() && opts.exclude_exts.is_none() {
+        return true;
+    }
+    let suffixes = ext_suffixes(path, opts.case_sensitive_ext);
+    let matches = |exts: &std::collections::HashSet<String>| {
+        suffixes.iter().any(|s| exts.contains(s.as_str()))
+    };
+    if opts.exclude_exts.as_ref().is_some_and(matches) {
+        return false;
+    }
+    opts.include_exts.as_ref().is_none_or(matches)
+}
+
+/// The entry a walk error is about, if the walker recorded one.
+fn walk_error_path(err: &ignore::Error) -> Option<&Path> {
+    match err {
+        ignore::Error::WithPath { path, .. } => Some(path),
+        ignore::Error::Loop { child, .. } => Some(child),
+        ignore::Error::WithDepth { err, .. } | ignore::Error::WithLineNumber { err, .. } => {
+            walk_error_path(err)
+        }
+        _ => None,
+    }
+}
+
+/// Dotted suffixes of the file name, longest first: `a.Test.rs` gives `["test.rs", "rs"]`
+/// (`["Test.rs", "rs"]` when `case_sensitive`). A name without one (including dotfiles
+/// like `.env`) gives `[""]`.
+fn ext_suffixes(path: &Path, case_sensitive: bool) -> Vec<String> {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let name = if case_sensitive {
+        name
+    } else {
+        name.to_ascii_lowercase()
+    };
+    let name = name.strip_prefix('.').unwrap_or(&name);
+    let suffixes: Vec<String> = name
+        .match_indices('.')
+        .map(|(i, _)| name[i + 1..].to_string())
+        .collect();
+    if suffixes.is_empty() {
+        vec![String::new()]
+    } else {
+        suffixes
+    }
+}
+
+/// Compiled glob patterns, e.g. `Options::always_include`. Patterns containing '/' match
+/// the path relative to a base directory; others match the file name.
+struct PathGlobs {
+    by_path: GlobSet,
+    by_name: GlobSet,
+}
+
+impl PathGlobs {
+    fn new(patterns: &[String]) -> Result<Self> {
+        let mut by_path = GlobSetBuilder::new();
+        let mut by_name = GlobSetBuilder::new();
+        for pat in patterns {
+            let glob = GlobBuilder::new(pat.trim_start_matches('/'))
+                .literal_separator(true)
+                .build()
+                .with_context(|| format!("invalid glob pattern: {pat}"))?;
+            if pat.contains('/') {
+                by_path.add(glob);
+            } else {
+                by_name.add(glob);
+            }
+        }
+        Ok(Self {
+            by_path: by_path.build()?,
+            by_name: by_name.build()?,
+        })
+    }
+
+    fn is_match(&self, rel: &Path) -> bool {
+        self.by_path.is_match(rel) || rel.file_name().is_some_and(|n| self.by_name.is_match(n))
+    }
+}
+
+/// `linguist-vendored` / `linguist-generated` rules from the root `.gitattributes`.
+struct LinguistAttributes {
+    rules: Vec<LinguistRule>,
+}
+
+struct LinguistRule {
+    matcher: GlobMatcher,
+    // Patterns without a slash match the file name at any depth
+    by_name: bool,
+    vendored: Option<bool>,
+    generated: Option<bool>,
+}
+
+impl LinguistAttributes {
+    /// Parse `<root>/.gitattributes`; `None` when absent or it sets no linguist markers.
+    fn load(root: &Path) -> Option<Self> {
+        let text = fs::read_to_string(root.join(".gitattributes")).ok()?;
+        let mut rules = Vec::new();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.split_whitespace();
+            let Some(pattern) = parts.next() else {
+                continue;
+            };
+            let (mut vendored, mut generated) = (None, None);
+            for attr in parts {
+                // `attr` / `attr=true` set it; `-attr`, `!attr` and `attr=false` unset it
+                let (name, value) = match attr.split_once('=') {
+                    Some((name, value)) => (name, value != "false"),
+                    None => match attr.strip_prefix(['-', '!']) {
+                        Some(name) => (name, false),
+                        None => (attr, true),
+                    },
+                };
+                match name {
+                    "linguist-vendored" => vendored = Some(value),
+                    "linguist-generated" => generated = Some(value),
+                    _ => {}
+                }
+            }
+            if vendored.is_none() && generated.is_none() {
+                continue;
+            }
+            // Unparseable patterns are ignored, as git does
+            let Ok(glob) = GlobBuilder::new(pattern.trim_start_matches('/'))
+                .literal_separator(true)
+                .build()
+            else {
+                continue;
+            };
+            rules.push(LinguistRule {
+                matcher: glob.compile_matcher(),
+                by_name: !pattern.contains('/'),
+                vendored,
+                generated,
+            });
+        }
+        (!rules.is_empty()).then_some(Self { rules })
+    }
+
+    /// `(vendored, generated)` for a root-relative path; later lines win, as in git.
+    fn lookup(&self, rel: &Path) -> (bool, bool) {
+        let (mut vendored, mut generated) = (false, false);
+        for rule in &self.rules {
+            let hit = if rule.by_name {
+                rel.file_name().is_some_and(|n| rule.matcher.is_match(n))
+            } else {
+                rule.matcher.is_match(rel)
+            };
+            if hit {
+                vendored = rule.vendored.unwrap_or(vendored);
+                generated = rule.generated.unwrap_or(generated);
+            }
+        }
+        (vendored, generated)
+    }
+}
+
+/// The tiktoken encoding named `encoding`, boxed as a `Tokenizer`.
+pub fn get_encoder(encoding: &str) -> Result<Box<dyn Tokenizer>> {
+    Ok(Box::new(tiktoken_encoder(encoding)?))
+}
+
+fn tiktoken_encoder(encoding: &str) -> Result<CoreBPE> {
+    let info = encoding_info(encoding).with_context(|| {
+        let names: Vec<&str> = ENCODINGS.iter().map(|e| e.name).collect();
+        format!(
+            "Unsupported encoding: {encoding} (supported: {})",
+            names.join(", ")
+        )
+    })?;
+    (info.load)().with_context(|| format!("Failed to init {encoding}"))
+}
+
+/// A built-in tiktoken encoding and the model families that use it.
+#[derive(Debug, Clone, Copy)]
+pub struct EncodingInfo {
+    pub name: &'static str,
+    /// Approximate vocabulary size
+    pub token_number: usize,
+    pub models: &'static [&'static str],
+    load: fn() -> Result<CoreBPE>,
+}
+
+/// Every encoding `get_encoder` accepts, default first.
+pub const ENCODINGS: &[EncodingInfo] = &[
+    EncodingInfo {
+        name: "o200k_base",
+        token_number: 200_000,
+        models: &["GPT-4o", "GPT-4.1", "o1", "o3", "o4"],
+        load: tiktoken_rs::o200k_base,
+    },
+    EncodingInfo {
+        name: "cl100k_base",
+        token_number: 100_000,
+        models: &["ChatGPT", "text-embedding-ada-002"],
+        load: tiktoken_rs::cl100k_base,
+    },
+    EncodingInfo {
+        name: "p50k_base",
+        token_number: 50_000,
+        models: &["Code models", "text-davinci-002", "text-davinci-003"],
+        load: tiktoken_rs::p50k_base,
+    },
+    EncodingInfo {
+        name: "p50k_edit",
+        token_number: 50_000,
+        models: &["text-davinci-edit-001", "code-davinci-edit-001"],
+        load: tiktoken_rs::p50k_edit,
+    },
+    EncodingInfo {
+        name: "r50k_base",
+        token_number: 50_000,
+        models: &["GPT-3 (davinci)"],
+        load: tiktoken_rs::r50k_base,
+    },
+];
+
+pub fn encoding_info(name: &str) -> Option<&'static EncodingInfo> {
+    ENCODINGS.iter().find(|e| e.name == name)
+}
+
+/// Pre-tokenization pattern for vocabularies loaded by `load_encoder_file` (cl100k_base's).
+const CUSTOM_ENCODING_PATTERN: &str = "(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\\r\\n\\p{L}\\p{N}]?\\p{L}+|\\p{N}{1,3}| ?[^\\s\\p{L}\\p{N}]+[\\r\\n]*|\\s*[\\r\\n]+|\\s+(?!\\S)|\\s+";
+
+/// Load a tiktoken-format vocabulary: one `<base64 token> <rank>` pair per line, as in
+/// OpenAI's `.tiktoken` files. `<|endoftext|>` is added after the highest rank, and text is
+/// split with the cl100k_base pattern before BPE.
+pub fn load_encoder_file(path: &Path) -> Result<CoreBPE> {
+    use base64::{engine::general_purpose, Engine as _};
+
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("failed to read encoding file {}", path.display()))?;
+    let mut encoder = rustc_hash::FxHashMap::default();
+    for (i, line) in text
+        .lines()
+        .enumerate()
+        .filter(|(_, l)| !l.trim().is_empty())
+    {
+        let parsed = line.split_once(' ').and_then(|(token, rank)| {
+            let token = general_purpose::STANDARD.decode(token).ok()?;
+            Some((token, rank.trim().parse::<usize>().ok()?))
+        });
+        let (token, rank) = parsed.with_context(|| {
+            format!(
+                "{}:{}: expected `<base64 token> <rank>`",
+                path.display(),
+                i + 1
+            )
+        })?;
+        encoder.insert(token, rank);
+    }
+    anyhow::ensure!(!encoder.is_empty(), "{} has no tokens", path.display());
+    let next_rank = encoder.values().max().map_or(0, |r| r + 1);
+    let mut special = rustc_hash::FxHashMap::default();
+    special.insert("<|endoftext|>".to_string(), next_rank);
+    CoreBPE::new(encoder, special, CUSTOM_ENCODING_PATTERN)
+        .with_context(|| format!("invalid encoding file {}", path.display()))
+}
+
+/// A token counting backend. Scans only need a count, so tiktoken, HuggingFace tokenizers
+/// and the chars / 4 estimate plug in alike.
+pub trait Tokenizer: Send + Sync {
+    /// Number of tokens in `text`, treating special-token text such as `<|endoftext|>` as
+    /// the special token itself.
+    fn count_special(&self, text: &str) -> usize;
+
+    /// Number of tokens in `text` with special-token text encoded as ordinary text.
+    /// Backends without special tokens count the same either way.
+    fn count_ordinary(&self, text: &str) -> usize {
+        self.count_special(text)
+    }
+
+    /// Count large inputs in chunks of about `chunk_bytes` (see
+    /// `count_tokens_in_text_chunked`).
+    fn count_chunked(&self, text: &str, chunk_bytes: usize) -> usize {
+        count_tokens_in_text_chunked(self, text, chunk_bytes)
+    }
+}
+
+impl<T: Tokenizer + ?Sized> Tokenizer for Box<T> {
+    fn count_special(&self, text: &str) -> usize {
+        (**self).count_special(text)
+    }
+
+    fn count_ordinary(&self, text: &str) -> usize {
+        (**self).count_ordinary(text)
+    }
+
+    fn count_chunked(&self, text: &str, chunk_bytes: usize) -> usize {
+        (**self).count_chunked(text, chunk_bytes)
+    }
+}
+
+impl Tokenizer for CoreBPE {
+    fn count_special(&self, text: &str) -> usize {
+        self.encode_with_special_tokens(text).len()
+    }
+
+    fn count_ordinary(&self, text: &str) -> usize {
+        self.encode_ordinary(text).len()
+    }
+}
+
+/// Wraps a backend so that all counting ignores special tokens (`Options::allow_special`
+/// turned off).
+pub struct NoSpecialTokens<T>(pub T);
+
+impl<T: Tokenizer> Tokenizer for NoSpecialTokens<T> {
+    fn count_special(&self, text: &str) -> usize {
+        self.0.count_ordinary(text)
+    }
+
+    fn count_ordinary(&self, text: &str) -> usize {
+        self.0.count_ordinary(text)
+    }
+}
+
+/// The `Options::estimate` backend: one token per four characters (see `estimate_tokens`).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CharEstimate;
+
+impl Tokenizer for CharEstimate {
+    fn count_special(&self, text: &str) -> usize {
+        estimate_tokens(text)
+    }
+
+    // Splitting would only add rounding per chunk
+    fn count_chunked(&self, text: &str, _chunk_bytes: usize) -> usize {
+        estimate_tokens(text)
+    }
+}
+
+#[cfg(feature = "hf")]
+impl Tokenizer for tokenizers::Tokenizer {
+    fn count_special(&self, text: &str) -> usize {
+        // Special tokens such as BOS are not part of the text, so they are not added
+        self.encode(text, false).map_or(0, |enc| enc.len())
+    }
+}
+
+/// Load a HuggingFace `tokenizer.json`, e.g. from a Llama or Mistral model repository.
+#[cfg(feature = "hf")]
+pub fn load_hf_tokenizer(path: &Path) -> Result<tokenizers::Tokenizer> {
+    tokenizers::Tokenizer::from_file(path)
+        .map_err(|err| anyhow::anyhow!("{err}"))
+        .with_context(|| format!("failed to load tokenizer {}", path.display()))
+}
+
+/// The tokenizer selected by `opts`: `CharEstimate` in estimate mode, `hf_tokenizer` or
+/// `encoding_file` when set, else the named `encoding`.
+pub fn encoder_for(opts: &Options) -> Result<Box<dyn Tokenizer>> {
+    if opts.estimate {
+        return Ok(Box::new(CharEstimate));
+    }
+    if let Some(path) = &opts.hf_tokenizer {
+        #[cfg(feature = "hf")]
+        return Ok(Box::new(load_hf_tokenizer(path)?));
+        #[cfg(not(feature = "hf"))]
+        anyhow::bail!(
+            "cannot load {}: loctok was built without the `hf` feature",
+            path.display()
+        );
+    }
+    let encoder: Box<dyn Tokenizer> = match &opts.encoding_file {
+        Some(path) => Box::new(load_encoder_file(path)?),
+        None => get_encoder(&opts.encoding)?,
+    };
+    Ok(if opts.allow_special {
+        encoder
+    } else {
+        Box::new(NoSpecialTokens(encoder))
+    })
+}
+
+/// A tokenizer ready for counting, identified by its encoding name.
+/// Build once with `Encoding::new` and reuse it across `count_text` calls.
+pub struct Encoding {
+    name: String,
+    bpe: Box<dyn Tokenizer>,
+}
+
+impl Encoding {
+    pub fn new(name: &str) -> Result<Self> {
+        Ok(Self {
+            name: name.to_string(),
+            bpe: get_encoder(name)?,
+        })
+    }
+
+    /// Load a tiktoken-format vocabulary file (see `load_encoder_file`), named by its path.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        Ok(Self {
+            name: path.display().to_string(),
+            bpe: Box::new(load_encoder_file(path)?),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl std::fmt::Debug for Encoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Encoding")
+            .field("name", &self.name)
+            .finish()
+    }
+}
+
+/// Counts for a piece of in-memory text (no path or size information).
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct FileCountLite {
+    pub tokens: usize,
+    pub lines: usize,
+    pub language: Option<String>,
+}
+
+/// Count tokens and non-empty lines of raw text, e.g. an unsaved editor buffer.
+/// `language` is passed through unchanged so callers can tag the result.
+pub fn count_text(text: &str, language: Option<&str>, encoding: &Encoding) -> FileCountLite {
+    FileCountLite {
+        tokens: count_tokens_in_text(&encoding.bpe, text),
+        lines: count_non_empty_lines(text),
+        language: language.map(str::to_string),
+    }
+}
+
+/// Default for `Options::long_line_threshold`, in characters.
+pub const DEFAULT_LONG_LINE_THRESHOLD: usize = 120;
+
+/// Length in characters of the longest line, and how many lines exceed `threshold`.
+/// Line breaks (`\n`, `\r\n`, lone `\r`) are not counted.
+pub fn line_lengths(text: &str, threshold: usize) -> (usize, usize) {
+    text.split(['\n', '\r'])
+        .map(|line| line.chars().count())
+        .fold((0, 0), |(max, long), len| {
+            (max.max(len), long + usize::from(len > threshold))
+        })
+}
+
+/// Chunk size used by `count_tokens_in_text` when `Options::chunk_bytes` is unset.
+pub const DEFAULT_CHUNK_BYTES: usize = 512;
+
+/// Count tokens in a string with a fast path and a timeout fallback.
+pub fn count_tokens_in_text<T: Tokenizer + ?Sized>(encoder: &T, text: &str) -> usize {
+    encoder.count_chunked(text, DEFAULT_CHUNK_BYTES)
+}
+
+/// Like `count_tokens_in_text` with an explicit chunk size in bytes.
+/// Texts up to four chunks are encoded in one go; longer ones are split near whitespace
+/// and encoded in parallel. Tokens cannot merge across a split, so very small chunks
+/// drift further from an unsplit encode.
+pub fn count_tokens_in_text_chunked<T: Tokenizer + ?Sized>(
+    encoder: &T,
+    text: &str,
+    chunk_bytes: usize,
+) -> usize {
+    let chunk_bytes = chunk_bytes.max(1);
+
+    // Quick exit for trivial cases
+    if text.is_empty() {
+        return 0;
+    }
+
+    // For short inputs or when we couldn't split, just do a blocking encode
+    if text.len() <= chunk_bytes.saturating_mul(4) {
+        return encoder.count_special(text);
+    }
+
+    // Split into chunks to avoid some edge cases that can make the program super slow, such
+    // as a multi-megabyte minified line encoded in one call
+    // Chunk the input and recurse in parallel (without further timeouts)
+    let chunks = split_text_into_chunks(text, chunk_bytes);
+    if chunks.len() <= 1 {
+        return encoder.count_special(text);
+    }
+    chunks.par_iter().map(|s| encoder.count_special(s)).sum()
+}
+
+fn split_text_into_chunks<'a>(text: &'a str, max_chunk_bytes: usize) -> Vec<&'a str> {
+    debug_assert!(max_chunk_bytes > 0);
+    let mut chunks: Vec<&'a str> = Vec::new();
+    let mut start = 0usize;
+    let len = text.len();
+
+    while start < len {
+        let remaining = len - start;
+        if remaining <= max_chunk_bytes {
+            chunks.push(&text[start..]);
+            break;
+        }
+
+        // 1) Ensure at least `max_chunk_bytes` bytes in this chunk
+        let mut base_rel = 0usize; // end boundary relative to `start`
+        let mut acc = 0usize;
+        for (off, ch) in text[start..].char_indices() {
+            let w = ch.len_utf8();
+            acc += w;
+            base_rel = off + w; // boundary after this char
+            if acc >= max_chunk_bytes {
+                break;
+            }
+        }
+        let base_end = start + base_rel;
+
+        // 2) Look ahead up to another `max_chunk_bytes` bytes for a nice split
+        let mut extended_end = None;
+        let mut la_bytes = 0usize;
+        for (off, ch) in text[base_end..].char_indices() {
+            let w = ch.len_utf8();
+            if ch == ' ' || ch == '\n' {
+                extended_end = Some(base_end + off + w); // split after the whitespace
+                break;
+            }
+            la_bytes += w;
+            if la_bytes >= max_chunk_bytes {
+                break;
+            }
+        }
+        // No whitespace in reach (minified JS/CSS, base64): cut hard at the char boundary, so
+        // no chunk grows past `max_chunk_bytes` plus one char
+        let end = extended_end.unwrap_or(base_end);
+        chunks.push(&text[start..end]);
+        start = end;
+    }
+
+    chunks
+}
+
+/// Count lines with non-whitespace content. `\n`, `\r\n` and lone `\r` all end a line,
+/// so the same file counts the same regardless of who committed it.
+pub fn count_non_empty_lines(text: &str) -> usize {
+    // Blank pieces between `\r` and `\n` are dropped by the filter anyway
+    text.split(['\n', '\r'])
+        .filter(|l| !l.trim().is_empty())
+        .count()
+}
+
+/// Count all lines and the blank (empty or whitespace-only) ones among them, in one pass.
+/// Line breaks match `count_non_empty_lines`; a final line without a break still counts,
+/// so this is `wc -l` plus one for text lacking a trailing newline.
+pub fn count_line_totals(text: &str) -> (usize, usize) {
+    let (mut total, mut blank) = (0, 0);
+    let mut rest = text;
+    while !rest.is_empty() {
+        let end = rest.find(['\n', '\r']).unwrap_or(rest.len());
+        total += 1;
+        if rest[..end].trim().is_empty() {
+            blank += 1;
+        }
+        let brk = if rest[end..].starts_with("\r\n") {
+            2
+        } else {
+            usize::from(end < rest.len())
+        };
+        rest = &rest[end + brk..];
+    }
+    (total, blank)
+}
+
+/// Lowercase extension to language, sorted by extension for `language_from_path`'s binary
+/// search. Keys like `makefile` also match extensionless files by name. A language after
+/// `/` (e.g. "Perl/Prolog") only marks an ambiguity.
+pub static EXT_TO_LANG: &[(&str, &str)] = &[
+    ("4th", "Forth"),
+    ("_coffee", "CoffeeScript"),
+    ("_js", "JavaScript"),
+    ("a51", "Assembly"),
+    ("abap", "ABAP"),
+    ("ac", "m4"),
+    ("ack", "Perl"),
+    ("ada", "Ada"),
+    ("adb", "Ada"),
+    ("adml", "XML"),
+    ("admx", "XML"),
+    ("ado", "Stata"),
+    ("adoc", "AsciiDoc"),
+    ("ads", "Ada"),
+    ("adso", "ADSO/IDSM"),
+    ("agda", "Agda"),
+    ("ahk", "AutoHotkey"),
+    ("ahkl", "AutoHotkey"),
+    ("aj", "AspectJ"),
+    ("al", "Perl"),
+    ("am", "make"),
+    ("ample", "AMPLE"),
+    ("ant", "XML"),
+    ("apl", "APL"),
+    ("apla", "APL"),
+    ("aplc", "APL"),
+    ("aplf", "APL"),
+    ("apli", "APL"),
+    ("apln", "APL"),
+    ("aplo", "APL"),
+    ("applescript", "AppleScript"),
+    ("appraisals", "Ruby"),
+    ("arcconfig", "JSON"),
+    ("aria", "Aria"),
+    ("art", "Arturo"),
+    ("as", "ActionScript"),
+    ("asa", "ASP"),
+    ("asax", "ASP.NET"),
+    ("asciidoc", "AsciiDoc"),
+    ("ascx", "ASP.NET"),
+    ("asd", "Lisp"),
+    ("ashx", "ASP"),
+    ("asm", "Assembly"),
+    ("asmx", "ASP.NET"),
+    ("asp", "ASP"),
+    ("aspx", "ASP.NET"),
+    ("astro", "Astro"),
+    ("asy", "Asymptote"),
+    ("auk", "awk"),
+    ("aux", "TeX"),
+    ("avsc", "JSON"),
+    ("aw", "PHP"),
+    ("awk", "awk"),
+    ("axd", "ASP"),
+    ("axml", "XML"),
+    ("b", "Brainfuck"),
+    ("bas", "Visual Basic"),
+    ("bash", "Bourne Again Shell"),
+    ("bat", "DOS Batch"),
+    ("bazel", "Starlark"),
+    ("bbx", "TeX"),
+    ("bdy", "Oracle PL/SQL"),
+    ("berksfile", "Ruby"),
+    ("bf", "Brainfuck"),
+    ("bib", "TeX"),
+    ("blade", "Blade"),
+    ("blp", "Blueprint"),
+    ("bod", "Oracle PL/SQL"),
+    ("bones", "JavaScript"),
+    ("boot", "Clojure"),
+    ("brewfile", "Ruby"),
+    ("brs", "BrightScript"),
+    ("bst", "TeX"),
+    ("btm", "DOS Batch"),
+    ("btp", "BizTalk Pipeline"),
+    ("btproj", "MSBuild script"),
+    ("buck", "Python"),
+    ("build", "NAnt script"),
+    ("builder", "Ruby"),
+    ("buildfile", "Ruby"),
+    ("builds", "XML"),
+    ("bzl", "Starlark"),
+    ("c", "C"),
+    ("c++", "C++"),
+    ("c++m", "C++"),
+    ("c5", "CoCoA 5"),
+    ("cairo", "Cairo"),
+    ("cake", "Cake Build Script"),
+    ("cakefile", "CoffeeScript"),
+    ("capfile", "Ruby"),
+    ("carbon", "Carbon"),
+    ("cats", "C"),
+    ("cbl", "COBOL"),
+    ("cbx", "TeX"),
+    ("cc", "C++"),
+    ("ccm", "C++"),
+    ("ccp", "COBOL"),
+    ("ccproj", "XML"),
+    ("ccs", "CCS"),
+    ("ccxml", "XML"),
+    ("cdc", "Cadence"),
+    ("cdf", "Mathematica"),
+    ("cfc", "ColdFusion CFScript"),
+    ("cfm", "ColdFusion"),
+    ("cfml", "ColdFusion"),
+    ("cg", "HLSL"),
+    ("cg3", "Constraint Grammar"),
+    ("cginc", "HLSL"),
+    ("ch", "xBase Header"),
+    ("chpl", "Chapel"),
+    ("cii", "TNSDL"),
+    ("cin", "TNSDL"),
+    ("circom", "Circom"),
+    ("civet", "Civet"),
+    ("cj", "Clojure/Cangjie"),
+    ("cjs", "JavaScript"),
+    ("cjsx", "CoffeeScript"),
+    ("cl", "Lisp/OpenCL"),
+    ("cl2", "Clojure"),
+    ("clang-format", "YAML"),
+    ("clang-tidy", "YAML"),
+    ("classpath", "XML"),
+    ("clixml", "XML"),
+    ("clj", "Clojure"),
+    ("cljc", "ClojureC"),
+    ("cljs", "ClojureScript"),
+    ("cljscm", "Clojure"),
+    ("cljx", "Clojure"),
+    ("cls", "Visual Basic/TeX/Apex Class"),
+    ("cmake", "CMake"),
+    ("cmd", "DOS Batch"),
+    ("cob", "COBOL"),
+    ("cobol", "COBOL"),
+    ("cocoa5", "CoCoA 5"),
+    ("cocoa5server", "CoCoA 5"),
+    ("coffee", "CoffeeScript"),
+    ("comp", "GLSL"),
+    ("component", "Visualforce Component"),
+    ("cpanfile", "Perl"),
+    ("cpkg5", "CoCoA 5"),
+    ("cpp", "C++"),
+    ("cppm", "C++"),
+    ("cproject", "XML"),
+    ("cpy", "COBOL"),
+    ("cql", "SQL"),
+    ("cr", "Crystal"),
+    ("cs", "C#/Smalltalk"),
+    ("cscfg", "XML"),
+    ("csdef", "XML"),
+    ("csh", "C Shell"),
+    ("cshtml", "Razor"),
+    ("csl", "XML"),
+    ("cson", "CSON"),
+    ("csproj", "MSBuild script"),
+    ("css", "CSS"),
+    ("csv", "CSV"),
+    ("ct", "XML"),
+    ("ctl", "Visual Basic"),
+    ("ctp", "PHP"),
+    ("cts", "TypeScript"),
+    ("cu", "CUDA"),
+    ("cuh", "CUDA"),
+    ("cvt", "Civet"),
+    ("cvtx", "Civet"),
+    ("cxx", "C++"),
+    ("cxxm", "C++"),
+    ("d", "D/dtrace"),
+    ("da", "DAL"),
+    ("dangerfile", "Ruby"),
+    ("dart", "Dart"),
+    ("dcl", "Clean"),
+    ("def", "Windows Module Definition"),
+    ("deliverfile", "Ruby"),
+    ("depproj", "XML"),
+    ("derw", "Derw"),
+    ("dfm", "Delphi Form"),
+    ("dfy", "Dafny"),
+    ("dhall", "dhall"),
+    ("diff", "diff"),
+    ("dita", "DITA"),
+    ("ditamap", "XML"),
+    ("ditaval", "XML"),
+    ("dlm", "IDL"),
+    ("dmap", "NASTRAN DMAP"),
+    ("do", "Stata"),
+    ("dockerfile", "Dockerfile"),
+    ("dofile", "AMPLE"),
+    ("doh", "Stata"),
+    ("dotsettings", "XML"),
+    ("dpr", "Pascal"),
+    ("drl", "Drools"),
+    ("dsc", "DenizenScript"),
+    ("dsr", "Visual Basic"),
+    ("dt", "DIET"),
+    ("dtd", "DTD"),
+    ("dtx", "TeX"),
+    ("dxl", "DOORS Extension Language"),
+    ("dyalog", "APL"),
+    ("dyapp", "APL"),
+    ("e", "Specman e"),
+    ("e4", "Forth"),
+    ("ec", "C"),
+    ("ecpp", "ECPP"),
+    ("ecr", "Embedded Crystal"),
+    ("editorconfig", "INI"),
+    ("eex", "EEx"),
+    ("ejs", "EJS"),
+    ("el", "Lisp"),
+    ("eliom", "OCaml"),
+    ("eliomi", "OCaml"),
+    ("elm", "Elm"),
+    ("emakefile", "Erlang"),
+    ("erb", "ERB"),
+    ("erl", "Erlang"),
+    ("es6", "JavaScript"),
+    ("ets", "ArkTs"),
+    ("ex", "Elixir"),
+    ("exp", "Expect"),
+    ("expr-dist", "R"),
+    ("exs", "Elixir Script"),
+    ("eye", "Ruby"),
+    ("f", "Fortran 77/Forth"),
+    ("f03", "Fortran 2003"),
+    ("f77", "Fortran 77"),
+    ("f83", "Forth"),
+    ("f90", "Fortran 90"),
+    ("f95", "Fortran 95"),
+    ("fastfile", "Ruby"),
+    ("fb", "Forth"),
+    ("fbs", "Flatbuffers"),
+    ("feature", "Cucumber"),
+    ("filters", "XML"),
+    ("fish", "Fish Shell"),
+    ("fmt", "Oracle Forms"),
+    ("fnc", "Oracle PL/SQL"),
+    ("fnl", "Fennel"),
+    ("focexec", "Focus"),
+    ("for", "Fortran 77/Forth"),
+    ("forth", "Forth"),
+    ("fp", "GLSL"),
+    ("fpm", "Forth"),
+    ("fr", "Forth"),
+    ("frag", "GLSL"),
+    ("frg", "GLSL"),
+    ("frm", "Visual Basic"),
+    ("frt", "Forth"),
+    ("frx", "Visual Basic"),
+    ("fs", "F#/Forth"),
+    ("fsh", "GLSL"),
+    ("fshader", "GLSL"),
+    ("fsi", "F#"),
+    ("fsl", "Finite State Language"),
+    ("fsproj", "XML"),
+    ("fsx", "F# Script"),
+    ("ft", "Forth"),
+    ("fth", "Forth"),
+    ("ftl", "Freemarker Template"),
+    ("ftn", "Fortran 77"),
+    ("fun", "Standard ML"),
+    ("fut", "Futhark"),
+    ("fxh", "HLSL"),
+    ("fxml", "FXML"),
+    ("g", "ANTLR Grammar"),
+    ("g4", "ANTLR Grammar"),
+    ("gant", "Groovy"),
+    ("gawk", "awk"),
+    ("gclient", "Python"),
+    ("gd", "GDScript"),
+    ("gdshader", "Godot Shaders"),
+    ("gemfile", "Ruby"),
+    ("gemrc", "YAML"),
+    ("gemspec", "Ruby"),
+    ("geo", "GLSL"),
+    ("geojson", "JSON"),
+    ("geom", "GLSL"),
+    ("gjs", "Glimmer JavaScript"),
+    ("glade", "Glade"),
+    ("gleam", "Gleam"),
+    ("glsl", "GLSL"),
+    ("glslv", "GLSL"),
+    ("gltf", "JSON"),
+    ("gmx", "XML"),
+    ("gnumakefile", "make"),
+    ("go", "Go"),
+    ("god", "Ruby"),
+    ("gql", "GraphQL"),
+    ("gradle", "Gradle"),
+    ("graphql", "GraphQL"),
+    ("graphqls", "GraphQL"),
+    ("groovy", "Groovy"),
+    ("grt", "Groovy"),
+    ("grxml", "XML"),
+    ("gshader", "GLSL"),
+    ("gsp", "Grails"),
+    ("gtpl", "Groovy"),
+    ("gts", "Glimmer TypeScript"),
+    ("guardfile", "Ruby"),
+    ("gvy", "Groovy"),
+    ("gyp", "Python"),
+    ("gypi", "Python"),
+    ("h", "C/C++ Header"),
+    ("h++", "C++"),
+    ("ha", "Hare"),
+    ("haml", "Haml"),
+    ("handlebars", "Handlebars"),
+    ("har", "JSON"),
+    ("hb", "Harbour"),
+    ("hbs", "Handlebars"),
+    ("hcl", "HCL"),
+    ("heex", "HTML EEx"),
+    ("hh", "C/C++ Header"),
+    ("hic", "Clojure"),
+    ("hlean", "Lean"),
+    ("hlsl", "HLSL"),
+    ("hlsli", "HLSL"),
+    ("hoon", "Hoon"),
+    ("hpp", "C/C++ Header"),
+    ("hrl", "Erlang"),
+    ("hs", "Haskell"),
+    ("hsc", "Haskell"),
+    ("htm", "HTML"),
+    ("html", "HTML"),
+    ("htmlhintrc", "JSON"),
+    ("hx", "Haxe"),
+    ("hxsl", "Haxe"),
+    ("hxx", "C/C++ Header"),
+    ("i", "SWIG"),
+    ("i3", "Modula3"),
+    ("ice", "Slice"),
+    ("iced", "CoffeeScript"),
+    ("icl", "Clean"),
+    ("idc", "C"),
+    ("idl", "IDL"),
+    ("idr", "Idris"),
+    ("ig", "Modula3"),
+    ("ihlp", "Stata"),
+    ("il", "SKILL/.NET IL"),
+    ("ils", "SKILL++"),
+    ("imba", "Imba"),
+    ("iml", "XML"),
+    ("in1", "TNSDL"),
+    ("in2", "TNSDL"),
+    ("in3", "TNSDL"),
+    ("in4", "TNSDL"),
+    ("inc", "PHP/Pascal/Fortran/Pawn"),
+    ("inf", "TNSDL"),
+    ("ini", "INI"),
+    ("inl", "C++"),
+    ("ino", "Arduino Sketch"),
+    ("ins", "TeX"),
+    ("interface", "TNSDL"),
+    ("ipf", "Igor Pro"),
+    ("ipl", "IPL"),
+    ("ipp", "C++"),
+    ("ipynb", "Jupyter Notebook"),
+    ("irbrc", "Ruby"),
+    ("ism", "InstallShield"),
+    ("itk", "Tcl/Tk"),
+    ("iuml", "PlantUML"),
+    ("ivy", "XML"),
+    ("ixx", "C++"),
+    ("j2", "Jinja Template"),
+    ("jade", "Pug"),
+    ("jai", "Jai"),
+    ("jake", "JavaScript"),
+    ("jakefile", "JavaScript"),
+    ("janet", "Janet"),
+    ("jarfile", "Ruby"),
+    ("java", "Java"),
+    ("jbuilder", "Ruby"),
+    ("jcl", "JCL"),
+    ("jelly", "XML"),
+    ("jenkinsfile", "Groovy"),
+    ("jinja", "Jinja Template"),
+    ("jinja2", "Jinja Template"),
+    ("jl", "Lisp/Julia"),
+    ("js", "JavaScript"),
+    ("jsb", "JavaScript"),
+    ("jscad", "JavaScript"),
+    ("jsf", "JavaServer Faces"),
+    ("jsfl", "JavaScript"),
+    ("jsm", "JavaScript"),
+    ("json", "JSON"),
+    ("json-tmlanguage", "JSON"),
+    ("json5", "JSON5"),
+    ("jsonl", "JSON"),
+    ("jsonnet", "Jsonnet"),
+    ("jsp", "JSP"),
+    ("jspeg", "tspeg"),
+    ("jspf", "JSP"),
+    ("jsproj", "XML"),
+    ("jss", "JavaScript"),
+    ("jssm", "Finite State Language"),
+    ("jsx", "JSX"),
+    ("junos", "Juniper Junos"),
+    ("just", "Justfile"),
+    ("kml", "XML"),
+    ("kojo", "Scala"),
+    ("ksc", "Kermit"),
+    ("ksh", "Korn Shell"),
+    ("kt", "Kotlin"),
+    ("ktm", "Kotlin"),
+    ("kts", "Kotlin"),
+    ("kv", "kvlang"),
+    ("l", "lex"),
+    ("lagda", "Agda"),
+    ("launch", "XML"),
+    ("lbx", "TeX"),
+    ("ld", "Linker Script"),
+    ("lean", "Lean"),
+    ("lektorproject", "INI"),
+    ("lem", "Lem"),
+    ("less", "LESS"),
+    ("lex", "lex"),
+    ("lfe", "LFE"),
+    ("lgt", "Logtalk"),
+    ("lhs", "Haskell"),
+    ("lidr", "Literate Idris"),
+    ("liquid", "liquid"),
+    ("lisp", "Lisp"),
+    ("lit", "PL/M"),
+    ("ll", "LLVM IR"),
+    ("lmi", "Python"),
+    ("logtalk", "Logtalk"),
+    ("lp", "AnsProlog"),
+    ("lpr", "Pascal"),
+    ("lsp", "Lisp"),
+    ("ltx", "TeX"),
+    ("lua", "Lua"),
+    ("luau", "Luau"),
+    ("m", "MATLAB/Mathematica/Objective-C/MUMPS/Mercury"),
+    ("m3", "Modula3"),
+    ("m4", "m4"),
+    ("ma", "Mathematica"),
+    ("makefile", "make"),
+    ("mako", "Mako"),
+    ("mao", "Mako"),
+    ("markdown", "Markdown"),
+    ("master", "ASP.NET"),
+    ("mat", "Unity-Prefab"),
+    ("mata", "Stata"),
+    ("matah", "Stata"),
+    ("mathematica", "Mathematica"),
+    ("mavenfile", "Ruby"),
+    ("mawk", "awk"),
+    ("mbt", "MoonBit"),
+    ("mbti", "MoonBit"),
+    ("mbtx", "MoonBit"),
+    ("mbty", "MoonBit"),
+    ("mc", "Windows Message File"),
+    ("mcmeta", "JSON"),
+    ("md", "Markdown"),
+    ("mdown", "Markdown"),
+    ("mdpolicy", "XML"),
+    ("mdwn", "Markdown"),
+    ("mdx", "Markdown"),
+    ("met", "Teamcenter met"),
+    ("metal", "Metal"),
+    ("mg", "Modula3"),
+    ("mipage", "APL"),
+    ("mir", "YAML"),
+    ("mjml", "XML"),
+    ("mjs", "JavaScript"),
+    ("mk", "make"),
+    ("mkd", "Markdown"),
+    ("mkdn", "Markdown"),
+    ("mkdown", "Markdown"),
+    ("mkii", "TeX"),
+    ("mkiv", "TeX"),
+    ("mkvi", "TeX"),
+    ("ml", "OCaml"),
+    ("ml4", "OCaml"),
+    ("mli", "OCaml"),
+    ("mll", "OCaml"),
+    ("mly", "OCaml"),
+    ("mm", "Objective-C++"),
+    ("mo", "Modelica"),
+    ("mojo", "Mojo"),
+    ("mojom", "Mojom"),
+    ("mps", "MUMPS"),
+    ("msbuild", "MSBuild script"),
+    ("msg", "Gencat NLS"),
+    ("mspec", "Ruby"),
+    ("mt", "Mathematica"),
+    ("mth", "Teamcenter mth"),
+    ("mts", "TypeScript"),
+    ("mustache", "Mustache"),
+    ("mxml", "MXML"),
+    ("mysql", "SQL"),
+    ("n", "Nemerle"),
+    ("nasm", "Assembly"),
+    ("natvis", "XML"),
+    ("nawk", "awk"),
+    ("nbp", "Mathematica"),
+    ("ncl", "Nickel"),
+    ("ndproj", "XML"),
+    ("nf", "Nextflow"),
+    ("nim", "Nim"),
+    ("nimble", "Nim"),
+    ("nimrod", "Nim"),
+    ("nims", "Nim"),
+    ("nix", "Nix"),
+    ("njk", "Nunjucks"),
+    ("njs", "JavaScript"),
+    ("nlogo", "NetLogo"),
+    ("nls", "NetLogo"),
+    ("nomad", "HCL"),
+    ("nproj", "XML"),
+    ("nse", "Lua"),
+    ("nu", "Nushell"),
+    ("nuon", "Nushell Object Notation"),
+    ("nuspec", "XML"),
+    ("nut", "Squirrel"),
+    ("odd", "XML"),
+    ("odin", "Odin"),
+    ("odx", "BizTalk Orchestration"),
+    ("org", "Org Mode"),
+    ("oscript", "LiveLink OScript"),
+    ("osm", "XML"),
+    ("p", "Pascal/Pawn"),
+    ("p4", "P4"),
+    ("p6", "Raku/Prolog"),
+    ("p8", "Lua"),
+    ("pac", "JavaScript"),
+    ("pad", "Ada"),
+    ("page", "Visualforce Page"),
+    ("pas", "Pascal"),
+    ("pascal", "Pascal"),
+    ("patch", "diff"),
+    ("pawn", "Pawn"),
+    ("pbt", "PowerBuilder"),
+    ("pcc", "C++"),
+    ("pcl", "Patran Command Language"),
+    ("pd_lua", "Lua"),
+    ("pde", "Processing"),
+    ("peg", "PEG"),
+    ("peggy", "peggy"),
+    ("pegjs", "peg.js"),
+    ("pek", "Pek"),
+    ("perl", "Perl"),
+    ("pest", "Pest"),
+    ("pfo", "Fortran 77"),
+    ("pgc", "C"),
+    ("ph", "Perl"),
+    ("phakefile", "PHP"),
+    ("php", "PHP"),
+    ("php3", "PHP"),
+    ("php4", "PHP"),
+    ("php5", "PHP"),
+    ("php_cs", "PHP"),
+    ("phps", "PHP"),
+    ("phpt", "PHP"),
+    ("phtml", "PHP"),
+    ("pig", "Pig Latin"),
+    ("pkgproj", "XML"),
+    ("pkl", "Pkl"),
+    ("pl", "Perl/Prolog"),
+    ("pl1", "PL/I"),
+    ("plantuml", "PlantUML"),
+    ("plh", "Perl"),
+    ("plist", "XML"),
+    ("plm", "PL/M"),
+    ("plx", "Perl"),
+    ("pm", "Perl"),
+    ("pm6", "Raku"),
+    ("po", "PO File"),
+    ("podfile", "Ruby"),
+    ("podspec", "Ruby"),
+    ("pom", "Maven"),
+    ("pony", "Pony"),
+    ("pp", "Pascal/Puppet"),
+    ("pprx", "Rexx"),
+    ("prc", "Oracle PL/SQL"),
+    ("prefab", "Unity-Prefab"),
+    ("prefs", "INI"),
+    ("prg", "xBase"),
+    ("prisma", "Prisma Schema"),
+    ("pro", "IDL/Qt Project/Prolog/ProGuard"),
+    ("proj", "XML"),
+    ("project", "XML"),
+    ("prolog", "Prolog"),
+    ("properties", "Properties"),
+    ("props", "XML"),
+    ("proto", "Protocol Buffers"),
+    ("prql", "PRQL"),
+    ("prw", "xBase"),
+    ("pryrc", "Ruby"),
+    ("ps1", "PowerShell"),
+    ("ps1xml", "XML"),
+    ("psc1", "XML"),
+    ("psd1", "PowerShell"),
+    ("psgi", "Perl"),
+    ("psm1", "PowerShell"),
+    ("psql", "SQL"),
+    ("pt", "XML"),
+    ("pu", "PlantUML"),
+    ("pug", "Pug"),
+    ("puml", "PlantUML"),
+    ("puppetfile", "Ruby"),
+    ("purs", "PureScript"),
+    ("pwn", "Pawn"),
+    ("pxd", "Cython"),
+    ("pxi", "Cython"),
+    ("py", "Python"),
+    ("py3", "Python"),
+    ("pyde", "Python"),
+    ("pyi", "Python"),
+    ("pyj", "RapydScript"),
+    ("pyp", "Python"),
+    ("pyt", "Python"),
+    ("pyw", "Python"),
+    ("pyx", "Cython"),
+    ("qbs", "QML"),
+    ("qml", "QML"),
+    ("r", "R"),
+    ("rabl", "Ruby"),
+    ("rake", "Ruby"),
+    ("raku", "Raku"),
+    ("rakumod", "Raku"),
+    ("raml", "RAML"),
+    ("razor", "Razor"),
+    ("rb", "Ruby"),
+    ("rbuild", "Ruby"),
+    ("rbw", "Ruby"),
+    ("rbx", "Ruby"),
+    ("rbxs", "Lua"),
+    ("rc", "Windows Resource File"),
+    ("rc2", "Windows Resource File"),
+    ("rd", "R"),
+    ("rdf", "XML"),
+    ("re", "ReasonML"),
+    ("reek", "YAML"),
+    ("rego", "Rego"),
+    ("rei", "ReasonML"),
+    ("res", "ReScript"),
+    ("resi", "ReScript"),
+    ("rest", "reStructuredText"),
+    ("resx", "XML"),
+    ("rex", "Oracle Reports"),
+    ("rexfile", "Perl"),
+    ("rexx", "Rexx"),
+    ("rform", "Ring"),
+    ("rh", "Ring"),
+    ("rhtml", "Ruby HTML"),
+    ("ring", "Ring"),
+    ("rkt", "Racket"),
+    ("rktd", "Racket"),
+    ("rktl", "Racket"),
+    ("rlx", "Constraint Grammar"),
+    ("robot", "RobotFramework"),
+    ("ronn", "Markdown"),
+    ("rou", "TNSDL"),
+    ("rprofile", "R"),
+    ("rs", "Rust"),
+    ("rss", "XML"),
+    ("rst", "reStructuredText"),
+    ("rsx", "R"),
+    ("ru", "Ruby"),
+    ("rules", "Snakemake"),
+    ("rviz", "YAML"),
+    ("rx", "Forth"),
+    ("s", "Assembly"),
+    ("sas", "SAS"),
+    ("sass", "Sass"),
+    ("sbl", "Softbridge Basic"),
+    ("sbt", "Scala"),
+    ("sc", "Scheme"),
+    ("sca", "Visual Fox Pro"),
+    ("scad", "OpenSCAD"),
+    ("scala", "Scala"),
+    ("sch", "Scheme"),
+    ("scm", "Scheme"),
+    ("sconscript", "Python"),
+    ("sconstruct", "Python"),
+    ("scrbl", "Racket"),
+    ("scss", "SCSS"),
+    ("scxml", "XML"),
+    ("sdl", "TNSDL"),
+    ("sdt", "TNSDL"),
+    ("sed", "sed"),
+    ("ses", "Patran Command Language"),
+    ("sfproj", "XML"),
+    ("sh", "Bourne Shell"),
+    ("shader", "HLSL"),
+    ("shproj", "XML"),
+    ("sig", "Standard ML"),
+    ("sitemap", "ASP.NET"),
+    ("sjs", "JavaScript"),
+    ("sld", "Scheme"),
+    ("slim", "Slim"),
+    ("slint", "Slint"),
+    ("sln", "Visual Studio Solution"),
+    ("sls", "Scheme/SaltStack"),
+    ("smarty", "Smarty"),
+    ("smk", "Snakemake"),
+    ("sml", "Standard ML"),
+    ("snakefile", "Python"),
+    ("snapfile", "Ruby"),
+    ("sol", "Solidity"),
+    ("sp", "SparForte"),
+    ("spc", "Oracle PL/SQL"),
+    ("spd", "TNSDL"),
+    ("sps", "Scheme"),
+    ("sql", "SQL"),
+    ("sra", "PowerBuilder"),
+    ("srdf", "XML"),
+    ("srf", "PowerBuilder"),
+    ("srm", "PowerBuilder"),
+    ("srs", "PowerBuilder"),
+    ("sru", "PowerBuilder"),
+    ("srw", "PowerBuilder"),
+    ("ss", "Scheme"),
+    ("ssc", "TNSDL"),
+    ("ssjs", "JavaScript"),
+    ("sss", "SugarSS"),
+    ("sst", "TNSDL"),
+    ("st", "Smalltalk"),
+    ("startup", "AMPLE"),
+    ("sthlp", "Stata"),
+    ("storyboard", "XML"),
+    ("sttheme", "XML"),
+    ("sty", "TeX"),
+    ("styl", "Stylus"),
+    ("sublime-snippet", "XML"),
+    ("sublime-syntax", "YAML"),
+    ("surql", "SurrealQL"),
+    ("sv", "Verilog-SystemVerilog"),
+    ("svelte", "Svelte"),
+    ("svg", "SVG"),
+    ("svh", "Verilog-SystemVerilog"),
+    ("swift", "Swift"),
+    ("syntax", "YAML"),
+    ("tab", "SQL"),
+    ("tac", "Python"),
+    ("targets", "XML"),
+    ("tcc", "C++"),
+    ("tcl", "Tcl/Tk"),
+    ("tcsh", "C Shell"),
+    ("td", "TableGen"),
+    ("teal", "TEAL"),
+    ("templ", "Templ"),
+    ("tern-config", "JSON"),
+    ("tern-project", "JSON"),
+    ("tesc", "GLSL"),
+    ("tese", "GLSL"),
+    ("tex", "TeX"),
+    ("text", "Text"),
+    ("tf", "HCL"),
+    ("tfstate", "JSON"),
+    ("tfvars", "HCL"),
+    ("thor", "Ruby"),
+    ("thorfile", "Ruby"),
+    ("thrift", "Thrift"),
+    ("tk", "Tcl/Tk"),
+    ("tla", "TLA+"),
+    ("tmcommand", "XML"),
+    ("tml", "XML"),
+    ("tmlanguage", "XML"),
+    ("tmpreferences", "XML"),
+    ("tmsnippet", "XML"),
+    ("tmtheme", "XML"),
+    ("toml", "TOML"),
+    ("topojson", "JSON"),
+    ("tpd", "TITAN Project File Information"),
+    ("tpl", "Smarty"),
+    ("tpp", "C++"),
+    ("tres", "Godot Resource"),
+    ("trg", "Oracle PL/SQL"),
+    ("trigger", "Apex Trigger"),
+    ("ts", "TypeScript/Qt Linguist"),
+    ("tscn", "Godot Scene"),
+    ("tspeg", "tspeg"),
+    ("tss", "Titanium Style Sheet"),
+    ("tsx", "TypeScript"),
+    ("ttcn", "TTCN"),
+    ("ttcn2", "TTCN"),
+    ("ttcn3", "TTCN"),
+    ("ttcnpp", "TTCN"),
+    ("twig", "Twig"),
+    ("txt", "Text"),
+    ("typ", "Typst"),
+    ("udf", "SQL"),
+    ("ui", "XML-Qt-GTK/Glade"),
+    ("um", "Umka"),
+    ("urdf", "XML"),
+    ("uss", "USS"),
+    ("ux", "XML"),
+    ("uxml", "UXML"),
+    ("v", "Verilog-SystemVerilog/Coq"),
+    ("vagrantfile", "Ruby"),
+    ("vala", "Vala"),
+    ("vapi", "Vala Header"),
+    ("vb", "Visual Basic .NET"),
+    ("vba", "VB for Applications"),
+    ("vbhtml", "Visual Basic"),
+    ("vbp", "Visual Basic"),
+    ("vbproj", "Visual Basic .NET"),
+    ("vbs", "Visual Basic Script"),
+    ("vbw", "Visual Basic"),
+    ("vcproj", "MSBuild script"),
+    ("vcxproj", "XML"),
+    ("vert", "GLSL"),
+    ("vhd", "VHDL"),
+    ("vhdl", "VHDL"),
+    ("vhf", "VHDL"),
+    ("vhi", "VHDL"),
+    ("vho", "VHDL"),
+    ("vhs", "VHDL"),
+    ("vht", "VHDL"),
+    ("vhw", "VHDL"),
+    ("vim", "vim script"),
+    ("viw", "SQL"),
+    ("vm", "Velocity Template Language"),
+    ("vrx", "GLSL"),
+    ("vsh", "GLSL"),
+    ("vshader", "GLSL"),
+    ("vsixmanifest", "XML"),
+    ("vssettings", "XML"),
+    ("vstemplate", "XML"),
+    ("vue", "Vuejs Component"),
+    ("vxml", "XML"),
+    ("vy", "Vyper"),
+    ("wast", "WebAssembly"),
+    ("wat", "WebAssembly"),
+    ("watchmanconfig", "JSON"),
+    ("watchr", "Ruby"),
+    ("wdproj", "MSBuild script"),
+    ("webapp", "JSON"),
+    ("webinfo", "ASP.NET"),
+    ("webmanifest", "JSON"),
+    ("wgsl", "WGSL"),
+    ("wixproj", "MSBuild script"),
+    ("wl", "Mathematica"),
+    ("wlt", "Mathematica"),
+    ("wlua", "Lua"),
+    ("workbook", "Markdown"),
+    ("workspace", "Python"),
+    ("wscript", "Python"),
+    ("wsd", "PlantUML"),
+    ("wsdl", "Web Services Description"),
+    ("wsf", "XML"),
+    ("wsgi", "Python"),
+    ("wxi", "WiX include"),
+    ("wxl", "WiX string localization"),
+    ("wxml", "WXML"),
+    ("wxs", "WiX source"),
+    ("wxss", "WXSS"),
+    ("x", "Logos"),
+    ("x3d", "XML"),
+    ("xacro", "XML"),
+    ("xaml", "XAML"),
+    ("xht", "HTML"),
+    ("xhtml", "XHTML"),
+    ("xib", "XML"),
+    ("xlf", "XML"),
+    ("xliff", "XML"),
+    ("xm", "Logos"),
+    ("xmi", "XMI"),
+    ("xml", "XML"),
+    ("xpo", "X++"),
+    ("xproj", "XML"),
+    ("xpy", "Python"),
+    ("xq", "XQuery"),
+    ("xql", "XQuery"),
+    ("xqm", "XQuery"),
+    ("xquery", "XQuery"),
+    ("xqy", "XQuery"),
+    ("xrl", "Erlang"),
+    ("xsd", "XSD"),
+    ("xsjs", "JavaScript"),
+    ("xsjslib", "JavaScript"),
+    ("xsl", "XSLT"),
+    ("xslt", "XSLT"),
+    ("xspec", "XML"),
+    ("xtend", "Xtend"),
+    ("xul", "XML"),
+    ("y", "yacc"),
+    ("yacc", "yacc"),
+    ("yaml", "YAML"),
+    ("yaml-tmlanguage", "YAML"),
+    ("yang", "Yang"),
+    ("yap", "Prolog"),
+    ("yarn", "Yarn"),
+    ("yml", "YAML"),
+    ("yrl", "Erlang"),
+    ("yyp", "JSON"),
+    ("zcml", "XML"),
+    ("zig", "Zig"),
+    ("zsh", "zsh"),
+    ("ʕ◔ϖ◔ʔ", "Go"),
+    ("🔥", "Mojo"),
+];
+
+fn language_for_ext(ext: &str) -> Option<&'static str> {
+    EXT_TO_LANG
+        .binary_search_by(|(e, _)| (*e).cmp(ext))
+        .ok()
+        .map(|i| EXT_TO_LANG[i].1)
+}
+
+/// Shown name for a table language: the part before any `/`.
+fn primary_language(lang: &str) -> &str {
+    lang.split('/').next().unwrap_or(lang)
+}
+
+pub fn language_from_path(path: &Path) -> String {
+    let lang = match path.extension() {
+        Some(ext) => language_for_ext(&ext.to_string_lossy().to_ascii_lowercase()),
+        None => language_for_name(path),
+    };
+    primary_language(lang.unwrap_or("Others")).to_string()
+}
+
+/// Language of an extensionless file such as `Makefile`, `Dockerfile` or `Gemfile`.
+fn language_for_name(path: &Path) -> Option<&'static str> {
+    let name = path.file_name()?.to_str()?.to_ascii_lowercase();
+    match name.as_str() {
+        // Bazel's `BUILD`, not a NAnt `.build` script
+        "build" => Some("Starlark"),
+        name => language_for_ext(name),
+    }
+}
+
+/// Extensions `language_from_path` recognizes, grouped by language name (in `EXT_TO_LANG`
+/// order, so sorted). Keys like `makefile` also stand for extensionless files of that name.
+pub fn known_languages() -> BTreeMap<String, Vec<&'static str>> {
+    let mut langs: BTreeMap<String, Vec<&'static str>> = BTreeMap::new();
+    for &(ext, lang) in EXT_TO_LANG {
+        langs
+            .entry(primary_language(lang).to_string())
+            .or_default()
+            .push(ext);
+    }
+    langs
+}
+
+/// Like `language_from_path`, but inspects `text` to resolve a few ambiguous extensions
+/// (`.m`, `.h`, `.pl`, `.v`). Falls back to the extension mapping when not confident.
+pub fn language_from_content(path: &Path, text: &str) -> String {
+    let ext = path
+        .extension()
+        .and_then(|s| s.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    // Only the head of the file is needed to tell these apart
+    let head = match text.char_indices().nth(16 * 1024) {
+        Some((idx, _)) => &text[..idx],
+        None => text,
+    };
+    let has_line = |pred: &dyn Fn(&str) -> bool| head.lines().map(str::trim_start).any(pred);
+    let objc = || {
+        has_line(&|l| {
+            l.starts_with("@interface")
+                || l.starts_with("@implementation")
+                || l.starts_with("@protocol")
+                || l.starts_with("#import")
+        })
+    };
+    let guess = match ext.as_str() {
+        "m" if objc() => Some("Objective-C"),
+        "m" if has_line(&|l| l.starts_with(":- module")) => Some("Mercury"),
+        "m" if has_line(&|l| l.starts_with("function ") || l.starts_with('%')) => Some("MATLAB"),
+        "h" if objc() => Some("Objective-C"),
+        "h" if has_line(&|l| {
+            l.starts_with("class ")
+                || l.starts_with("namespace ")
+                || l.starts_with("template")
+                || l.starts_with("public:")
+                || l.starts_with("private:")
+                || l.contains("std::")
+        }) =>
+        {
+            Some("C++")
+        }
+        "pl" if has_line(&|l| {
+            l.starts_with("use strict") || l.starts_with("my $") || l.starts_with("#!/usr/bin/perl")
+        }) =>
+        {
+            Some("Perl")
+        }
+        "pl" if has_line(&|l| l.starts_with(":-")) => Some("Prolog"),
+        "v" if has_line(&|l| l.starts_with("endmodule")) => Some("Verilog-SystemVerilog"),
+        "v" if has_line(&|l| l.starts_with("Proof") || l.starts_with("Qed")) => Some("Coq"),
+        _ => None,
+    };
+    guess.map_or_else(|| language_from_path(path), str::to_string)
+}
+
+#[derive(Debug, Serialize, JsonSchema, Clone, Default)]
+pub struct LangSummary {
+    pub language: String,
+    pub files: usize,
+    pub lines: usize,
+    pub total_lines: usize,
+    pub blank_lines: usize,
+    pub tokens: usize,
+    pub bytes: u64,
+    pub words: usize,
+    pub chars: usize,
+    // Share of all lines / tokens, in percent rounded to one decimal
+    pub lines_pct: f64,
+    pub tokens_pct: f64,
+}
+
+impl LangSummary {
+    fn add_file(map: &mut BTreeMap<String, LangSummary>, f: &FileCount) {
+        let entry = map
+            .entry(f.language.clone())
+            .or_insert_with(|| LangSummary {
+                language: f.language.clone(),
+                ..LangSummary::default()
+            });
+        entry.files += 1;
+        entry.lines += f.lines;
+        entry.total_lines += f.total_lines;
+        entry.blank_lines += f.blank_lines;
+        entry.tokens += f.tokens;
+        entry.bytes += f.bytes;
+        entry.words += f.words;
+        entry.chars += f.chars;
+    }
+
+    fn absorb(&mut self, other: &LangSummary) {
+        self.files += other.files;
+        self.lines += other.lines;
+        self.total_lines += other.total_lines;
+        self.blank_lines += other.blank_lines;
+        self.tokens += other.tokens;
+        self.bytes += other.bytes;
+        self.words += other.words;
+        self.chars += other.chars;
+    }
+}
+
+pub fn aggregate_by_language(files: &[FileCount]) -> Vec<LangSummary> {
+    let mut map: BTreeMap<String, LangSummary> = BTreeMap::new();
+    for f in files {
+        LangSummary::add_file(&mut map, f);
+    }
+    finish_summaries(map)
+}
+
+/// Fill in the percentages and order rows by tokens, largest first.
+fn finish_summaries(map: BTreeMap<String, LangSummary>) -> Vec<LangSummary> {
+    let mut v: Vec<LangSummary> = map.into_values().collect();
+    let total_lines: usize = v.iter().map(|s| s.lines).sum();
+    let total_tokens: usize = v.iter().map(|s| s.tokens).sum();
+    for s in &mut v {
+        s.lines_pct = percent(s.lines, total_lines);
+        s.tokens_pct = percent(s.tokens, total_tokens);
+    }
+    // Sort by token count desc; ties go by name so output never depends on collection order
+    v.sort_by(|a, b| {
+        b.tokens
+            .cmp(&a.tokens)
+            .then_with(|| a.language.cmp(&b.language))
+    });
+    v
+}
+
+/// Totals per lowercased file extension, for when one language spans several (e.g. the
+/// `.csproj`, `.props` and `.xml` files all counted as XML).
+#[derive(Debug, Serialize, JsonSchema, Clone, Default)]
+pub struct ExtSummary {
+    // Extension with its leading dot, or the file name when there is none
+    pub extension: String,
+    pub files: usize,
+    pub lines: usize,
+    pub total_lines: usize,
+    pub blank_lines: usize,
+    pub tokens: usize,
+    pub bytes: u64,
+    pub words: usize,
+    pub chars: usize,
+    // Share of all lines / tokens, in percent rounded to one decimal
+    pub lines_pct: f64,
+    pub tokens_pct: f64,
+}
+
+/// Like `aggregate_by_language`, grouped by `extension_key` instead of language.
+pub fn aggregate_by_extension(files: &[FileCount]) -> Vec<ExtSummary> {
+    let mut map: BTreeMap<String, LangSummary> = BTreeMap::new();
+    for f in files {
+        let key = extension_key(&f.path);
+        let entry = map.entry(key.clone()).or_insert_with(|| LangSummary {
+            language: key,
+            ..LangSummary::default()
+        });
+        entry.absorb(&LangSummary {
+            files: 1,
+            lines: f.lines,
+            total_lines: f.total_lines,
+            blank_lines: f.blank_lines,
+            tokens: f.tokens,
+            bytes: f.bytes,
+            words: f.words,
+            chars: f.chars,
+            ..LangSummary::default()
+        });
+    }
+    finish_summaries(map)
+        .into_iter()
+        .map(|s| ExtSummary {
+            extension: s.language,
+            files: s.files,
+            lines: s.lines,
+            total_lines: s.total_lines,
+            blank_lines: s.blank_lines,
+            tokens: s.tokens,
+            bytes: s.bytes,
+            words: s.words,
+            chars: s.chars,
+            lines_pct: s.lines_pct,
+            tokens_pct: s.tokens_pct,
+        })
+        .collect()
+}
+
+/// Lowercased extension with a leading dot (`.csproj`), or the file name when there is
+/// no extension (`Makefile`).
+fn extension_key(path: &Path) -> String {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some(ext) => format!(".{}", ext.to_ascii_lowercase()),
+        None => path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+    }
+}
+
+/// `part` as a percentage of `whole`, rounded to one decimal; 0 when `whole` is 0.
+fn percent(part: usize, whole: usize) -> f64 {
+    if whole == 0 {
+        return 0.0;
+    }
+    (part as f64 * 1000.0 / whole as f64).round() / 10.0
+}
+
+/// Files that fell into the "Others" bucket, grouped by extension (or file name when
+/// there is no extension).
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct UnmatchedSummary {
+    pub extension: String,
+    pub files: usize,
+    pub tokens: usize,
+}
+
+pub fn unmatched_extensions(files: &[FileCount]) -> Vec<UnmatchedSummary> {
+    let mut map: BTreeMap<String, UnmatchedSummary> = BTreeMap::new();
+    for f in files.iter().filter(|f| f.language == "Others") {
+        let key = extension_key(&f.path);
+        let entry = map.entry(key.clone()).or_insert_with(|| UnmatchedSummary {
+            extension: key,
+            ..UnmatchedSummary::default()
+        });
+        entry.files += 1;
+        entry.tokens += f.tokens;
+    }
+    let mut v: Vec<UnmatchedSummary> = map.into_values().collect();
+    v.sort_by(|a, b| {
+        b.tokens
+            .cmp(&a.tokens)
+            .then_with(|| a.extension.cmp(&b.extension))
+    });
+    v
+}
+
+/// The `n` files with the most tokens, largest first; ties are ordered by path.
+pub fn largest_files(files: &[FileCount], n: usize) -> Vec<&FileCount> {
+    let mut v: Vec<&FileCount> = files.iter().collect();
+    v.sort_by(|a, b| b.tokens.cmp(&a.tokens).then_with(|| a.path.cmp(&b.path)));
+    v.truncate(n);
+    v
+}
+
+pub fn count_tokens_in_path<P: AsRef<Path>>(root: P, opts: &Options) -> Result<CountResult> {
+    count_tokens_in_path_with_progress::<P, fn(usize, usize)>(root, opts, None)
+}
+
+/// Like `count_tokens_in_path`, but reports progress via the provided callback.
+/// The callback receives `(processed_files, total_files)`.
+pub fn count_tokens_in_path_with_progress<P, F>(
+    root: P,
+    opts: &Options,
+    progress: Option<&F>,
+) -> Result<CountResult>
+where
+    P: AsRef<Path>,
+    F: Fn(usize, usize) + Send + Sync,
+{
+    let (result, _) = scan(root.as_ref(), opts, progress, false, None)?;
+    Ok(result)
+}
+
+/// Count `root` on a background thread, sending each `FileCount` as soon as it is
+/// counted (in completion order, not sorted). The channel closes when the scan ends;
+/// join the handle for any error. Files left out by `exclude_empty` are not sent.
+pub fn spawn_count<P: AsRef<Path>>(
+    root: P,
+    opts: &Options,
+) -> (JoinHandle<Result<()>>, Receiver<FileCount>) {
+    let root = root.as_ref().to_path_buf();
+    let opts = opts.clone();
+    let (tx, rx) = mpsc::channel();
+    let handle = std::thread::spawn(move || {
+        // A dropped receiver only means nobody is listening; the scan still completes
+        let send = |file: &FileCount| {
+            let _ = tx.send(file.clone());
+        };
+        scan::<fn(usize, usize)>(&root, &opts, None, false, Some(&send)).map(|_| ())
+    });
+    (handle, rx)
+}
+
+/// Totals from `count_summary`: the rows of `aggregate_by_language` without the per-file
+/// records behind them.
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct SummaryResult {
+    pub total: usize,
+    pub by_language: Vec<LangSummary>,
+    // Same as `CountResult::skipped`
+    pub skipped: Vec<Warning>,
+}
+
+/// Like `aggregate_by_language(&count_tokens_in_path(..)?.files)`, but each file is folded
+/// into its language as soon as it is counted, so memory stays flat on huge trees.
+/// The cache is read but not updated, since no per-file records are kept.
+pub fn count_summary<P: AsRef<Path>>(root: P, opts: &Options) -> Result<SummaryResult> {
+    count_summary_with_progress::<P, fn(usize, usize)>(root, opts, None)
+}
+
+/// Like `count_summary`, reporting `(processed_files, total_files)` to `progress`.
+pub fn count_summary_with_progress<P, F>(
+    root: P,
+    opts: &Options,
+    progress: Option<&F>,
+) -> Result<SummaryResult>
+where
+    P: AsRef<Path>,
+    F: Fn(usize, usize) + Send + Sync,
+{
+    let ScanSetup {
+        encoder,
+        opts,
+        skipped,
+        paths,
+        cache,
+    } = prepare_scan(root.as_ref(), opts)?;
+    let opts = &opts;
+    let total_files = paths.len();
+    if let Some(cb) = progress {
+        cb(0, total_files);
+    }
+
+    let processed = AtomicUsize::new(0);
+    type Acc = (BTreeMap<String, LangSummary>, Vec<Warning>);
+    let (langs, empty) = paths
+        .par_iter()
+        .fold(Acc::default, |(mut langs, mut empty), path| {
+            let stamp = opts.cache_path.as_ref().and_then(|_| FileStamp::of(path));
+            let file = match stamp.and_then(|st| cache.get(path, st)) {
+                Some(file) => Some(file),
+                None => read_text_file(path, opts, opts.sample_bytes)
+                    .and_then(|text| count_text_file(path, &text, &*encoder, opts)),
+            };
+            match file {
+                Some(file) if opts.exclude_empty && is_blank(&file, opts) => {
+                    empty.push(Warning::Empty { path: file.path });
+                }
+                Some(file) => LangSummary::add_file(&mut langs, &file),
+                None => {}
+            }
+            let done = processed.fetch_add(1, Ordering::Relaxed) + 1;
+            if let Some(cb) = progress {
+                cb(done, total_files);
+            }
+            (langs, empty)
+        })
+        .reduce(
+            Acc::default,
+            |(mut langs, mut empty), (more, more_empty)| {
+                for (language, summary) in more {
+                    match langs.get_mut(&language) {
+                        Some(entry) => entry.absorb(&summary),
+                        None => {
+                            langs.insert(language, summary);
+                        }
+                    }
+                }
+                empty.extend(more_empty);
+                (langs, empty)
+            },
+        );
+
+    let by_language = finish_summaries(langs);
+    Ok(SummaryResult {
+        total: by_language.iter().map(|l| l.tokens).sum(),
+        by_language,
+        skipped: sorted_skips(&skipped, empty),
+    })
+}
+
+/// Count tokens and collect file contents in a single pass, reading each file once.
+/// Texts are `(relative_path, content)` sorted by path, as in `collect_filtered_texts`.
+pub fn count_tokens_and_collect_texts<P: AsRef<Path>>(
+    root: P,
+    opts: &Options,
+) -> Result<(CountResult, Vec<(PathBuf, String)>)> {
+    scan::<fn(usize, usize)>(root.as_ref(), opts, None, true, None)
+}
+
+/// Route a non-fatal warning to `opts.on_warning`, or print it to stderr unless `opts.quiet`.
+fn warn(opts: &Options, warning: Warning) {
+    if let Some(cb) = &opts.on_warning {
+        (cb.0)(&warning);
+    } else if !opts.quiet && !matches!(warning, Warning::TooSmall { .. }) {
+        // A minimum size is asked for to drop many stubs; a line for each would be noise
+        eprintln!("warn: {warning}");
+    }
+}
+
+/// Files larger than this are skipped with a warning unless `Options::max_file_bytes`
+/// says otherwise.
+const MAX_FILE_BYTES: u64 = 64 * 1024 * 1024;
+
+/// The skip for a file of `bytes` outside `opts`' size bounds, if any.
+fn size_skip(path: &Path, bytes: u64, opts: &Options) -> Option<Warning> {
+    let path = path.to_path_buf();
+    if bytes > opts.max_file_bytes.unwrap_or(MAX_FILE_BYTES) {
+        Some(Warning::TooLarge { path, bytes })
+    } else if opts.min_file_bytes.is_some_and(|min| bytes < min) {
+        Some(Warning::TooSmall { path, bytes })
+    } else {
+        None
+    }
+}
+
+/// Read `path` as UTF-8 text (only the first `limit` bytes when set), applying the size
+/// and binary guards. Returns `None` for skipped files.
+fn read_text_file(path: &Path, opts: &Options, limit: Option<usize>) -> Option<FileText> {
+    match load_text(path, opts, limit) {
+        Ok(Some(text)) if !opts.include_binary && has_binary_chars(&text) => {
+            warn(
+                opts,
+                Warning::SuspectedBinary {
+                    path: path.to_path_buf(),
+                },
+            );
+            None
+        }
+        Ok(text) => text,
+        Err(warning) => {
+            warn(opts, warning);
+            None
+        }
+    }
+}
+
+/// Whether `file` counts as empty for `exclude_empty`: no tokens, or no non-empty lines
+/// when lines are counted.
+fn is_blank(file: &FileCount, opts: &Options) -> bool {
+    file.tokens == 0 || (opts.count_lines && file.lines == 0)
+}
+
+/// Count freshly read `text`, dropping it with a warning when its token density says it
+/// is binary after all.
+fn count_text_file(
+    path: &Path,
+    text: &FileText,
+    encoder: &dyn Tokenizer,
+    opts: &Options,
+) -> Option<FileCount> {
+    let file = file_count_sampled(path, text, text.file_bytes(), encoder, opts);
+    if !opts.include_binary && has_binary_density(&file) {
+        warn(opts, Warning::SuspectedBinary { path: file.path });
+        return None;
+    }
+    Some(file)
+}
+
+/// Share of control characters (other than whitespace) and U+FFFD above which UTF-8
+/// text is treated as binary.
+const BINARY_CHAR_RATIO: f64 = 0.1;
+/// Only this many leading bytes are inspected for `BINARY_CHAR_RATIO`.
+const BINARY_SNIFF_BYTES: usize = 8 * 1024;
+/// Tokens per byte above which a file is treated as binary. Code and prose stay under
+/// 0.4 and CJK text under 0.7; junk bytes tokenize close to one token each.
+const BINARY_TOKENS_PER_BYTE: f64 = 0.8;
+/// Files smaller than this are too short for the token-density check to mean anything.
+const BINARY_DENSITY_MIN_BYTES: u64 = 512;
+
+/// Whether the start of `text` is dominated by control or replacement characters.
+fn has_binary_chars(text: &str) -> bool {
+    let mut end = text.len().min(BINARY_SNIFF_BYTES);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    let (mut chars, mut odd) = (0usize, 0usize);
+    for c in text[..end].chars() {
+        chars += 1;
+        if c == '\u{FFFD}' || (c.is_control() && !matches!(c, '\t' | '\n' | '\r' | '\x0c')) {
+            odd += 1;
+        }
+    }
+    chars > 0 && odd as f64 > chars as f64 * BINARY_CHAR_RATIO
+}
+
+/// Whether `file` produced implausibly many tokens for its size.
+fn has_binary_density(file: &FileCount) -> bool {
+    file.bytes >= BINARY_DENSITY_MIN_BYTES
+        && file.tokens as f64 > file.bytes as f64 * BINARY_TOKENS_PER_BYTE
 }
 
-#[derive(Debug, Serialize, Clone)]
-pub struct CountResult {
-    pub total: usize,
-    pub files: Vec<FileCount>,
+/// Files at least this large are memory-mapped instead of read into a buffer.
+const MMAP_THRESHOLD_BYTES: u64 = 1024 * 1024;
+
+/// UTF-8 file contents, either owned or memory-mapped to keep peak memory flat on huge files.
+enum FileText {
+    Owned(String),
+    // Invariant: the mapped bytes were validated as UTF-8 when the map was created
+    Mapped(memmap2::Mmap),
+    // The leading bytes of a larger file, read for `Options::sample_bytes`
+    Sample { text: String, file_bytes: u64 },
 }
 
-/// Internal helper: enumerate files under `root` honoring ignore rules and `opts` filters.
-fn enumerate_filtered_paths<P: AsRef<Path>>(root: P, opts: &Options) -> Vec<PathBuf> {
-    let mut builder = WalkBuilder::new(root);
-    // Honor .gitignore and related git rules explicitly; control hidden files via option
-    builder.hidden(!opts.include_hidden);
-    builder.follow_links(false);
-    builder.ignore(true); // respect .ignore
-    builder.git_ignore(true); // respect .gitignore
-    builder.git_global(true); // respect global gitignore
-    builder.git_exclude(true); // respect .git/info/exclude
-                               // In environments without a .git directory, also treat .gitignore as a custom ignore file
-    builder.add_custom_ignore_filename(".gitignore");
+impl FileText {
+    fn into_string(self) -> String {
+        match self {
+            FileText::Owned(s) | FileText::Sample { text: s, .. } => s,
+            FileText::Mapped(_) => String::from(&*self),
+        }
+    }
 
-    let walker = builder.build();
-    let mut paths: Vec<PathBuf> = Vec::new();
-    for dent in walker {
-        let dent = match dent {
-            Ok(d) => d,
-            Err(err) => {
-                eprintln!("warn: skipping entry: {err}");
-                continue;
-            }
-        };
+    /// Size of the whole file, which exceeds the text's length for a sample.
+    fn file_bytes(&self) -> u64 {
+        match self {
+            FileText::Sample { file_bytes, .. } => *file_bytes,
+            _ => self.len() as u64,
+        }
+    }
+}
 
-        let ft = match dent.file_type() {
-            Some(t) if t.is_file() => t,
-            _ => continue,
-        };
-        let _ = ft; // silence unused in some toolchains
-
-        let path = dent.path();
-        // Filter by extension if requested
-        if let Some(exts) = &opts.include_exts {
-            let ext = path
-                .extension()
-                .and_then(|s| s.to_str())
-                .map(|s| s.trim_start_matches('.').to_ascii_lowercase());
-            let keep = match ext {
-                Some(ref e) => exts.contains(e),
-                None => exts.contains(""),
-            };
-            if !keep {
-                continue;
-            }
+impl std::ops::Deref for FileText {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        match self {
+            FileText::Owned(s) | FileText::Sample { text: s, .. } => s,
+            // SAFETY: validated with `std::str::from_utf8` in `load_text`
+            FileText::Mapped(m) => unsafe { std::str::from_utf8_unchecked(m) },
         }
-        paths.push(path.to_path_buf());
     }
-    paths
 }
 
-pub fn get_encoder(encoding: &str) -> Result<CoreBPE> {
-    match encoding {
-        // Common encodings
-        "cl100k_base" => tiktoken_rs::cl100k_base().context("Failed to init cl100k_base"),
-        "o200k_base" => tiktoken_rs::o200k_base().context("Failed to init o200k_base"),
-        "p50k_base" => tiktoken_rs::p50k_base().context("Failed to init p50k_base"),
-        "p50k_edit" => tiktoken_rs::p50k_edit().context("Failed to init p50k_edit"),
-        "r50k_base" => tiktoken_rs::r50k_base().context("Failed to init r50k_base"),
-        other => anyhow::bail!("Unsupported encoding: {other}"),
+/// Read `path` as UTF-8 text within `opts`' size bounds, or only its first `limit` bytes
+/// (cut back to a character boundary) when it is larger. `Ok(None)` means the file is not
+/// UTF-8 (treated as binary).
+fn load_text(
+    path: &Path,
+    opts: &Options,
+    limit: Option<usize>,
+) -> std::result::Result<Option<FileText>, Warning> {
+    let metadata = fs::metadata(path).map_err(|err| Warning::Metadata {
+        path: path.to_path_buf(),
+        message: err.to_string(),
+    })?;
+    if let Some(skip) = size_skip(path, metadata.len(), opts) {
+        return Err(skip);
+    }
+    let read_err = |err: std::io::Error| Warning::Read {
+        path: path.to_path_buf(),
+        message: err.to_string(),
+    };
+    if let Some(limit) = limit.filter(|&n| metadata.len() > n as u64) {
+        use std::io::Read;
+        let mut bytes = Vec::with_capacity(limit);
+        fs::File::open(path)
+            .and_then(|f| f.take(limit as u64).read_to_end(&mut bytes))
+            .map_err(read_err)?;
+        // The limit may split a multi-byte character; only a cut-off tail is forgiven
+        let valid = match std::str::from_utf8(&bytes) {
+            Ok(_) => bytes.len(),
+            Err(err) if err.error_len().is_none() => err.valid_up_to(),
+            Err(_) => return Ok(None),
+        };
+        bytes.truncate(valid);
+        return Ok(String::from_utf8(bytes).ok().map(|text| FileText::Sample {
+            text,
+            file_bytes: metadata.len(),
+        }));
+    }
+    if metadata.len() >= MMAP_THRESHOLD_BYTES {
+        let file = fs::File::open(path).map_err(read_err)?;
+        // SAFETY: the map is read-only; concurrent truncation by another process is
+        // outside our control, the same caveat every mmap-based scanner accepts.
+        let map = unsafe { memmap2::Mmap::map(&file) }.map_err(read_err)?;
+        if std::str::from_utf8(&map).is_err() {
+            return Ok(None);
+        }
+        return Ok(Some(FileText::Mapped(map)));
     }
+    let bytes = fs::read(path).map_err(read_err)?;
+    Ok(String::from_utf8(bytes).ok().map(FileText::Owned))
 }
 
-/// Count tokens in a string with a fast path and a timeout fallback.
-pub fn count_tokens_in_text(encoder: &CoreBPE, text: &str) -> usize {
-    const CHUNK_SIZE: usize = 512; // bytes
+/// Chunk size for tokenizing long texts: `Options::chunk_bytes` or `DEFAULT_CHUNK_BYTES`.
+fn chunk_bytes(opts: &Options) -> usize {
+    opts.chunk_bytes.unwrap_or(DEFAULT_CHUNK_BYTES)
+}
 
-    // Quick exit for trivial cases
-    if text.is_empty() {
-        return 0;
+/// Rough token count used by `Options::estimate`: one token per four characters.
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+fn tokens_for(text: &str, encoder: &dyn Tokenizer, opts: &Options) -> usize {
+    if opts.normalize_whitespace {
+        encoder.count_chunked(&normalize_whitespace(text), chunk_bytes(opts))
+    } else {
+        encoder.count_chunked(text, chunk_bytes(opts))
     }
+}
 
-    // For short inputs or when we couldn't split, just do a blocking encode
-    if text.len() <= CHUNK_SIZE * 4 {
-        return encoder.encode_with_special_tokens(text).len();
+/// `text` with every run of spaces and tabs collapsed to one space and trailing spaces and
+/// tabs removed from each line, for `Options::normalize_whitespace`. Line breaks (`\n` or
+/// `\r\n`) are kept, so the text has the same lines.
+pub fn normalize_whitespace(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for line in text.split_inclusive('\n') {
+        let body = line
+            .strip_suffix('\n')
+            .map_or(line, |l| l.strip_suffix('\r').unwrap_or(l));
+        let mut in_run = false;
+        for ch in body.trim_end_matches([' ', '\t']).chars() {
+            let blank = ch == ' ' || ch == '\t';
+            if !(blank && in_run) {
+                out.push(if blank { ' ' } else { ch });
+            }
+            in_run = blank;
+        }
+        out.push_str(&line[body.len()..]);
     }
+    out
+}
 
-    // Split into chunks to avoid some edge cases that can make the progrom super slow
-    // Chunk the input and recurse in parallel (without further timeouts)
-    let chunks = split_text_into_chunks(text, CHUNK_SIZE);
-    if chunks.len() <= 1 {
-        return encoder.encode_with_special_tokens(text).len();
+/// Build the `FileCount` for already-loaded `text`.
+fn file_count_for_text(
+    path: &Path,
+    text: &str,
+    encoder: &dyn Tokenizer,
+    opts: &Options,
+) -> FileCount {
+    let language = if opts.smart_language {
+        language_from_content(path, text)
+    } else {
+        language_from_path(path)
+    };
+    let (max_line_len, long_lines) = line_lengths(text, opts.long_line_threshold);
+    let (total_lines, blank_lines) = if opts.count_lines {
+        count_line_totals(text)
+    } else {
+        (0, 0)
+    };
+    FileCount {
+        path: path.to_path_buf(),
+        tokens: tokens_for(text, encoder, opts),
+        lines: total_lines - blank_lines,
+        total_lines,
+        blank_lines,
+        language,
+        bytes: text.len() as u64,
+        words: text.split_whitespace().count(),
+        chars: text.chars().count(),
+        max_line_len,
+        long_lines,
+        sampled: false,
     }
-    chunks
-        .par_iter()
-        .map(|s| encoder.encode_with_special_tokens(s).len())
-        .sum()
 }
 
-fn split_text_into_chunks<'a>(text: &'a str, max_chunk_bytes: usize) -> Vec<&'a str> {
-    debug_assert!(max_chunk_bytes > 0);
-    let mut chunks: Vec<&'a str> = Vec::new();
-    let mut start = 0usize;
-    let len = text.len();
+/// Count `text` from a file of `file_bytes` bytes. With `Options::sample_bytes` set and a
+/// larger file, only that many leading bytes are counted and the counts scaled up.
+fn file_count_sampled(
+    path: &Path,
+    text: &str,
+    file_bytes: u64,
+    encoder: &dyn Tokenizer,
+    opts: &Options,
+) -> FileCount {
+    let limit = match opts.sample_bytes {
+        Some(limit) if file_bytes > limit as u64 => limit,
+        _ => return file_count_for_text(path, text, encoder, opts),
+    };
+    let mut end = text.len().min(limit);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    let sample = &text[..end];
+    let mut file = file_count_for_text(path, sample, encoder, opts);
+    let scale = file_bytes as f64 / sample.len().max(1) as f64;
+    let scaled = |n: usize| (n as f64 * scale).round() as usize;
+    file.tokens = scaled(file.tokens);
+    file.lines = scaled(file.lines);
+    file.total_lines = scaled(file.total_lines);
+    file.blank_lines = scaled(file.blank_lines);
+    file.words = scaled(file.words);
+    file.chars = scaled(file.chars);
+    file.long_lines = scaled(file.long_lines);
+    file.bytes = file_bytes;
+    file.sampled = true;
+    file
+}
 
-    while start < len {
-        let remaining = len - start;
-        if remaining <= max_chunk_bytes {
-            chunks.push(&text[start..]);
-            break;
+/// Count a single file without walking a directory, honoring `Options::sample_bytes`.
+/// Fails if the file is unreadable, exceeds the size limit, or is not UTF-8 text.
+pub fn count_file<P: AsRef<Path>>(path: P, opts: &Options) -> Result<FileCount> {
+    let path = path.as_ref();
+    let text = load_text(path, opts, opts.sample_bytes)?
+        .with_context(|| format!("{} is not a UTF-8 text file", path.display()))?;
+    let encoder = encoder_for(opts)?;
+    Ok(file_count_sampled(
+        path,
+        &text,
+        text.file_bytes(),
+        &*encoder,
+        opts,
+    ))
+}
+
+/// Whether `path` is a file `count_archive` can read: `.zip`, `.tar.gz` or `.tgz`.
+pub fn is_archive(path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    [".zip", ".tar.gz", ".tgz"]
+        .iter()
+        .any(|ext| name.ends_with(ext))
+        && path.is_file()
+}
+
+/// Count the files inside a `.zip`, `.tar.gz` or `.tgz` archive without extracting it.
+/// Entries pass the same hidden, extension, language and common-directory filters as a
+/// walk (ignore files and linguist attributes do not apply) and are reported as
+/// `<archive>/<entry path>`. Non-UTF-8 entries are skipped like binary files.
+#[cfg(feature = "archive")]
+pub fn count_archive<P: AsRef<Path>>(archive: P, opts: &Options) -> Result<CountResult> {
+    let archive = archive.as_ref();
+    let encoder = encoder_for(opts)?;
+    let always = PathGlobs::new(&opts.always_include).context("invalid always-include pattern")?;
+    let keep = |rel: &Path| {
+        (opts.include_hidden
+            || !is_hidden_under(Path::new(""), rel)
+            || (!opts.always_include.is_empty() && always.is_match(rel)))
+            && (!opts.exclude_common || !in_common_excluded_dir(Path::new(""), rel))
+            && ext_allowed(rel, opts)
+    };
+
+    let mut texts: Vec<(PathBuf, String)> = Vec::new();
+    let mut skipped = Vec::new();
+    let mut add = |rel: &Path, size: u64, reader: &mut dyn std::io::Read| {
+        if !keep(rel) {
+            return;
         }
+        let path = archive.join(rel);
+        let warning = if let Some(skip) = size_skip(&path, size, opts) {
+            skip
+        } else {
+            let mut bytes = Vec::with_capacity(size as usize);
+            match reader.read_to_end(&mut bytes) {
+                Ok(_) => match String::from_utf8(bytes) {
+                    Ok(text) if !opts.include_binary && has_binary_chars(&text) => {
+                        Warning::SuspectedBinary { path }
+                    }
+                    Ok(text) => {
+                        texts.push((path, text));
+                        return;
+                    }
+                    Err(_) => return,
+                },
+                Err(err) => Warning::Read {
+                    path,
+                    message: err.to_string(),
+                },
+            }
+        };
+        skipped.push(warning.clone());
+        warn(opts, warning);
+    };
 
-        // 1) Ensure at least `max_chunk_bytes` bytes in this chunk
-        let mut base_rel = 0usize; // end boundary relative to `start`
-        let mut acc = 0usize;
-        for (off, ch) in text[start..].char_indices() {
-            let w = ch.len_utf8();
-            acc += w;
-            base_rel = off + w; // boundary after this char
-            if acc >= max_chunk_bytes {
-                break;
+    let file = fs::File::open(archive)
+        .with_context(|| format!("failed to open archive {}", archive.display()))?;
+    let name = archive.to_string_lossy().to_ascii_lowercase();
+    if name.ends_with(".zip") {
+        let mut zip = zip::ZipArchive::new(file)
+            .with_context(|| format!("failed to read zip archive {}", archive.display()))?;
+        for i in 0..zip.len() {
+            let mut entry = zip.by_index(i)?;
+            // `enclosed_name` drops entries that would escape the archive root
+            if let (true, Some(rel)) = (entry.is_file(), entry.enclosed_name()) {
+                add(&rel, entry.size(), &mut entry);
             }
         }
-        let base_end = start + base_rel;
-
-        // 2) Look ahead up to another `max_chunk_bytes` bytes for a nice split
-        let mut extended_end = None;
-        let mut la_bytes = 0usize;
-        for (off, ch) in text[base_end..].char_indices() {
-            let w = ch.len_utf8();
-            if ch == ' ' || ch == '\n' {
-                extended_end = Some(base_end + off + w); // split after the whitespace
-                break;
+    } else {
+        let mut tar = tar::Archive::new(flate2::read::GzDecoder::new(file));
+        let entries = tar
+            .entries()
+            .with_context(|| format!("failed to read tar archive {}", archive.display()))?;
+        for entry in entries {
+            let mut entry = entry
+                .with_context(|| format!("failed to read tar archive {}", archive.display()))?;
+            if !entry.header().entry_type().is_file() {
+                continue;
             }
-            la_bytes += w;
-            if la_bytes >= max_chunk_bytes {
-                break;
+            let rel = entry.path()?.into_owned();
+            let rel: PathBuf = rel
+                .components()
+                .filter(|c| matches!(c, std::path::Component::Normal(_)))
+                .collect();
+            if !rel.as_os_str().is_empty() {
+                let size = entry.size();
+                add(&rel, size, &mut entry);
             }
         }
-        let end = extended_end.unwrap_or(base_end);
-        chunks.push(&text[start..end]);
-        start = end;
     }
 
-    chunks
+    let counted: Vec<FileCount> = texts
+        .par_iter()
+        .map(|(path, text)| file_count_sampled(path, text, text.len() as u64, &*encoder, opts))
+        .collect();
+    let mut files = Vec::with_capacity(counted.len());
+    for file in counted {
+        if opts.exclude_empty && is_blank(&file, opts) {
+            skipped.push(Warning::Empty { path: file.path });
+        } else if !opts.include_binary && has_binary_density(&file) {
+            let warning = Warning::SuspectedBinary { path: file.path };
+            warn(opts, warning.clone());
+            skipped.push(warning);
+        } else {
+            files.push(file);
+        }
+    }
+    // Archive order is whatever the packer wrote
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    skipped.sort_by(|a, b| a.path().cmp(&b.path()));
+    Ok(CountResult {
+        total: files.iter().map(|f| f.tokens).sum(),
+        files,
+        skipped,
+    })
 }
 
-pub fn count_non_empty_lines(text: &str) -> usize {
-    text.lines().filter(|l| !l.trim().is_empty()).count()
+#[cfg(not(feature = "archive"))]
+pub fn count_archive<P: AsRef<Path>>(archive: P, _opts: &Options) -> Result<CountResult> {
+    anyhow::bail!(
+        "cannot read {}: loctok was built without the `archive` feature",
+        archive.as_ref().display()
+    )
 }
 
-pub fn language_from_path(path: &Path) -> String {
-    let ext = path
-        .extension()
-        .and_then(|s| s.to_str())
-        .unwrap_or("")
-        .to_ascii_lowercase();
-    let ans = match ext.as_str() {
-        "abap" => "ABAP",
-        "ac" => "m4",
-        "ada" => "Ada",
-        "adb" => "Ada",
-        "ads" => "Ada",
-        "adso" => "ADSO/IDSM",
-        "ahkl" => "AutoHotkey",
-        "ahk" => "AutoHotkey",
-        "agda" => "Agda",
-        "lagda" => "Agda",
-        "aj" => "AspectJ",
-        "am" => "make",
-        "ample" => "AMPLE",
-        "apl" => "APL",
-        "apla" => "APL",
-        "aplf" => "APL",
-        "aplo" => "APL",
-        "apln" => "APL",
-        "aplc" => "APL",
-        "apli" => "APL",
-        "applescript" => "AppleScript",
-        "dyalog" => "APL",
-        "dyapp" => "APL",
-        "mipage" => "APL",
-        "art" => "Arturo",
-        "as" => "ActionScript",
-        "adoc" => "AsciiDoc",
-        "asciidoc" => "AsciiDoc",
-        "dofile" => "AMPLE",
-        "startup" => "AMPLE",
-        "aria" => "Aria",
-        "axd" => "ASP",
-        "ashx" => "ASP",
-        "asa" => "ASP",
-        "asax" => "ASP.NET",
-        "ascx" => "ASP.NET",
-        "asd" => "Lisp",
-        "asmx" => "ASP.NET",
-        "asp" => "ASP",
-        "aspx" => "ASP.NET",
-        "master" => "ASP.NET",
-        "sitemap" => "ASP.NET",
-        "nasm" => "Assembly",
-        "a51" => "Assembly",
-        "asm" => "Assembly",
-        "astro" => "Astro",
-        "asy" => "Asymptote",
-        "cshtml" => "Razor",
-        "razor" => "Razor",
-        "nawk" => "awk",
-        "mawk" => "awk",
-        "gawk" => "awk",
-        "auk" => "awk",
-        "awk" => "awk",
-        "bash" => "Bourne Again Shell",
-        "bazel" => "Starlark",
-        "BUILD" => "Bazel",
-        "dxl" => "DOORS Extension Language",
-        "bat" => "DOS Batch",
-        "BAT" => "DOS Batch",
-        "cmd" => "DOS Batch",
-        "CMD" => "DOS Batch",
-        "btm" => "DOS Batch",
-        "BTM" => "DOS Batch",
-        "blade" => "Blade",
-        "blade.php" => "Blade",
-        "build.xml" => "Ant",
-        "b" => "Brainfuck",
-        "bf" => "Brainfuck",
-        "blp" => "Blueprint",
-        "brs" => "BrightScript",
-        "bzl" => "Starlark",
-        "btp" => "BizTalk Pipeline",
-        "odx" => "BizTalk Orchestration",
-        "carbon" => "Carbon",
-        "cpy" => "COBOL",
-        "cobol" => "COBOL",
-        "ccp" => "COBOL",
-        "cbl" => "COBOL",
-        "CBL" => "COBOL",
-        "idc" => "C",
-        "cats" => "C",
-        "c" => "C",
-        "c++" => "C++",
-        "C" => "C++",
-        "cc" => "C++",
-        "ccm" => "C++",
-        "c++m" => "C++",
-        "cppm" => "C++",
-        "cxxm" => "C++",
-        "h++" => "C++",
-        "inl" => "C++",
-        "ipp" => "C++",
-        "ixx" => "C++",
-        "tcc" => "C++",
-        "tpp" => "C++",
-        "cdc" => "Cadence",
-        "ccs" => "CCS",
-        "civet" => "Civet",
-        "cvt" => "Civet",
-        "cvtx" => "Civet",
-        "cfc" => "ColdFusion CFScript",
-        "cfml" => "ColdFusion",
-        "cfm" => "ColdFusion",
-        "chpl" => "Chapel",
-        "cl" => "Lisp/OpenCL",
-        "riemann.config" => "Clojure",
-        "hic" => "Clojure",
-        "cljx" => "Clojure",
-        "cljscm" => "Clojure",
-        "cljs.hl" => "Clojure",
-        "cl2" => "Clojure",
-        "boot" => "Clojure",
-        "cj" => "Clojure/Cangjie",
-        "clj" => "Clojure",
-        "cljs" => "ClojureScript",
-        "cljc" => "ClojureC",
-        "cls" => "Visual Basic/TeX/Apex Class",
-        "cmake.in" => "CMake",
-        "CMakeLists.txt" => "CMake",
-        "cmake" => "CMake",
-        "cob" => "COBOL",
-        "COB" => "COBOL",
-        "cocoa5" => "CoCoA 5",
-        "c5" => "CoCoA 5",
-        "cpkg5" => "CoCoA 5",
-        "cocoa5server" => "CoCoA 5",
-        "iced" => "CoffeeScript",
-        "cjsx" => "CoffeeScript",
-        "cakefile" => "CoffeeScript",
-        "_coffee" => "CoffeeScript",
-        "coffee" => "CoffeeScript",
-        "component" => "Visualforce Component",
-        "cg3" => "Constraint Grammar",
-        "rlx" => "Constraint Grammar",
-        "Containerfile" => "Containerfile",
-        "cpp" => "C++",
-        "CPP" => "C++",
-        "cr" => "Crystal",
-        "cs" => "C#/Smalltalk",
-        "designer.cs" => "C# Designer",
-        "cake" => "Cake Build Script",
-        "csh" => "C Shell",
-        "cson" => "CSON",
-        "css" => "CSS",
-        "csv" => "CSV",
-        "cu" => "CUDA",
-        "cuh" => "CUDA",
-        "cxx" => "C++",
-        "d" => "D/dtrace",
-        "dfy" => "Dafny",
-        "da" => "DAL",
-        "dart" => "Dart",
-        "dsc" => "DenizenScript",
-        "derw" => "Derw",
-        "def" => "Windows Module Definition",
-        "dhall" => "dhall",
-        "dt" => "DIET",
-        "patch" => "diff",
-        "diff" => "diff",
-        "dmap" => "NASTRAN DMAP",
-        "sthlp" => "Stata",
-        "matah" => "Stata",
-        "mata" => "Stata",
-        "ihlp" => "Stata",
-        "doh" => "Stata",
-        "ado" => "Stata",
-        "do" => "Stata",
-        "DO" => "Stata",
-        "Dockerfile" => "Dockerfile",
-        "dockerfile" => "Dockerfile",
-        "pascal" => "Pascal",
-        "lpr" => "Pascal",
-        "dfm" => "Delphi Form",
-        "dpr" => "Pascal",
-        "dita" => "DITA",
-        "drl" => "Drools",
-        "dtd" => "DTD",
-        "ec" => "C",
-        "ecpp" => "ECPP",
-        "eex" => "EEx",
-        "el" => "Lisp",
-        "elm" => "Elm",
-        "exs" => "Elixir Script",
-        "ex" => "Elixir",
-        "ecr" => "Embedded Crystal",
-        "ejs" => "EJS",
-        "erb" => "ERB",
-        "ERB" => "ERB",
-        "ets" => "ArkTs",
-        "yrl" => "Erlang",
-        "xrl" => "Erlang",
-        "rebar.lock" => "Erlang",
-        "rebar.config.lock" => "Erlang",
-        "rebar.config" => "Erlang",
-        "emakefile" => "Erlang",
-        "app.src" => "Erlang",
-        "erl" => "Erlang",
-        "exp" => "Expect",
-        "4th" => "Forth",
-        "fish" => "Fish Shell",
-        "fsl" => "Finite State Language",
-        "jssm" => "Finite State Language",
-        "fnl" => "Fennel",
-        "forth" => "Forth",
-        "fr" => "Forth",
-        "frt" => "Forth",
-        "fth" => "Forth",
-        "f83" => "Forth",
-        "fb" => "Forth",
-        "fpm" => "Forth",
-        "e4" => "Forth",
-        "rx" => "Forth",
-        "ft" => "Forth",
-        "f77" => "Fortran 77",
-        "F77" => "Fortran 77",
-        "f90" => "Fortran 90",
-        "F90" => "Fortran 90",
-        "f95" => "Fortran 95",
-        "F95" => "Fortran 95",
-        "f" => "Fortran 77/Forth",
-        "F" => "Fortran 77",
-        "for" => "Fortran 77/Forth",
-        "FOR" => "Fortran 77",
-        "ftl" => "Freemarker Template",
-        "ftn" => "Fortran 77",
-        "FTN" => "Fortran 77",
-        "f03" => "Fortran 2003",
-        "F03" => "Fortran 2003",
-        "fmt" => "Oracle Forms",
-        "focexec" => "Focus",
-        "fs" => "F#/Forth",
-        "fsi" => "F#",
-        "fsx" => "F# Script",
-        "fut" => "Futhark",
-        "fxml" => "FXML",
-        "gnumakefile" => "make",
-        "Gnumakefile" => "make",
-        "gd" => "GDScript",
-        "gdshader" => "Godot Shaders",
-        "vshader" => "GLSL",
-        "vsh" => "GLSL",
-        "vrx" => "GLSL",
-        "gshader" => "GLSL",
-        "glslv" => "GLSL",
-        "geo" => "GLSL",
-        "fshader" => "GLSL",
-        "fsh" => "GLSL",
-        "frg" => "GLSL",
-        "fp" => "GLSL",
-        "fbs" => "Flatbuffers",
-        "gjs" => "Glimmer JavaScript",
-        "gts" => "Glimmer TypeScript",
-        "glsl" => "GLSL",
-        "graphqls" => "GraphQL",
-        "gql" => "GraphQL",
-        "graphql" => "GraphQL",
-        "vert" => "GLSL",
-        "tesc" => "GLSL",
-        "tese" => "GLSL",
-        "geom" => "GLSL",
-        "feature" => "Cucumber",
-        "frag" => "GLSL",
-        "comp" => "GLSL",
-        "g" => "ANTLR Grammar",
-        "g4" => "ANTLR Grammar",
-        "gleam" => "Gleam",
-        "go" => "Go",
-        "ʕ◔ϖ◔ʔ" => "Go",
-        "gsp" => "Grails",
-        "jenkinsfile" => "Groovy",
-        "gvy" => "Groovy",
-        "gtpl" => "Groovy",
-        "grt" => "Groovy",
-        "groovy" => "Groovy",
-        "gant" => "Groovy",
-        "gradle" => "Gradle",
-        "gradle.kts" => "Gradle",
-        "h" => "C/C++ Header",
-        "H" => "C/C++ Header",
-        "hh" => "C/C++ Header",
-        "hpp" => "C/C++ Header",
-        "hxx" => "C/C++ Header",
-        "hb" => "Harbour",
-        "hrl" => "Erlang",
-        "hsc" => "Haskell",
-        "hs" => "Haskell",
-        "tfvars" => "HCL",
-        "hcl" => "HCL",
-        "tf" => "HCL",
-        "nomad" => "HCL",
-        "hlsli" => "HLSL",
-        "fxh" => "HLSL",
-        "hlsl" => "HLSL",
-        "shader" => "HLSL",
-        "cg" => "HLSL",
-        "cginc" => "HLSL",
-        "haml.deface" => "Haml",
-        "haml" => "Haml",
-        "handlebars" => "Handlebars",
-        "hbs" => "Handlebars",
-        "ha" => "Hare",
-        "hxsl" => "Haxe",
-        "hx" => "Haxe",
-        "HC" => "HolyC",
-        "hoon" => "Hoon",
-        "xht" => "HTML",
-        "html.hl" => "HTML",
-        "htm" => "HTML",
-        "html" => "HTML",
-        "heex" => "HTML EEx",
-        "i3" => "Modula3",
-        "ice" => "Slice",
-        "icl" => "Clean",
-        "dcl" => "Clean",
-        "dlm" => "IDL",
-        "idl" => "IDL",
-        "idr" => "Idris",
-        "lidr" => "Literate Idris",
-        "imba" => "Imba",
-        "prefs" => "INI",
-        "lektorproject" => "INI",
-        "buildozer.spec" => "INI",
-        "ini" => "INI",
-        "editorconfig" => "INI",
-        "ism" => "InstallShield",
-        "ipl" => "IPL",
-        "pro" => "IDL/Qt Project/Prolog/ProGuard",
-        "ig" => "Modula3",
-        "il" => "SKILL/.NET IL",
-        "ils" => "SKILL++",
-        "inc" => "PHP/Pascal/Fortran/Pawn",
-        "ino" => "Arduino Sketch",
-        "ipf" => "Igor Pro",
-        "pde" => "Processing",
-        "itk" => "Tcl/Tk",
-        "java" => "Java",
-        "jcl" => "JCL",
-        "jl" => "Lisp/Julia",
-        "jai" => "Jai",
-        "janet" => "Janet",
-        "xsjslib" => "JavaScript",
-        "xsjs" => "JavaScript",
-        "ssjs" => "JavaScript",
-        "sjs" => "JavaScript",
-        "pac" => "JavaScript",
-        "njs" => "JavaScript",
-        "mjs" => "JavaScript",
-        "cjs" => "JavaScript",
-        "jss" => "JavaScript",
-        "jsm" => "JavaScript",
-        "jsfl" => "JavaScript",
-        "jscad" => "JavaScript",
-        "jsb" => "JavaScript",
-        "jakefile" => "JavaScript",
-        "jake" => "JavaScript",
-        "bones" => "JavaScript",
-        "_js" => "JavaScript",
-        "js" => "JavaScript",
-        "es6" => "JavaScript",
-        "jsf" => "JavaServer Faces",
-        "jsx" => "JSX",
-        "xhtml" => "XHTML",
-        "j2" => "Jinja Template",
-        "jinja" => "Jinja Template",
-        "jinja2" => "Jinja Template",
-        "yyp" => "JSON",
-        "webmanifest" => "JSON",
-        "webapp" => "JSON",
-        "topojson" => "JSON",
-        "tfstate.backup" => "JSON",
-        "tfstate" => "JSON",
-        "mcmod.info" => "JSON",
-        "mcmeta" => "JSON",
-        "json-tmlanguage" => "JSON",
-        "jsonl" => "JSON",
-        "har" => "JSON",
-        "gltf" => "JSON",
-        "geojson" => "JSON",
-        "composer.lock" => "JSON",
-        "avsc" => "JSON",
-        "watchmanconfig" => "JSON",
-        "tern-project" => "JSON",
-        "tern-config" => "JSON",
-        "htmlhintrc" => "JSON",
-        "arcconfig" => "JSON",
-        "json" => "JSON",
-        "json5" => "JSON5",
-        "jsonnet" => "Jsonnet",
-        "jsp" => "JSP",
-        "jspf" => "JSP",
-        "junos" => "Juniper Junos",
-        "just" => "Justfile",
-        "vm" => "Velocity Template Language",
-        "kv" => "kvlang",
-        "ksc" => "Kermit",
-        "ksh" => "Korn Shell",
-        "ktm" => "Kotlin",
-        "kt" => "Kotlin",
-        "kts" => "Kotlin",
-        "hlean" => "Lean",
-        "lean" => "Lean",
-        "lhs" => "Haskell",
-        "lex" => "lex",
-        "l" => "lex",
-        "ld" => "Linker Script",
-        "lem" => "Lem",
-        "less" => "LESS",
-        "lfe" => "LFE",
-        "liquid" => "liquid",
-        "lsp" => "Lisp",
-        "lisp" => "Lisp",
-        "ll" => "LLVM IR",
-        "lgt" => "Logtalk",
-        "logtalk" => "Logtalk",
-        "lp" => "AnsProlog",
-        "wlua" => "Lua",
-        "rbxs" => "Lua",
-        "pd_lua" => "Lua",
-        "p8" => "Lua",
-        "nse" => "Lua",
-        "lua" => "Lua",
-        "luau" => "Luau",
-        "m3" => "Modula3",
-        "m4" => "m4",
-        "makefile" => "make",
-        "Makefile" => "make",
-        "mao" => "Mako",
-        "mako" => "Mako",
-        "workbook" => "Markdown",
-        "ronn" => "Markdown",
-        "mkdown" => "Markdown",
-        "mkdn" => "Markdown",
-        "mkd" => "Markdown",
-        "mdx" => "Markdown",
-        "mdwn" => "Markdown",
-        "mdown" => "Markdown",
-        "markdown" => "Markdown",
-        "contents.lr" => "Markdown",
-        "md" => "Markdown",
-        "org" => "Org Mode",
-        "mc" => "Windows Message File",
-        "met" => "Teamcenter met",
-        "mg" => "Modula3",
-        "mojom" => "Mojom",
-        "mojo" => "Mojo",
-        "🔥" => "Mojo",
-        "mbt" => "MoonBit",
-        "mbti" => "MoonBit",
-        "mbtx" => "MoonBit",
-        "mbty" => "MoonBit",
-        "meson.build" => "Meson",
-        "metal" => "Metal",
-        "mk" => "make",
-        "ml4" => "OCaml",
-        "eliomi" => "OCaml",
-        "eliom" => "OCaml",
-        "ml" => "OCaml",
-        "mli" => "OCaml",
-        "mly" => "OCaml",
-        "mll" => "OCaml",
-        "m" => "MATLAB/Mathematica/Objective-C/MUMPS/Mercury",
-        "mm" => "Objective-C++",
-        "msg" => "Gencat NLS",
-        "nbp" => "Mathematica",
-        "mathematica" => "Mathematica",
-        "ma" => "Mathematica",
-        "cdf" => "Mathematica",
-        "mt" => "Mathematica",
-        "wl" => "Mathematica",
-        "wlt" => "Mathematica",
-        "mo" => "Modelica",
-        "mustache" => "Mustache",
-        "wdproj" => "MSBuild script",
-        "csproj" => "MSBuild script",
-        "vcproj" => "MSBuild script",
-        "wixproj" => "MSBuild script",
-        "btproj" => "MSBuild script",
-        "msbuild" => "MSBuild script",
-        "sln" => "Visual Studio Solution",
-        "mps" => "MUMPS",
-        "mth" => "Teamcenter mth",
-        "n" => "Nemerle",
-        "nlogo" => "NetLogo",
-        "nls" => "NetLogo",
-        "nf" => "Nextflow",
-        "ncl" => "Nickel",
-        "nims" => "Nim",
-        "nimrod" => "Nim",
-        "nimble" => "Nim",
-        "nim.cfg" => "Nim",
-        "nim" => "Nim",
-        "nix" => "Nix",
-        "nu" => "Nushell",
-        "nuon" => "Nushell Object Notation",
-        "nut" => "Squirrel",
-        "njk" => "Nunjucks",
-        "odin" => "Odin",
-        "oscript" => "LiveLink OScript",
-        "bod" => "Oracle PL/SQL",
-        "bdy" => "Oracle PL/SQL",
-        "spc" => "Oracle PL/SQL",
-        "fnc" => "Oracle PL/SQL",
-        "prc" => "Oracle PL/SQL",
-        "trg" => "Oracle PL/SQL",
-        "p" => "Pascal/Pawn",
-        "pad" => "Ada",
-        "page" => "Visualforce Page",
-        "pas" => "Pascal",
-        "pcc" => "C++",
-        "rexfile" => "Perl",
-        "psgi" => "Perl",
-        "ph" => "Perl",
-        "makefile.pl" => "Perl",
-        "cpanfile" => "Perl",
-        "al" => "Perl",
-        "ack" => "Perl",
-        "perl" => "Perl",
-        "pfo" => "Fortran 77",
-        "pgc" => "C",
-        "phpt" => "PHP",
-        "phps" => "PHP",
-        "phakefile" => "PHP",
-        "ctp" => "PHP",
-        "aw" => "PHP",
-        "php_cs.dist" => "PHP",
-        "php_cs" => "PHP",
-        "php3" => "PHP",
-        "php4" => "PHP",
-        "php5" => "PHP",
-        "php" => "PHP",
-        "phtml" => "PHP",
-        "pig" => "Pig Latin",
-        "plh" => "Perl",
-        "pl" => "Perl/Prolog",
-        "PL" => "Perl/Prolog",
-        "p6" => "Raku/Prolog",
-        "P6" => "Raku/Prolog",
-        "plx" => "Perl",
-        "pm" => "Perl",
-        "pm6" => "Raku",
-        "raku" => "Raku",
-        "rakumod" => "Raku",
-        "pom.xml" => "Maven",
-        "pom" => "Maven",
-        "scad" => "OpenSCAD",
-        "yap" => "Prolog",
-        "prolog" => "Prolog",
-        "P" => "Prolog",
-        "pp" => "Pascal/Puppet",
-        "viw" => "SQL",
-        "udf" => "SQL",
-        "tab" => "SQL",
-        "mysql" => "SQL",
-        "cql" => "SQL",
-        "psql" => "SQL",
-        "xpy" => "Python",
-        "wsgi" => "Python",
-        "wscript" => "Python",
-        "workspace" => "Python",
-        "tac" => "Python",
-        "snakefile" => "Python",
-        "sconstruct" => "Python",
-        "sconscript" => "Python",
-        "pyt" => "Python",
-        "pyp" => "Python",
-        "pyi" => "Python",
-        "pyde" => "Python",
-        "py3" => "Python",
-        "lmi" => "Python",
-        "gypi" => "Python",
-        "gyp" => "Python",
-        "build.bazel" => "Python",
-        "buck" => "Python",
-        "gclient" => "Python",
-        "py" => "Python",
-        "pyw" => "Python",
-        "ipynb" => "Jupyter Notebook",
-        "pyj" => "RapydScript",
-        "pxi" => "Cython",
-        "pxd" => "Cython",
-        "pyx" => "Cython",
-        "qbs" => "QML",
-        "qml" => "QML",
-        "watchr" => "Ruby",
-        "vagrantfile" => "Ruby",
-        "thorfile" => "Ruby",
-        "thor" => "Ruby",
-        "snapfile" => "Ruby",
-        "ru" => "Ruby",
-        "rbx" => "Ruby",
-        "rbw" => "Ruby",
-        "rbuild" => "Ruby",
-        "rabl" => "Ruby",
-        "puppetfile" => "Ruby",
-        "podfile" => "Ruby",
-        "mspec" => "Ruby",
-        "mavenfile" => "Ruby",
-        "jbuilder" => "Ruby",
-        "jarfile" => "Ruby",
-        "guardfile" => "Ruby",
-        "god" => "Ruby",
-        "gemspec" => "Ruby",
-        "gemfile.lock" => "Ruby",
-        "gemfile" => "Ruby",
-        "fastfile" => "Ruby",
-        "eye" => "Ruby",
-        "deliverfile" => "Ruby",
-        "dangerfile" => "Ruby",
-        "capfile" => "Ruby",
-        "buildfile" => "Ruby",
-        "builder" => "Ruby",
-        "brewfile" => "Ruby",
-        "berksfile" => "Ruby",
-        "appraisals" => "Ruby",
-        "pryrc" => "Ruby",
-        "irbrc" => "Ruby",
-        "rb" => "Ruby",
-        "podspec" => "Ruby",
-        "rake" => "Ruby",
-
-        "rex" => "Oracle Reports",
-        "pprx" => "Rexx",
-        "rexx" => "Rexx",
-        "rhtml" => "Ruby HTML",
-        "circom" => "Circom",
-        "cairo" => "Cairo",
-        "rs.in" => "Rust",
-        "rs" => "Rust",
-        "rst.txt" => "reStructuredText",
-        "rest.txt" => "reStructuredText",
-        "rest" => "reStructuredText",
-        "rst" => "reStructuredText",
-        "s" => "Assembly",
-        "S" => "Assembly",
-        "SCA" => "Visual Fox Pro",
-        "sca" => "Visual Fox Pro",
-        "sbt" => "Scala",
-        "kojo" => "Scala",
-        "scala" => "Scala",
-        "sbl" => "Softbridge Basic",
-        "SBL" => "Softbridge Basic",
-        "sed" => "sed",
-        "sp" => "SparForte",
-        "sol" => "Solidity",
-        "p4" => "P4",
-        "ses" => "Patran Command Language",
-        "pcl" => "Patran Command Language",
-        "pwn" => "Pawn",
-        "pawn" => "Pawn",
-        "pek" => "Pek",
-        "peg" => "PEG",
-        "pegjs" => "peg.js",
-        "peggy" => "peggy",
-        "pest" => "Pest",
-        "pkl" => "Pkl",
-        "prisma" => "Prisma Schema",
-        "tspeg" => "tspeg",
-        "jspeg" => "tspeg",
-        "pl1" => "PL/I",
-        "plm" => "PL/M",
-        "lit" => "PL/M",
-        "iuml" => "PlantUML",
-        "pu" => "PlantUML",
-        "puml" => "PlantUML",
-        "plantuml" => "PlantUML",
-        "wsd" => "PlantUML",
-        "properties" => "Properties",
-        "po" => "PO File",
-        "pony" => "Pony",
-        "pbt" => "PowerBuilder",
-        "sra" => "PowerBuilder",
-        "srf" => "PowerBuilder",
-        "srm" => "PowerBuilder",
-        "srs" => "PowerBuilder",
-        "sru" => "PowerBuilder",
-        "srw" => "PowerBuilder",
-        "jade" => "Pug",
-        "pug" => "Pug",
-        "purs" => "PureScript",
-        "prefab" => "Unity-Prefab",
-        "proto" => "Protocol Buffers",
-        "mat" => "Unity-Prefab",
-        "ps1" => "PowerShell",
-        "psd1" => "PowerShell",
-        "psm1" => "PowerShell",
-        "prql" => "PRQL",
-        "rsx" => "R",
-        "rd" => "R",
-        "expr-dist" => "R",
-        "rprofile" => "R",
-        "R" => "R",
-        "r" => "R",
-        "raml" => "RAML",
-        "ring" => "Ring",
-        "rh" => "Ring",
-        "rform" => "Ring",
-        "rktd" => "Racket",
-        "rkt" => "Racket",
-        "rktl" => "Racket",
-        "Rmd" => "Rmd",
-        "re" => "ReasonML",
-        "rei" => "ReasonML",
-        "res" => "ReScript",
-        "resi" => "ReScript",
-        "scrbl" => "Racket",
-        "sps" => "Scheme",
-        "sc" => "Scheme",
-        "ss" => "Scheme",
-        "scm" => "Scheme",
-        "sch" => "Scheme",
-        "sls" => "Scheme/SaltStack",
-        "sld" => "Scheme",
-        "robot" => "RobotFramework",
-        "rc" => "Windows Resource File",
-        "rc2" => "Windows Resource File",
-        "sas" => "SAS",
-        "sass" => "Sass",
-        "scss" => "SCSS",
-        "sh" => "Bourne Shell",
-        "smarty" => "Smarty",
-        "sml" => "Standard ML",
-        "sig" => "Standard ML",
-        "fun" => "Standard ML",
-        "slim" => "Slim",
-        "e" => "Specman e",
-        "sql" => "SQL",
-        "SQL" => "SQL",
-        "sproc.sql" => "SQL Stored Procedure",
-        "spoc.sql" => "SQL Stored Procedure",
-        "spc.sql" => "SQL Stored Procedure",
-        "udf.sql" => "SQL Stored Procedure",
-        "data.sql" => "SQL Data",
-        "sss" => "SugarSS",
-        "slint" => "Slint",
-        "st" => "Smalltalk",
-        "rules" => "Snakemake",
-        "smk" => "Snakemake",
-        "styl" => "Stylus",
-        "surql" => "SurrealQL",
-        "i" => "SWIG",
-        "svelte" => "Svelte",
-        "sv" => "Verilog-SystemVerilog",
-        "svh" => "Verilog-SystemVerilog",
-        "svg" => "SVG",
-        "SVG" => "SVG",
-        "v" => "Verilog-SystemVerilog/Coq",
-        "td" => "TableGen",
-        "tcl" => "Tcl/Tk",
-        "tcsh" => "C Shell",
-        "tk" => "Tcl/Tk",
-        "teal" => "TEAL",
-        "templ" => "Templ",
-        "mkvi" => "TeX",
-        "mkiv" => "TeX",
-        "mkii" => "TeX",
-        "ltx" => "TeX",
-        "lbx" => "TeX",
-        "ins" => "TeX",
-        "cbx" => "TeX",
-        "bib" => "TeX",
-        "bbx" => "TeX",
-        "aux" => "TeX",
-        "tex" => "TeX",
-        "toml" => "TOML",
-        "sty" => "TeX",
-
-        "dtx" => "TeX",
-        "bst" => "TeX",
-        "txt" => "Text",
-        "text" => "Text",
-        "tres" => "Godot Resource",
-        "tscn" => "Godot Scene",
-        "thrift" => "Thrift",
-        "tla" => "TLA+",
-        "tpl" => "Smarty",
-        "trigger" => "Apex Trigger",
-        "ttcn" => "TTCN",
-        "ttcn2" => "TTCN",
-        "ttcn3" => "TTCN",
-        "ttcnpp" => "TTCN",
-        "sdl" => "TNSDL",
-        "ssc" => "TNSDL",
-        "sdt" => "TNSDL",
-        "spd" => "TNSDL",
-        "sst" => "TNSDL",
-        "rou" => "TNSDL",
-        "cin" => "TNSDL",
-        "cii" => "TNSDL",
-        "interface" => "TNSDL",
-        "in1" => "TNSDL",
-        "in2" => "TNSDL",
-        "in3" => "TNSDL",
-        "in4" => "TNSDL",
-        "inf" => "TNSDL",
-        "tpd" => "TITAN Project File Information",
-        "ts" => "TypeScript/Qt Linguist",
-        "cts" => "TypeScript",
-        "mts" => "TypeScript",
-        "tsx" => "TypeScript",
-        "tss" => "Titanium Style Sheet",
-        "twig" => "Twig",
-        "typ" => "Typst",
-        "um" => "Umka",
-        "uss" => "USS",
-        "uxml" => "UXML",
-        "ui" => "XML-Qt-GTK/Glade",
-        "glade" => "Glade",
-        "vala" => "Vala",
-        "vapi" => "Vala Header",
-        "vhw" => "VHDL",
-        "vht" => "VHDL",
-        "vhs" => "VHDL",
-        "vho" => "VHDL",
-        "vhi" => "VHDL",
-        "vhf" => "VHDL",
-        "vhd" => "VHDL",
-        "VHD" => "VHDL",
-        "vhdl" => "VHDL",
-        "VHDL" => "VHDL",
-        "bas" => "Visual Basic",
-        "BAS" => "Visual Basic",
-        "ctl" => "Visual Basic",
-        "dsr" => "Visual Basic",
-        "frm" => "Visual Basic",
-        "frx" => "Visual Basic",
-        "FRX" => "Visual Basic",
-        "vba" => "VB for Applications",
-        "VBA" => "VB for Applications",
-        "vbhtml" => "Visual Basic",
-        "VBHTML" => "Visual Basic",
-        "vbproj" => "Visual Basic .NET",
-        "vbp" => "Visual Basic",
-        "vbs" => "Visual Basic Script",
-        "VBS" => "Visual Basic Script",
-        "vb" => "Visual Basic .NET",
-        "VB" => "Visual Basic .NET",
-        "vbw" => "Visual Basic",
-        "vue" => "Vuejs Component",
-        "vy" => "Vyper",
-        "webinfo" => "ASP.NET",
-        "wsdl" => "Web Services Description",
-        "x" => "Logos",
-        "xm" => "Logos",
-        "xpo" => "X++",
-        "xmi" => "XMI",
-        "XMI" => "XMI",
-        "zcml" => "XML",
-        "xul" => "XML",
-        "xspec" => "XML",
-        "xproj" => "XML",
-        "xml.dist" => "XML",
-        "xliff" => "XML",
-        "xlf" => "XML",
-        "xib" => "XML",
-        "xacro" => "XML",
-        "x3d" => "XML",
-        "wsf" => "XML",
-        "web.release.config" => "XML",
-        "web.debug.config" => "XML",
-        "web.config" => "XML",
-        "wxml" => "WXML",
-        "wxss" => "WXSS",
-        "vxml" => "XML",
-        "vstemplate" => "XML",
-        "vssettings" => "XML",
-        "vsixmanifest" => "XML",
-        "vcxproj" => "XML",
-        "ux" => "XML",
-        "urdf" => "XML",
-        "tmtheme" => "XML",
-        "tmsnippet" => "XML",
-        "tmpreferences" => "XML",
-        "tmlanguage" => "XML",
-        "tml" => "XML",
-        "tmcommand" => "XML",
-        "targets" => "XML",
-        "sublime-snippet" => "XML",
-        "sttheme" => "XML",
-        "storyboard" => "XML",
-        "srdf" => "XML",
-        "shproj" => "XML",
-        "sfproj" => "XML",
-        "settings.stylecop" => "XML",
-        "scxml" => "XML",
-        "rss" => "XML",
-        "resx" => "XML",
-        "rdf" => "XML",
-        "pt" => "XML",
-        "psc1" => "XML",
-        "ps1xml" => "XML",
-        "props" => "XML",
-        "proj" => "XML",
-        "plist" => "XML",
-        "pkgproj" => "XML",
-        "packages.config" => "XML",
-        "osm" => "XML",
-        "odd" => "XML",
-        "nuspec" => "XML",
-        "nuget.config" => "XML",
-        "nproj" => "XML",
-        "ndproj" => "XML",
-        "natvis" => "XML",
-        "mjml" => "XML",
-        "mdpolicy" => "XML",
-        "launch" => "XML",
-        "kml" => "XML",
-        "jsproj" => "XML",
-        "jelly" => "XML",
-        "ivy" => "XML",
-        "iml" => "XML",
-        "grxml" => "XML",
-        "gmx" => "XML",
-        "fsproj" => "XML",
-        "filters" => "XML",
-        "dotsettings" => "XML",
-        "dll.config" => "XML",
-        "ditaval" => "XML",
-        "ditamap" => "XML",
-        "depproj" => "XML",
-        "ct" => "XML",
-        "csl" => "XML",
-        "csdef" => "XML",
-        "cscfg" => "XML",
-        "cproject" => "XML",
-        "clixml" => "XML",
-        "ccxml" => "XML",
-        "ccproj" => "XML",
-        "builds" => "XML",
-        "axml" => "XML",
-        "app.config" => "XML",
-        "ant" => "XML",
-        "admx" => "XML",
-        "adml" => "XML",
-        "project" => "XML",
-        "classpath" => "XML",
-        "xml" => "XML",
-        "XML" => "XML",
-        "mxml" => "MXML",
-        "xml.builder" => "builder",
-        "build" => "NAnt script",
-        "vim" => "vim script",
-        "swift" => "Swift",
-        "xaml" => "XAML",
-        "wast" => "WebAssembly",
-        "wat" => "WebAssembly",
-        "wgsl" => "WGSL",
-        "wxs" => "WiX source",
-        "wxi" => "WiX include",
-        "wxl" => "WiX string localization",
-        "prw" => "xBase",
-        "prg" => "xBase",
-        "ch" => "xBase Header",
-        "xqy" => "XQuery",
-        "xqm" => "XQuery",
-        "xql" => "XQuery",
-        "xq" => "XQuery",
-        "xquery" => "XQuery",
-        "xsd" => "XSD",
-        "XSD" => "XSD",
-        "xslt" => "XSLT",
-        "XSLT" => "XSLT",
-        "xsl" => "XSLT",
-        "XSL" => "XSLT",
-        "xtend" => "Xtend",
-        "yacc" => "yacc",
-        "y" => "yacc",
-        "yml.mysql" => "YAML",
-        "yaml-tmlanguage" => "YAML",
-        "syntax" => "YAML",
-        "sublime-syntax" => "YAML",
-        "rviz" => "YAML",
-        "reek" => "YAML",
-        "mir" => "YAML",
-        "glide.lock" => "YAML",
-        "gemrc" => "YAML",
-        "clang-tidy" => "YAML",
-        "clang-format" => "YAML",
-        "yaml" => "YAML",
-        "yml" => "YAML",
-        "yang" => "Yang",
-        "yarn" => "Yarn",
-        "zig" => "Zig",
-        "zsh" => "zsh",
-        "rego" => "Rego",
-        _ => "Others",
+/// Count in-memory text as if it were the file at `path`, e.g. piped stdin.
+/// `path` only labels the result and picks the language; nothing is read from disk.
+pub fn count_str<P: AsRef<Path>>(path: P, text: &str, opts: &Options) -> Result<FileCount> {
+    let encoder = encoder_for(opts)?;
+    Ok(file_count_for_text(path.as_ref(), text, &*encoder, opts))
+}
+
+/// Count several in-memory texts with one tokenizer, e.g. copy sections after rewriting.
+pub fn count_strs(items: &[(PathBuf, String)], opts: &Options) -> Result<Vec<FileCount>> {
+    let encoder = encoder_for(opts)?;
+    Ok(items
+        .iter()
+        .map(|(path, text)| file_count_for_text(path, text, &*encoder, opts))
+        .collect())
+}
+
+/// Token counts of one staged file before (HEAD) and after (index) the change.
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct StagedChange {
+    /// Path relative to the scanned directory
+    pub path: PathBuf,
+    pub before: usize,
+    pub after: usize,
+}
+
+impl StagedChange {
+    /// Net token change; negative when the file shrank or was deleted.
+    pub fn delta(&self) -> i64 {
+        self.after as i64 - self.before as i64
+    }
+}
+
+/// Count tokens of files staged under `root` (index vs HEAD), e.g. for a pre-commit hook.
+/// Honors the extension filter; binary or non-UTF-8 blobs count as zero tokens.
+pub fn count_staged<P: AsRef<Path>>(root: P, opts: &Options) -> Result<Vec<StagedChange>> {
+    let root = root.as_ref();
+    let encoder = encoder_for(opts)?;
+    // Before the first commit there is no HEAD; everything staged is an addition
+    let has_head = run_git(root, &["rev-parse", "--verify", "-q", "HEAD"]).is_ok();
+    let names = run_git(
+        root,
+        &[
+            "diff",
+            "--cached",
+            "--name-only",
+            "--no-renames",
+            "--relative",
+            "-z",
+        ],
+    )
+    .with_context(|| format!("failed to list staged changes in {}", root.display()))?;
+
+    let blob_tokens = |spec: String| -> usize {
+        run_git(root, &["cat-file", "blob", &spec])
+            .ok()
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+            .map_or(0, |text| tokens_for(&text, &*encoder, opts))
     };
-    let ans = if ans.contains('/') {
-        ans.split('/').next().unwrap_or(ans)
+    let mut changes = Vec::new();
+    for name in names.split(|b| *b == 0).filter(|n| !n.is_empty()) {
+        let name = String::from_utf8_lossy(name).into_owned();
+        let path = PathBuf::from(&name);
+        if !ext_allowed(&path, opts) {
+            continue;
+        }
+        // `:./` and `HEAD:./` resolve relative to `root` rather than the repository top
+        let before = if has_head {
+            blob_tokens(format!("HEAD:./{name}"))
+        } else {
+            0
+        };
+        let after = blob_tokens(format!(":./{name}"));
+        changes.push(StagedChange {
+            path,
+            before,
+            after,
+        });
+    }
+    Ok(changes)
+}
+
+/// Total tokens of the files under a directory as of one commit.
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct CommitCount {
+    pub commit: String,
+    /// Committer date, strict ISO 8601
+    pub date: String,
+    pub summary: String,
+    pub files: usize,
+    pub tokens: usize,
+}
+
+/// Count tokens under `root` at each of the last `last` first-parent commits of HEAD, oldest
+/// first. Blobs are read from git rather than the working tree, and each distinct blob is
+/// tokenized once however many commits contain it. Honors the hidden, extension, language
+/// and size filters; symlinks, submodules and binary or non-UTF-8 blobs are left out.
+pub fn count_history<P: AsRef<Path>>(
+    root: P,
+    last: usize,
+    opts: &Options,
+) -> Result<Vec<CommitCount>> {
+    let root = root.as_ref();
+    let encoder = encoder_for(opts)?;
+    let log = run_git(
+        root,
+        &[
+            "log",
+            "-z",
+            "--first-parent",
+            &format!("--max-count={last}"),
+            "--format=%H%x00%cI%x00%s",
+        ],
+    )
+    .with_context(|| format!("failed to read the history of {}", root.display()))?;
+    let log = log.strip_suffix(&[0]).unwrap_or(&log);
+    let fields: Vec<String> = if log.is_empty() {
+        Vec::new()
     } else {
-        ans
+        log.split(|b| *b == 0)
+            .map(|f| String::from_utf8_lossy(f).into_owned())
+            .collect()
     };
-    ans.to_string()
+
+    // Blob id -> tokens, or `None` for blobs that are not counted text
+    let mut blob_tokens: HashMap<String, Option<usize>> = HashMap::new();
+    let mut commits = Vec::new();
+    for entry in fields.chunks(3) {
+        let [commit, date, summary] = entry else {
+            anyhow::bail!("unexpected `git log` output");
+        };
+        // Run inside `root`, ls-tree lists only that directory, with paths relative to it
+        let tree = run_git(root, &["ls-tree", "-r", "-z", "--long", commit.as_str()])?;
+        let mut blobs = Vec::new();
+        for line in tree.split(|b| *b == 0).filter(|l| !l.is_empty()) {
+            let line = String::from_utf8_lossy(line);
+            // `<mode> <type> <object> <size>\t<path>`
+            let Some((meta, name)) = line.split_once('\t') else {
+                continue;
+            };
+            let mut meta = meta.split_whitespace();
+            let (Some(mode), Some("blob"), Some(oid), Some(size)) =
+                (meta.next(), meta.next(), meta.next(), meta.next())
+            else {
+                continue;
+            };
+            let path = Path::new(name);
+            let size: u64 = size.parse().unwrap_or(0);
+            if mode == "120000"
+                || (!opts.include_hidden && is_hidden_under(Path::new(""), path))
+                || opts.max_file_bytes.is_some_and(|max| size > max)
+                || opts.min_file_bytes.is_some_and(|min| size < min)
+                || !ext_allowed(path, opts)
+            {
+                continue;
+            }
+            blobs.push(oid.to_string());
+        }
+
+        let mut new: Vec<&String> = blobs
+            .iter()
+            .filter(|oid| !blob_tokens.contains_key(*oid))
+            .collect();
+        new.sort();
+        new.dedup();
+        let contents = read_blobs(root, &new)?;
+        let counted: Vec<Option<usize>> = contents
+            .into_par_iter()
+            .map(|bytes| {
+                let text = String::from_utf8(bytes).ok()?;
+                if !opts.include_binary && has_binary_chars(&text) {
+                    return None;
+                }
+                Some(tokens_for(&text, &*encoder, opts))
+            })
+            .collect();
+        for (oid, tokens) in new.into_iter().zip(counted) {
+            blob_tokens.insert(oid.clone(), tokens);
+        }
+
+        let counted: Vec<usize> = blobs.iter().filter_map(|oid| blob_tokens[oid]).collect();
+        commits.push(CommitCount {
+            commit: commit.clone(),
+            date: date.clone(),
+            summary: summary.clone(),
+            files: counted.len(),
+            tokens: counted.iter().sum(),
+        });
+    }
+    commits.reverse();
+    Ok(commits)
 }
 
-#[derive(Debug, Serialize, Clone)]
-pub struct LangSummary {
-    pub language: String,
-    pub lines: usize,
-    pub tokens: usize,
+/// Contents of the given blobs, in order, through one `git cat-file --batch` process.
+fn read_blobs(dir: &Path, oids: &[&String]) -> Result<Vec<Vec<u8>>> {
+    use std::io::{BufRead, BufReader, Read as _, Write as _};
+    use std::process::{Command, Stdio};
+
+    if oids.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut child = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["cat-file", "--batch"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .context("failed to run git")?;
+    // Feed ids from another thread so a full stdout pipe cannot block the writer
+    let mut stdin = child.stdin.take().expect("piped stdin");
+    let input: String = oids.iter().map(|oid| format!("{oid}\n")).collect();
+    let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+
+    let mut reader = BufReader::new(child.stdout.take().expect("piped stdout"));
+    let mut blobs = Vec::with_capacity(oids.len());
+    for oid in oids {
+        // `<object> <type> <size>\n<contents>\n`, or `<object> missing\n`
+        let mut header = String::new();
+        reader.read_line(&mut header)?;
+        let size = header
+            .split_whitespace()
+            .nth(2)
+            .and_then(|n| n.parse::<usize>().ok())
+            .with_context(|| format!("failed to read blob {oid}: {}", header.trim()))?;
+        let mut contents = vec![0; size + 1];
+        reader.read_exact(&mut contents)?;
+        contents.pop();
+        blobs.push(contents);
+    }
+    writer.join().expect("stdin writer panicked")?;
+    child.wait()?;
+    Ok(blobs)
 }
 
-pub fn aggregate_by_language(files: &[FileCount]) -> Vec<LangSummary> {
-    use std::collections::BTreeMap;
-    let mut map: BTreeMap<String, (usize, usize)> = BTreeMap::new();
-    for f in files {
-        let lang = language_from_path(&f.path);
-        let entry = map.entry(lang).or_insert((0, 0));
-        entry.0 += f.lines;
-        entry.1 += f.tokens;
+// Bump whenever the meaning of a cached field changes (v3: lone `\r` ends a line,
+// v4: line lengths)
+const CACHE_VERSION: u32 = 5;
+
+fn default_true() -> bool {
+    true
+}
+
+/// Modification time and size used to decide whether a cached count is still valid.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+struct FileStamp {
+    mtime_ns: u64,
+    size: u64,
+}
+
+impl FileStamp {
+    fn of(path: &Path) -> Option<Self> {
+        let meta = fs::metadata(path).ok()?;
+        let mtime = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        Some(Self {
+            mtime_ns: mtime.as_nanos() as u64,
+            size: meta.len(),
+        })
     }
-    let mut v: Vec<LangSummary> = map
-        .into_iter()
-        .map(|(language, (lines, tokens))| LangSummary {
-            language,
-            lines,
-            tokens,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    #[serde(flatten)]
+    stamp: FileStamp,
+    tokens: usize,
+    lines: usize,
+    total_lines: usize,
+    blank_lines: usize,
+    language: String,
+    words: usize,
+    chars: usize,
+    max_line_len: usize,
+    long_lines: usize,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct CountCache {
+    version: u32,
+    encoding: String,
+    #[serde(default)]
+    encoding_file: Option<PathBuf>,
+    #[serde(default)]
+    hf_tokenizer: Option<PathBuf>,
+    // Languages differ when content sniffing is on, so entries are only valid for the same mode
+    #[serde(default)]
+    smart_language: bool,
+    // Chunking shifts token counts slightly
+    #[serde(default)]
+    chunk_bytes: Option<usize>,
+    #[serde(default)]
+    estimate: bool,
+    #[serde(default)]
+    long_line_threshold: usize,
+    // Suspected binary files are only cached when they were counted
+    #[serde(default)]
+    include_binary: bool,
+    #[serde(default)]
+    sample_bytes: Option<usize>,
+    // Entries from a scan without line counts have `lines` of 0
+    #[serde(default = "default_true")]
+    count_lines: bool,
+    #[serde(default = "default_true")]
+    allow_special: bool,
+    #[serde(default)]
+    normalize_whitespace: bool,
+    // Files outside the size bounds are skipped before the cache is consulted
+    #[serde(default)]
+    min_file_bytes: Option<u64>,
+    #[serde(default)]
+    max_file_bytes: Option<u64>,
+    entries: HashMap<String, CacheEntry>,
+}
+
+impl CountCache {
+    /// Load the cache for `opts`; a missing, corrupt, or mismatched file yields an empty cache.
+    fn load(path: &Path, opts: &Options) -> Self {
+        fs::read(path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<CountCache>(&bytes).ok())
+            .filter(|c| {
+                c.version == CACHE_VERSION
+                    && c.encoding == opts.encoding
+                    && c.encoding_file == opts.encoding_file
+                    && c.hf_tokenizer == opts.hf_tokenizer
+                    && c.smart_language == opts.smart_language
+                    && c.chunk_bytes == opts.chunk_bytes
+                    && c.estimate == opts.estimate
+                    && c.long_line_threshold == opts.long_line_threshold
+                    && c.include_binary == opts.include_binary
+                    && c.sample_bytes == opts.sample_bytes
+                    && c.count_lines == opts.count_lines
+                    && c.allow_special == opts.allow_special
+                    && c.normalize_whitespace == opts.normalize_whitespace
+                    && c.min_file_bytes == opts.min_file_bytes
+                    && c.max_file_bytes == opts.max_file_bytes
+            })
+            .unwrap_or_default()
+    }
+
+    fn get(&self, path: &Path, stamp: FileStamp) -> Option<FileCount> {
+        let entry = self.entries.get(path.to_string_lossy().as_ref())?;
+        (entry.stamp == stamp).then(|| FileCount {
+            path: path.to_path_buf(),
+            tokens: entry.tokens,
+            lines: entry.lines,
+            total_lines: entry.total_lines,
+            blank_lines: entry.blank_lines,
+            language: entry.language.clone(),
+            bytes: stamp.size,
+            words: entry.words,
+            chars: entry.chars,
+            max_line_len: entry.max_line_len,
+            long_lines: entry.long_lines,
+            sampled: self.sample_bytes.is_some_and(|n| stamp.size > n as u64),
         })
-        .collect();
-    // Sort by token count desc
-    v.sort_by(|a, b| b.tokens.cmp(&a.tokens));
-    v
+    }
+
+    /// Replace the cache with exactly `files`, dropping entries for files that no longer exist.
+    fn save<'a>(
+        path: &Path,
+        opts: &Options,
+        files: impl Iterator<Item = (&'a FileCount, FileStamp)>,
+    ) -> Result<()> {
+        let cache = CountCache {
+            version: CACHE_VERSION,
+            encoding: opts.encoding.clone(),
+            encoding_file: opts.encoding_file.clone(),
+            hf_tokenizer: opts.hf_tokenizer.clone(),
+            smart_language: opts.smart_language,
+            chunk_bytes: opts.chunk_bytes,
+            estimate: opts.estimate,
+            long_line_threshold: opts.long_line_threshold,
+            include_binary: opts.include_binary,
+            sample_bytes: opts.sample_bytes,
+            count_lines: opts.count_lines,
+            allow_special: opts.allow_special,
+            normalize_whitespace: opts.normalize_whitespace,
+            min_file_bytes: opts.min_file_bytes,
+            max_file_bytes: opts.max_file_bytes,
+            entries: files
+                .map(|(f, stamp)| {
+                    let entry = CacheEntry {
+                        stamp,
+                        tokens: f.tokens,
+                        lines: f.lines,
+                        total_lines: f.total_lines,
+                        blank_lines: f.blank_lines,
+                        language: f.language.clone(),
+                        words: f.words,
+                        chars: f.chars,
+                        max_line_len: f.max_line_len,
+                        long_lines: f.long_lines,
+                    };
+                    (f.path.to_string_lossy().into_owned(), entry)
+                })
+                .collect(),
+        };
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).context("failed to create cache directory")?;
+        }
+        // Write then rename so a concurrent run never sees a half-written cache
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_vec(&cache)?).context("failed to write cache")?;
+        fs::rename(&tmp, path).context("failed to replace cache")?;
+        Ok(())
+    }
 }
 
-pub fn count_tokens_in_path<P: AsRef<Path>>(root: P, opts: &Options) -> Result<CountResult> {
-    count_tokens_in_path_with_progress::<P, fn(usize, usize)>(root, opts, None)
+/// Default cache location for scans of `root`: one file per root under the user cache dir
+/// (`$XDG_CACHE_HOME`, `~/.cache`, or `%LOCALAPPDATA%`). `None` if no cache dir is known.
+pub fn default_cache_path(root: &Path) -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("LOCALAPPDATA").map(PathBuf::from))
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".cache")))?;
+    let root = fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
+    // FNV-1a: stable across builds, unlike `DefaultHasher`
+    let hash = root
+        .to_string_lossy()
+        .bytes()
+        .fold(0xcbf29ce484222325u64, |h, b| {
+            (h ^ b as u64).wrapping_mul(0x100000001b3)
+        });
+    Some(base.join("loctok").join(format!("{hash:016x}.json")))
 }
 
-/// Like `count_tokens_in_path`, but reports progress via the provided callback.
-/// The callback receives `(processed_files, total_files)`.
-pub fn count_tokens_in_path_with_progress<P, F>(
-    root: P,
+/// Shared scan: count every filtered file in parallel, optionally keeping its text and
+/// handing each kept file to `on_file` as soon as it is counted.
+fn scan<F>(
+    root: &Path,
     opts: &Options,
     progress: Option<&F>,
-) -> Result<CountResult>
+    keep_texts: bool,
+    on_file: Option<&(dyn Fn(&FileCount) + Sync)>,
+) -> Result<(CountResult, Vec<(PathBuf, String)>)>
 where
-    P: AsRef<Path>,
     F: Fn(usize, usize) + Send + Sync,
 {
-    // Validate encoder early; per-thread encoders will be created below
-    let _ = get_encoder(&opts.encoding)?;
-
-    // Collect file paths first (sequential, cheap), then process in parallel
-    let paths: Vec<PathBuf> = enumerate_filtered_paths(&root, opts);
+    let ScanSetup {
+        encoder,
+        opts,
+        skipped,
+        paths,
+        cache,
+    } = prepare_scan(root, opts)?;
+    let opts = &opts;
     let total_files = paths.len();
     if let Some(cb) = progress {
         cb(0, total_files);
     }
 
     let processed = AtomicUsize::new(0);
-    let encoding = opts.encoding.clone();
-
-    // Simple encoder pool backed by a mutex-protected stack.
-    struct EncoderPool {
-        encoding: String,
-        inners: Mutex<Vec<CoreBPE>>,
-    }
-    impl EncoderPool {
-        fn new(encoding: String) -> Self {
-            Self {
-                encoding,
-                inners: Mutex::new(Vec::new()),
-            }
-        }
-        fn take(&self) -> CoreBPE {
-            if let Some(enc) = self.inners.lock().unwrap().pop() {
-                return enc;
-            }
-            // Create a new one if pool empty
-            get_encoder(&self.encoding).expect("Failed to init encoder")
-        }
-        fn give(&self, enc: CoreBPE) {
-            self.inners.lock().unwrap().push(enc);
-        }
-    }
 
-    let pool = Arc::new(EncoderPool::new(encoding.clone()));
-
-    let files: Vec<FileCount> = paths
+    let scanned: Vec<(FileCount, Option<String>, Option<FileStamp>)> = paths
         .par_iter()
         .filter_map(|path| {
-            // Skip files larger than 100MB
-            let metadata = match fs::metadata(path) {
-                Ok(m) => m,
-                Err(err) => {
-                    eprintln!("warn: failed to get metadata for {}: {err}", path.display());
-                    return None;
+            let stamp = opts.cache_path.as_ref().and_then(|_| FileStamp::of(path));
+            let cached = stamp.and_then(|st| cache.get(path, st));
+
+            let (file, text) = match cached {
+                // Cache hit and the text itself is not needed: skip reading entirely
+                Some(file) if !keep_texts => (file, None),
+                Some(file) => (file, Some(read_text_file(path, opts, None)?.into_string())),
+                None => {
+                    // Kept texts must be whole; the sample is then cut from the full text
+                    let limit = if keep_texts { None } else { opts.sample_bytes };
+                    let text = read_text_file(path, opts, limit)?;
+                    let file = count_text_file(path, &text, &*encoder, opts)?;
+                    (file, keep_texts.then(|| text.into_string()))
                 }
             };
-            if metadata.len() > 64 * 1024 * 1024 {
-                eprintln!(
-                    "warn: skipping large file ({}MB): {}",
-                    metadata.len() / 1024 / 1024,
-                    path.display()
-                );
-                return None;
-            }
-            let bytes = match fs::read(path) {
-                Ok(b) => b,
-                Err(err) => {
-                    eprintln!("warn: failed to read {}: {err}", path.display());
-                    return None;
+            if let Some(send) = on_file {
+                if !(opts.exclude_empty && is_blank(&file, opts)) {
+                    send(&file);
                 }
-            };
-            let byte_len = bytes.len();
-            let Ok(text) = String::from_utf8(bytes) else {
-                return None;
-            };
-
-            let enc = pool.take();
-            let tokens = count_tokens_in_text(&enc, &text);
-            pool.give(enc);
-            let lines = count_non_empty_lines(&text);
+            }
 
-            let res = Some(FileCount {
-                path: path.clone(),
-                tokens,
-                lines,
-            });
+            let res = Some((file, text, stamp));
             let done = processed.fetch_add(1, Ordering::Relaxed) + 1;
             if let Some(cb) = progress {
                 cb(done, total_files);
@@ -1314,9 +3586,116 @@ where
         })
         .collect();
 
+    if let Some(cache_path) = &opts.cache_path {
+        let entries = scanned
+            .iter()
+            .filter_map(|(file, _, stamp)| Some((file, (*stamp)?)));
+        if let Err(err) = CountCache::save(cache_path, opts, entries) {
+            warn(
+                opts,
+                Warning::Cache {
+                    path: cache_path.clone(),
+                    message: format!("{err:#}"),
+                },
+            );
+        }
+    }
+
+    let mut files = Vec::with_capacity(scanned.len());
+    let mut texts = Vec::new();
+    let mut empty = Vec::new();
+    for (file, text, _) in scanned {
+        if opts.exclude_empty && is_blank(&file, opts) {
+            // Recorded as skipped without going through `warn`: one stderr line per
+            // empty file would drown out real problems
+            empty.push(Warning::Empty { path: file.path });
+            continue;
+        }
+        if let Some(text) = text {
+            let rel = file.path.strip_prefix(root).unwrap_or(&file.path);
+            texts.push((rel.to_path_buf(), text));
+        }
+        files.push(file);
+    }
+    // Sort by path for deterministic output; rayon keeps the sorted walk order already,
+    // but the API promises it regardless of how the scan is parallelized
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    texts.sort_by(|a, b| a.0.cmp(&b.0));
+
     let total: usize = files.iter().map(|f| f.tokens).sum();
+    Ok((
+        CountResult {
+            total,
+            files,
+            skipped: sorted_skips(&skipped, empty),
+        },
+        texts,
+    ))
+}
+
+/// Everything a scan needs before it walks the files.
+struct ScanSetup {
+    encoder: Box<dyn Tokenizer>,
+    // The caller's options with a warning handler that also records skips
+    opts: Options,
+    skipped: Arc<Mutex<Vec<Warning>>>,
+    paths: Vec<PathBuf>,
+    cache: CountCache,
+}
+
+fn prepare_scan(root: &Path, opts: &Options) -> Result<ScanSetup> {
+    // Load the encoder before walking so a bad encoding fails fast; threads share it
+    let encoder = encoder_for(opts)?;
+
+    // Record skips for the caller while still reporting them through the usual handler
+    let skipped: Arc<Mutex<Vec<Warning>>> = Arc::default();
+    let opts = {
+        let inner = opts.clone();
+        let sink = Arc::clone(&skipped);
+        Options {
+            on_warning: Some(WarningCallback::new(move |w: &Warning| {
+                if w.is_skip() {
+                    sink.lock().unwrap().push(w.clone());
+                }
+                warn(&inner, w.clone());
+            })),
+            ..opts.clone()
+        }
+    };
+
+    // Collect file paths first (sequential, cheap), then process in parallel
+    let (mut paths, linguist_skips) = filtered_paths_and_skips(root, &opts)?;
+    skipped.lock().unwrap().extend(linguist_skips);
+    // Never count our own cache file when it lives inside the scanned tree
+    if let Some(cache) = opts
+        .cache_path
+        .as_ref()
+        .and_then(|p| fs::canonicalize(p).ok())
+    {
+        paths.retain(|p| fs::canonicalize(p).map_or(true, |c| c != cache));
+    }
+
+    let cache = opts
+        .cache_path
+        .as_deref()
+        .map(|p| CountCache::load(p, &opts))
+        .unwrap_or_default();
+    Ok(ScanSetup {
+        encoder,
+        opts,
+        skipped,
+        paths,
+        cache,
+    })
+}
 
-    Ok(CountResult { total, files })
+/// Skips recorded during a scan plus `empty`, sorted by path.
+fn sorted_skips(recorded: &Mutex<Vec<Warning>>, empty: Vec<Warning>) -> Vec<Warning> {
+    let mut skipped = std::mem::take(&mut *recorded.lock().unwrap());
+    skipped.extend(empty);
+    // Warnings arrive from worker threads in arbitrary order
+    skipped.sort_by(|a, b| a.path().cmp(&b.path()));
+    skipped
 }
 
 /// Step 1: Extract filtered relative file paths and their UTF-8 content.
@@ -1326,28 +3705,109 @@ pub fn collect_filtered_texts<P: AsRef<Path>>(
     opts: &Options,
 ) -> Result<Vec<(PathBuf, String)>> {
     let root_ref = root.as_ref();
-    let mut rel_and_text: Vec<(PathBuf, String)> = Vec::new();
-    let mut paths = enumerate_filtered_paths(root_ref, opts);
+    let mut paths = filtered_paths(root_ref, opts)?;
     // Sort by relative path for deterministic output
     paths.sort();
-    for abs in paths {
-        let rel = abs.strip_prefix(root_ref).unwrap_or(&abs).to_path_buf();
-        let Ok(bytes) = fs::read(&abs) else { continue };
-        let Ok(text) = String::from_utf8(bytes) else {
-            continue;
-        };
-        rel_and_text.push((rel, text));
-    }
+    let rel_and_text: Vec<(PathBuf, String)> = paths
+        .par_iter()
+        .filter_map(|abs| {
+            let text = read_text_file(abs, opts, None)?;
+            let rel = abs.strip_prefix(root_ref).unwrap_or(abs).to_path_buf();
+            Some((rel, text.into_string()))
+        })
+        .collect();
     Ok(rel_and_text)
 }
 
+/// How `build_copy_output_as` lays out each file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CopyFormat {
+    /// `/<path>:` between dashed lines, then numbered content lines
+    #[default]
+    Default,
+    /// `<document index="N"><source>…</source><document_contents>…</document_contents></document>`
+    /// inside `<documents>`, the structure Anthropic recommends for long-context prompts
+    Xml,
+    /// `### <path>` followed by the content in a fenced code block tagged with its language
+    Markdown,
+}
+
+/// Info string for a Markdown code fence showing `language` (as named by `language_from_path`),
+/// e.g. "rust" for Rust; empty when there is no sensible highlighter name.
+fn fence_info(language: &str) -> String {
+    let special = match language {
+        "C++" => "cpp",
+        "C#" => "csharp",
+        "F#" => "fsharp",
+        "Assembly" => "asm",
+        "Bourne Again Shell" => "bash",
+        "Bourne Shell" | "Korn Shell" => "sh",
+        "Fish Shell" => "fish",
+        "DOS Batch" => "bat",
+        "Objective-C" | "Objective-C++" => "objectivec",
+        "Protocol Buffers" => "protobuf",
+        "Standard ML" => "sml",
+        "Verilog-SystemVerilog" => "verilog",
+        "Visual Basic .NET" => "vbnet",
+        "Vuejs Component" => "vue",
+        "Jupyter Notebook" => "json",
+        "vim script" => "vim",
+        "make" => "makefile",
+        "Others" => "",
+        _ if language
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-') =>
+        {
+            return language.to_ascii_lowercase();
+        }
+        // Ambiguous groupings such as "Perl/Prolog" or multi-word names
+        _ => "",
+    };
+    special.to_string()
+}
+
 /// Step 2: Build final output from relative paths and content collected in step 1.
 /// Format:
 ///  - file tree header using ├──/└── and │/    guides
 ///  - blank line
 ///  - sections per file: `/<path>:` + dashed line + numbered content lines
-pub fn build_copy_output(_root: &Path, rel_and_texts: &[(PathBuf, String)]) -> String {
-    use std::collections::BTreeMap;
+pub fn build_copy_output(root: &Path, rel_and_texts: &[(PathBuf, String)]) -> String {
+    build_copy_output_as(root, rel_and_texts, &CopyStyle::default())
+}
+
+/// Layout choices for `build_copy_output_as`.
+#[derive(Debug, Clone)]
+pub struct CopyStyle {
+    pub format: CopyFormat,
+    // Render the file tree header
+    pub tree: bool,
+    // Prepended to every displayed path and placed at the top of the tree, e.g. the repo name
+    pub path_prefix: Option<String>,
+    // Token counts by the same relative paths as the texts; when set, each tree line ends with
+    // the file's tokens or the directory's subtotal
+    pub tree_tokens: Option<HashMap<PathBuf, usize>>,
+    // Thousands separators for those counts
+    pub locale: Locale,
+}
+
+impl Default for CopyStyle {
+    fn default() -> Self {
+        Self {
+            format: CopyFormat::Default,
+            tree: true,
+            path_prefix: None,
+            tree_tokens: None,
+            locale: Locale::en,
+        }
+    }
+}
+
+/// Like `build_copy_output`, with a choice of per-file layout, tree header and path prefix.
+pub fn build_copy_output_as(
+    _root: &Path,
+    rel_and_texts: &[(PathBuf, String)],
+    style: &CopyStyle,
+) -> String {
     use std::fmt::Write as _;
 
     // Normalize path to unix-style with '/'
@@ -1361,36 +3821,73 @@ pub fn build_copy_output(_root: &Path, rel_and_texts: &[(PathBuf, String)]) -> S
     #[derive(Default)]
     struct DirNode {
         dirs: BTreeMap<String, DirNode>,
-        files: Vec<String>,
+        files: Vec<(String, usize)>,
+        // Tokens of every file below, when `style.tree_tokens` is set
+        tokens: usize,
     }
 
+    // Looked up by the paths as given, before `path_prefix` is joined on
+    let file_tokens: Vec<usize> = rel_and_texts
+        .iter()
+        .map(|(rel, _)| {
+            style
+                .tree_tokens
+                .as_ref()
+                .and_then(|t| t.get(rel).copied())
+                .unwrap_or(0)
+        })
+        .collect();
+    let prefix = style
+        .path_prefix
+        .as_deref()
+        .map(|p| p.trim_matches('/'))
+        .filter(|p| !p.is_empty());
+    let rel_and_texts: Vec<(PathBuf, &String)> = rel_and_texts
+        .iter()
+        .map(|(rel, text)| match prefix {
+            Some(prefix) => (Path::new(prefix).join(rel), text),
+            None => (rel.clone(), text),
+        })
+        .collect();
+
     let mut root_node = DirNode::default();
-    let mut rel_paths: Vec<PathBuf> = rel_and_texts.iter().map(|(p, _)| p.clone()).collect();
+    let mut rel_paths: Vec<(PathBuf, usize)> = rel_and_texts
+        .iter()
+        .zip(file_tokens)
+        .map(|((p, _), tokens)| (p.clone(), tokens))
+        .collect();
     rel_paths.sort();
-    for rel in &rel_paths {
+    for (rel, tokens) in &rel_paths {
         let mut cur = &mut root_node;
+        cur.tokens += tokens;
         let mut comps = rel.components().peekable();
         while let Some(comp) = comps.next() {
             let name = comp.as_os_str().to_string_lossy().to_string();
             let is_last = comps.peek().is_none();
             if is_last {
-                cur.files.push(name);
+                cur.files.push((name, *tokens));
             } else {
                 cur = cur.dirs.entry(name).or_default();
+                cur.tokens += tokens;
             }
         }
     }
 
-    fn render_dir(node: &DirNode, prefix: &str, out: &mut String) {
+    fn render_dir(node: &DirNode, prefix: &str, annotate: Option<&Locale>, out: &mut String) {
         // Order: directories first, then files; both lexicographically
         let mut dir_names: Vec<_> = node.dirs.keys().cloned().collect();
         dir_names.sort();
         let mut file_names = node.files.clone();
         file_names.sort();
+        let label = |name: &str, tokens: usize| match annotate {
+            Some(_) if tokens == 1 => format!("{name} (1 token)"),
+            Some(locale) => format!("{name} ({} tokens)", tokens.to_formatted_string(locale)),
+            None => name.to_string(),
+        };
 
         enum Entry {
             Dir(String),
-            File(String),
+            File((String, usize)),
         }
         let mut entries: Vec<Entry> = Vec::new();
         for d in &dir_names {
@@ -1409,25 +3906,63 @@ pub fn build_copy_output(_root: &Path, rel_and_texts: &[(PathBuf, String)]) -> S
             };
             match e {
                 Entry::Dir(name) => {
-                    let _ = writeln!(out, "{}{}{}", prefix, branch, name);
-                    if let Some(child) = node.dirs.get(&name) {
-                        render_dir(child, &next_prefix, out);
-                    }
+                    let child = &node.dirs[&name];
+                    let _ = writeln!(out, "{}{}{}", prefix, branch, label(&name, child.tokens));
+                    render_dir(child, &next_prefix, annotate, out);
                 }
-                Entry::File(name) => {
-                    let _ = writeln!(out, "{}{}{}", prefix, branch, name);
+                Entry::File((name, tokens)) => {
+                    let _ = writeln!(out, "{}{}{}", prefix, branch, label(&name, tokens));
                 }
             }
         }
     }
 
     let mut s = String::new();
-    render_dir(&root_node, "", &mut s);
+    if style.tree {
+        let annotate = style.tree_tokens.as_ref().map(|_| &style.locale);
+        render_dir(&root_node, "", annotate, &mut s);
+    }
     if !s.is_empty() {
-        s.push_str("\n");
+        s.push('\n');
+    }
+
+    match style.format {
+        CopyFormat::Default => {}
+        CopyFormat::Xml => {
+            s.push_str("<documents>\n");
+            for (i, (rel, text)) in rel_and_texts.iter().enumerate() {
+                let _ = writeln!(s, "<document index=\"{}\">", i + 1);
+                let _ = writeln!(s, "<source>{}</source>", path_to_unix_string(rel));
+                s.push_str("<document_contents>\n");
+                s.push_str(text);
+                if !text.is_empty() && !text.ends_with('\n') {
+                    s.push('\n');
+                }
+                s.push_str("</document_contents>\n</document>\n");
+            }
+            s.push_str("</documents>\n");
+            return s;
+        }
+        CopyFormat::Markdown => {
+            for (rel, text) in &rel_and_texts {
+                // A fence longer than any backtick run inside the file cannot be closed early,
+                // so ``` in the content needs no escaping and is pasted verbatim
+                let longest_run = text.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+                let fence = "`".repeat(longest_run.max(2) + 1);
+                let lang = fence_info(&language_from_path(rel));
+                let _ = writeln!(s, "### {}\n", path_to_unix_string(rel));
+                let _ = writeln!(s, "{fence}{lang}");
+                s.push_str(text);
+                if !text.is_empty() && !text.ends_with('\n') {
+                    s.push('\n');
+                }
+                let _ = writeln!(s, "{fence}\n");
+            }
+            return s;
+        }
     }
 
-    for (rel, text) in rel_and_texts {
+    for (rel, text) in &rel_and_texts {
         let path_unix = path_to_unix_string(rel);
         s.push_str(
             "--------------------------------------------------------------------------------\n",
@@ -1443,12 +3978,420 @@ pub fn build_copy_output(_root: &Path, rel_and_texts: &[(PathBuf, String)]) -> S
                 let _ = writeln!(s, "{} | {}", i + 1, line);
             }
         }
+        // `lines()` renders "a" and "a\n" alike; flag the missing newline like `git diff`
+        if !text.is_empty() && !text.ends_with('\n') {
+            s.push_str("\\ No newline at end of file\n");
+        }
         s.push_str("\n\n");
     }
 
     s
 }
 
+/// Keep the first `head` and last `tail` lines of `text`, replacing the rest with a
+/// `… (truncated, N more lines)` line. Returns `None` when nothing would be cut.
+pub fn truncate_lines(text: &str, head: usize, tail: usize) -> Option<String> {
+    let lines: Vec<&str> = text.split_inclusive('\n').collect();
+    if lines.len() <= head + tail {
+        return None;
+    }
+    let omitted = lines.len() - head - tail;
+    let mut out: String = lines[..head].concat();
+    if !out.is_empty() && !out.ends_with('\n') {
+        out.push('\n');
+    }
+    out.push_str(&format!("… (truncated, {omitted} more lines)\n"));
+    out.push_str(&lines[lines.len() - tail..].concat());
+    Some(out)
+}
+
+// ----- Comments -----
+
+/// How comments and string literals are written in a language, as far as stripping needs.
+#[derive(Debug, Clone, Copy)]
+pub struct CommentSyntax {
+    pub line: &'static [&'static str],
+    pub block: Option<(&'static str, &'static str)>,
+    // Block comments may contain further block comments (Rust, Swift, Haskell, ...)
+    pub nested: bool,
+    // String delimiters as (open, close); longer openers must come first
+    pub strings: &'static [(&'static str, &'static str)],
+    // `'x'` is a character literal while a lone `'` is not (lifetimes, primes)
+    pub char_literals: bool,
+    // Line markers only count at the start of a word, e.g. `#` in shell but not in `$#`
+    pub line_at_word_start: bool,
+}
+
+/// Comment syntax for a language as named by `language_from_path`; `None` when unknown.
+pub fn comment_syntax(language: &str) -> Option<CommentSyntax> {
+    const DQ: &[(&str, &str)] = &[("\"", "\"")];
+    const DQ_SQ: &[(&str, &str)] = &[("\"", "\""), ("'", "'")];
+    const JS: &[(&str, &str)] = &[("\"", "\""), ("'", "'"), ("`", "`")];
+    const GO: &[(&str, &str)] = &[("\"", "\""), ("`", "`")];
+    const TRIPLE_DQ: &[(&str, &str)] = &[("\"\"\"", "\"\"\""), ("\"", "\"")];
+    const TRIPLE_DQ_SQ: &[(&str, &str)] = &[
+        ("\"\"\"", "\"\"\""),
+        ("'''", "'''"),
+        ("\"", "\""),
+        ("'", "'"),
+    ];
+    const LUA: &[(&str, &str)] = &[("[[", "]]"), ("\"", "\""), ("'", "'")];
+
+    let c_like = |strings, nested, char_literals| CommentSyntax {
+        line: &["//"],
+        block: Some(("/*", "*/")),
+        nested,
+        strings,
+        char_literals,
+        line_at_word_start: false,
+    };
+    let hash = |strings| CommentSyntax {
+        line: &["#"],
+        block: None,
+        nested: false,
+        strings,
+        char_literals: false,
+        line_at_word_start: true,
+    };
+    let syntax = match language {
+        "Rust" => c_like(DQ, true, true),
+        "C" | "C++" | "CUDA" | "Objective-C++" | "Java" | "C#" | "GLSL" | "Protocol Buffers"
+        | "Solidity" => c_like(DQ, false, true),
+        "Go" => c_like(GO, false, true),
+        "JavaScript" | "TypeScript" | "JSX" => c_like(JS, false, false),
+        "Kotlin" | "Scala" | "Swift" => c_like(TRIPLE_DQ, true, true),
+        "Dart" => c_like(TRIPLE_DQ_SQ, true, false),
+        "Groovy" | "PHP" => c_like(DQ_SQ, false, false),
+        "Zig" => CommentSyntax {
+            block: None,
+            ..c_like(DQ, false, true)
+        },
+        "CSS" => CommentSyntax {
+            line: &[],
+            ..c_like(DQ_SQ, false, false)
+        },
+        "SCSS" | "LESS" => c_like(DQ_SQ, false, false),
+        "Python" | "TOML" => hash(TRIPLE_DQ_SQ),
+        "Ruby" | "Perl" | "R" | "YAML" | "Elixir" | "Nim" | "CMake" | "Starlark" | "make"
+        | "Dockerfile" | "Bourne Shell" | "Bourne Again Shell" | "Korn Shell" | "zsh"
+        | "Fish Shell" => hash(DQ_SQ),
+        "SQL" => CommentSyntax {
+            line: &["--"],
+            block: Some(("/*", "*/")),
+            nested: false,
+            strings: DQ_SQ,
+            char_literals: false,
+            line_at_word_start: false,
+        },
+        "Lua" => CommentSyntax {
+            line: &["--"],
+            block: Some(("--[[", "]]")),
+            nested: false,
+            strings: LUA,
+            char_literals: false,
+            line_at_word_start: false,
+        },
+        "Haskell" | "Elm" => CommentSyntax {
+            line: &["--"],
+            block: Some(("{-", "-}")),
+            nested: true,
+            strings: DQ,
+            char_literals: true,
+            line_at_word_start: false,
+        },
+        "HTML" | "XML" => CommentSyntax {
+            line: &[],
+            block: Some(("<!--", "-->")),
+            nested: false,
+            strings: &[],
+            char_literals: false,
+            line_at_word_start: false,
+        },
+        _ => return None,
+    };
+    Some(syntax)
+}
+
+/// Remove comments from `text`, the content of `path`, leaving strings untouched.
+/// Lines left blank by the removal are dropped and trailing whitespace before a removed
+/// comment is trimmed; other lines are kept verbatim. A `#!` first line is kept.
+/// Returns `None` when the comment syntax of the file's language is unknown.
+pub fn strip_comments(path: &Path, text: &str) -> Option<String> {
+    let syntax = comment_syntax(&language_from_path(path))?;
+    // `.ts` is also Qt Linguist XML, where `//` is ordinary text
+    let ext = path.extension().and_then(|s| s.to_str()).unwrap_or("");
+    if ext.eq_ignore_ascii_case("ts") && text.trim_start().starts_with('<') {
+        return None;
+    }
+
+    // Pass 1: blank out comments but keep every line break, so lines still line up
+    let mut out = String::with_capacity(text.len());
+    let mut i = 0;
+    if text.starts_with("#!") {
+        i = text.find('\n').unwrap_or(text.len());
+        out.push_str(&text[..i]);
+    }
+    while i < text.len() {
+        let rest = &text[i..];
+        if let Some(&(open, close)) = syntax.strings.iter().find(|(o, _)| rest.starts_with(o)) {
+            let len = delimited_len(rest, open, close, false, true);
+            out.push_str(&rest[..len]);
+            i += len;
+            continue;
+        }
+        if syntax.char_literals && rest.starts_with('\'') {
+            if let Some(len) = char_literal_len(rest) {
+                out.push_str(&rest[..len]);
+                i += len;
+                continue;
+            }
+        }
+        if let Some((open, close)) = syntax.block.filter(|(o, _)| rest.starts_with(o)) {
+            let len = delimited_len(rest, open, close, syntax.nested, false);
+            let newlines = rest[..len].matches('\n').count();
+            if newlines > 0 {
+                out.push_str(&"\n".repeat(newlines));
+            } else if !out.ends_with(char::is_whitespace)
+                && !out.is_empty()
+                && !rest[len..].starts_with(char::is_whitespace)
+            {
+                // Keep `int/*x*/a` from collapsing into `inta`
+                out.push(' ');
+            }
+            i += len;
+            continue;
+        }
+        let at_word_start =
+            !syntax.line_at_word_start || out.is_empty() || out.ends_with(char::is_whitespace);
+        if at_word_start && syntax.line.iter().any(|m| rest.starts_with(m)) {
+            let mut len = rest.find('\n').unwrap_or(rest.len());
+            if rest[..len].ends_with('\r') {
+                len -= 1;
+            }
+            i += len;
+            continue;
+        }
+        let c = rest.chars().next().expect("non-empty");
+        out.push(c);
+        i += c.len_utf8();
+    }
+
+    // Pass 2: tidy only the lines that changed
+    let mut result = String::with_capacity(out.len());
+    for (before, after) in text.split_inclusive('\n').zip(out.split_inclusive('\n')) {
+        if before == after {
+            result.push_str(after);
+            continue;
+        }
+        let body = after.trim_end_matches(['\n', '\r']);
+        let ending = &after[body.len()..];
+        let body = body.trim_end();
+        if body.is_empty() && !before.trim().is_empty() {
+            continue;
+        }
+        result.push_str(body);
+        result.push_str(ending);
+    }
+    Some(result)
+}
+
+/// Byte length of the delimited span at the start of `s` (through `close`, or to the end when
+/// unterminated). In strings a backslash escapes the next character.
+fn delimited_len(s: &str, open: &str, close: &str, nested: bool, escapes: bool) -> usize {
+    let mut depth = 1;
+    let mut i = open.len();
+    while i < s.len() {
+        let rest = &s[i..];
+        if escapes && rest.starts_with('\\') {
+            i += 1 + rest[1..].chars().next().map_or(0, char::len_utf8);
+        } else if rest.starts_with(close) {
+            i += close.len();
+            depth -= 1;
+            if depth == 0 {
+                return i;
+            }
+        } else if nested && rest.starts_with(open) {
+            i += open.len();
+            depth += 1;
+        } else {
+            i += rest.chars().next().map_or(1, char::len_utf8);
+        }
+    }
+    s.len()
+}
+
+/// Length of a character literal such as `'a'` or `'\n'` at the start of `s`;
+/// `None` for a lone quote like a Rust lifetime (`'a`) or a Haskell prime.
+fn char_literal_len(s: &str) -> Option<usize> {
+    let body = &s[1..];
+    if let Some(escaped) = body.strip_prefix('\\') {
+        // `'\n'`, `'\''`, `'\u{1F600}'`: the closing quote follows within a few characters
+        let first = escaped.chars().next()?;
+        let close = escaped[first.len_utf8()..].find('\'')? + first.len_utf8();
+        return (close <= 10).then_some(1 + 1 + close + 1);
+    }
+    let c = body.chars().next().filter(|&c| c != '\'' && c != '\n')?;
+    body[c.len_utf8()..]
+        .starts_with('\'')
+        .then_some(1 + c.len_utf8() + 1)
+}
+
+// ----- Tree -----
+
+/// Whether a `TreeNode` is a directory or a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeKind {
+    Dir,
+    File,
+}
+
+/// A directory tree of counts; directory metrics are the sums of their children.
+#[derive(Debug, Clone, Serialize)]
+pub struct TreeNode {
+    pub name: String,
+    pub kind: NodeKind,
+    pub lines: usize,
+    pub total_lines: usize,
+    pub blank_lines: usize,
+    pub tokens: usize,
+    pub bytes: u64,
+    pub words: usize,
+    pub chars: usize,
+    // Keyed by name; serialized as a plain array in name order
+    #[serde(serialize_with = "serialize_children")]
+    pub children: BTreeMap<String, TreeNode>,
+}
+
+fn serialize_children<S: serde::Serializer>(
+    children: &BTreeMap<String, TreeNode>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.collect_seq(children.values())
+}
+
+impl TreeNode {
+    fn new_dir(name: String) -> Self {
+        Self {
+            name,
+            kind: NodeKind::Dir,
+            lines: 0,
+            total_lines: 0,
+            blank_lines: 0,
+            tokens: 0,
+            bytes: 0,
+            words: 0,
+            chars: 0,
+            children: BTreeMap::new(),
+        }
+    }
+
+    fn new_file(name: String, f: &FileCount) -> Self {
+        Self {
+            name,
+            kind: NodeKind::File,
+            lines: f.lines,
+            total_lines: f.total_lines,
+            blank_lines: f.blank_lines,
+            tokens: f.tokens,
+            bytes: f.bytes,
+            words: f.words,
+            chars: f.chars,
+            children: BTreeMap::new(),
+        }
+    }
+
+    /// Drop nodes more than `depth` levels below this one. Totals were summed by
+    /// `build_tree`, so pruned directories still count everything beneath them.
+    pub fn prune(&mut self, depth: usize) {
+        if depth == 0 {
+            self.children.clear();
+        } else {
+            for child in self.children.values_mut() {
+                child.prune(depth - 1);
+            }
+        }
+    }
+}
+
+fn rel_to_root(path: &Path, root_abs: &Path, root_arg: &Path) -> PathBuf {
+    // Prefer absolute root prefix; fall back to provided arg prefix; else filename
+    if let Ok(p) = path.strip_prefix(root_abs) {
+        return p.to_path_buf();
+    }
+    if let Ok(p) = path.strip_prefix(root_arg) {
+        return p.to_path_buf();
+    }
+    // Relative paths that do not share the root's spelling, e.g. `src/a.rs` under `.`
+    if let Some(p) = fs::canonicalize(path)
+        .ok()
+        .and_then(|abs| abs.strip_prefix(root_abs).ok().map(Path::to_path_buf))
+    {
+        return p;
+    }
+    path.file_name()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+/// Arrange `files` into a tree rooted at `root` (named after its last path component).
+pub fn build_tree(root: &Path, files: &[FileCount]) -> TreeNode {
+    let root_abs = std::fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
+    let root_name = root
+        .file_name()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| root.display().to_string());
+
+    let mut root_node = TreeNode::new_dir(root_name);
+
+    for f in files {
+        let rel = rel_to_root(&f.path, &root_abs, root);
+        let mut cur = &mut root_node;
+        let mut comps = rel.components().peekable();
+        while let Some(comp) = comps.next() {
+            let name = comp.as_os_str().to_string_lossy().to_string();
+            let is_last = comps.peek().is_none();
+            if is_last {
+                // file
+                cur.children
+                    .insert(name.clone(), TreeNode::new_file(name, f));
+            } else {
+                // dir
+                cur = cur
+                    .children
+                    .entry(name.clone())
+                    .or_insert_with(|| TreeNode::new_dir(name));
+            }
+        }
+    }
+
+    // Accumulate directory totals
+    fn accumulate(node: &mut TreeNode) {
+        if matches!(node.kind, NodeKind::Dir) {
+            node.lines = 0;
+            node.total_lines = 0;
+            node.blank_lines = 0;
+            node.tokens = 0;
+            node.bytes = 0;
+            node.words = 0;
+            node.chars = 0;
+            // We want dirs printed before files; BTreeMap groups by key, so we just sum all
+            for child in node.children.values_mut() {
+                accumulate(child);
+                node.lines += child.lines;
+                node.total_lines += child.total_lines;
+                node.blank_lines += child.blank_lines;
+                node.tokens += child.tokens;
+                node.bytes += child.bytes;
+                node.words += child.words;
+                node.chars += child.chars;
+            }
+        }
+    }
+    accumulate(&mut root_node);
+    root_node
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1463,6 +4406,536 @@ mod tests {
         assert_eq!(lang, "Rust");
     }
 
+    #[test]
+    fn test_warning_callback_receives_warnings() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = seen.clone();
+        let opts = Options {
+            quiet: true,
+            on_warning: Some(WarningCallback::new(move |w: &Warning| {
+                sink.lock().unwrap().push(w.to_string());
+            })),
+            ..Options::default()
+        };
+        warn(
+            &opts,
+            Warning::TooLarge {
+                path: PathBuf::from("big.bin"),
+                bytes: 128 * 1024 * 1024,
+            },
+        );
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec!["skipping large file (128MB): big.bin".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_count_text() {
+        let enc = Encoding::new("cl100k_base").unwrap();
+        let text = "fn main() {}\n\n  \nlet x = 1;\n";
+        let lite = count_text(text, Some("Rust"), &enc);
+        assert_eq!(lite.lines, 2);
+        assert_eq!(lite.tokens, count_tokens_in_text(&enc.bpe, text));
+        assert_eq!(lite.language.as_deref(), Some("Rust"));
+        assert_eq!(count_text("", None, &enc).tokens, 0);
+    }
+
+    #[test]
+    fn test_binary_heuristics_leave_real_text_alone() {
+        assert!(!has_binary_chars(
+            "fn main() {\n\tprintln!(\"hi\");\r\n}\x0c\n"
+        ));
+        assert!(!has_binary_chars(""));
+        assert!(has_binary_chars("\u{1}\u{2}\u{3}abc\u{0}\u{4}\u{5}"));
+        assert!(has_binary_chars("\u{FFFD}\u{FFFD}\u{FFFD}text"));
+
+        let opts = Options::default();
+        let prose = "The quick brown fox jumps over the lazy dog. ".repeat(40);
+        let cjk = "日本語のテキストを数える。中文文本也一样。".repeat(40);
+        for text in [prose, cjk] {
+            let file = count_str("t.txt", &text, &opts).unwrap();
+            assert!(!has_binary_density(&file), "{} tokens", file.tokens);
+        }
+        let junk = FileCount {
+            tokens: 900,
+            bytes: 1000,
+            ..count_str("t.txt", "", &opts).unwrap()
+        };
+        assert!(has_binary_density(&junk));
+        assert!(!has_binary_density(&FileCount {
+            bytes: 100,
+            tokens: 100,
+            ..junk
+        }));
+    }
+
+    #[test]
+    fn test_ext_to_lang_is_sorted_lowercase_and_unique() {
+        for pair in EXT_TO_LANG.windows(2) {
+            assert!(pair[0].0 < pair[1].0, "{:?} before {:?}", pair[0], pair[1]);
+        }
+        for (ext, _) in EXT_TO_LANG {
+            assert!(
+                !ext.contains('.') && *ext == ext.to_ascii_lowercase(),
+                "{ext}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_known_languages_agree_with_language_from_path() {
+        let langs = known_languages();
+        assert!(langs["Rust"].contains(&"rs"));
+        assert!(!langs.contains_key("Others"));
+        for (lang, exts) in &langs {
+            for ext in exts {
+                let path = format!("file.{ext}");
+                assert_eq!(&language_from_path(Path::new(&path)), lang, "{path}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_language_from_path_matches_extensionless_names() {
+        for (name, lang) in [
+            ("Makefile", "make"),
+            ("src/GNUmakefile", "make"),
+            ("Dockerfile", "Dockerfile"),
+            ("Gemfile", "Ruby"),
+            ("Vagrantfile", "Ruby"),
+            ("BUILD", "Starlark"),
+            ("README", "Others"),
+            ("a.makefile", "make"),
+        ] {
+            assert_eq!(language_from_path(Path::new(name)), lang, "{name}");
+        }
+    }
+
+    #[test]
+    fn test_language_from_content() {
+        let objc = "#import <Foundation/Foundation.h>\n@interface Foo : NSObject\n@end\n";
+        assert_eq!(language_from_content(Path::new("a.m"), objc), "Objective-C");
+        assert_eq!(language_from_content(Path::new("a.h"), objc), "Objective-C");
+        let matlab = "function y = f(x)\n  y = x + 1;\nend\n";
+        assert_eq!(language_from_content(Path::new("f.m"), matlab), "MATLAB");
+        let cpp = "#pragma once\nnamespace foo {\nclass Bar {};\n}\n";
+        assert_eq!(language_from_content(Path::new("bar.h"), cpp), "C++");
+        // Not confident: fall back to the extension mapping
+        let c = "#pragma once\nint add(int a, int b);\n";
+        assert_eq!(
+            language_from_content(Path::new("add.h"), c),
+            language_from_path(Path::new("add.h"))
+        );
+    }
+
+    #[test]
+    fn test_options_round_trip_through_json() {
+        let opts = Options {
+            encoding: "o200k_base".to_string(),
+            include_exts: Some(["rs".to_string(), "toml".to_string()].into()),
+            exclude_exts: Some(["test.rs".to_string()].into()),
+            always_include: vec![".env.example".to_string()],
+            cache_path: Some(PathBuf::from("/tmp/loctok.json")),
+            chunk_bytes: Some(4096),
+            exclude_common: true,
+            on_warning: Some(WarningCallback::new(|_| {})),
+            ..Options::default()
+        };
+        let json = serde_json::to_string(&opts).unwrap();
+        let back: Options = serde_json::from_str(&json).unwrap();
+        assert!(back.on_warning.is_none());
+        assert_eq!(back.include_exts, opts.include_exts);
+        assert_eq!(back.exclude_exts, opts.exclude_exts);
+        assert_eq!(back.always_include, opts.always_include);
+        assert_eq!(back.cache_path, opts.cache_path);
+        assert_eq!(back.encoding, opts.encoding);
+        assert_eq!(back.chunk_bytes, opts.chunk_bytes);
+        assert!(back.exclude_common);
+
+        let partial: Options = serde_json::from_str(r#"{"encoding":"p50k_base"}"#).unwrap();
+        assert_eq!(partial.encoding, "p50k_base");
+        assert_eq!(partial.long_line_threshold, DEFAULT_LONG_LINE_THRESHOLD);
+    }
+
+    #[test]
+    fn test_line_lengths_count_chars_not_bytes() {
+        assert_eq!(line_lengths("", 120), (0, 0));
+        assert_eq!(line_lengths("ab\r\nabcd\n", 3), (4, 1));
+        // Four chars, twelve bytes
+        assert_eq!(line_lengths("日本語字\nab", 3), (4, 1));
+        assert_eq!(line_lengths("abc\rabcdef", 10), (6, 0));
+    }
+
+    #[test]
+    fn test_long_line_without_whitespace_is_chunked() {
+        // Multi-byte chars make the hard cut land on char boundaries, not byte offsets
+        let text = "aé€😀".repeat(2_000);
+        let chunks = split_text_into_chunks(&text, 1_000);
+        assert!(
+            chunks.len() >= text.len() / 1_004,
+            "{} chunks",
+            chunks.len()
+        );
+        assert!(chunks.iter().all(|c| c.len() < 1_000 + 4));
+        assert_eq!(chunks.concat(), text);
+
+        let minified = "x".repeat(1_000_000);
+        let chunks = split_text_into_chunks(&minified, 64 * 1024);
+        assert_eq!(chunks.len(), 16);
+        assert!(chunks.iter().all(|c| c.len() <= 64 * 1024));
+    }
+
+    #[test]
+    fn test_language_ties_are_ordered_by_name() {
+        let opts = Options {
+            estimate: true,
+            ..Options::default()
+        };
+        let file = |path: &str| count_str(path, "fn main() {}\n", &opts).unwrap();
+        let mut files = vec![file("a.py"), file("b.rs"), file("c.go"), file("d.rs")];
+        for _ in 0..files.len() {
+            files.rotate_left(1);
+            let langs: Vec<String> = aggregate_by_language(&files)
+                .into_iter()
+                .map(|s| s.language)
+                .collect();
+            assert_eq!(langs, ["Rust", "Go", "Python"]);
+        }
+    }
+
+    #[test]
+    fn test_build_tree_accumulates_dir_totals() {
+        let file = |path: &str, lines: usize, tokens: usize| FileCount {
+            path: PathBuf::from(path),
+            tokens,
+            lines,
+            total_lines: lines,
+            blank_lines: 0,
+            language: "Rust".to_string(),
+            bytes: 10,
+            words: 2,
+            chars: 10,
+            max_line_len: 10,
+            long_lines: 0,
+            sampled: false,
+        };
+        let files = [
+            file("proj/src/a.rs", 3, 30),
+            file("proj/src/deep/b.rs", 4, 40),
+            file("proj/c.rs", 1, 5),
+        ];
+        let tree = build_tree(Path::new("proj"), &files);
+        assert_eq!(tree.name, "proj");
+        assert_eq!(tree.kind, NodeKind::Dir);
+        assert_eq!((tree.lines, tree.tokens, tree.bytes), (8, 75, 30));
+        let src = &tree.children["src"];
+        assert_eq!((src.lines, src.tokens, src.words), (7, 70, 4));
+        assert_eq!(src.children["deep"].tokens, 40);
+        assert_eq!(tree.children["c.rs"].kind, NodeKind::File);
+
+        let mut pruned = tree.clone();
+        pruned.prune(1);
+        assert_eq!(pruned.tokens, 75);
+        assert_eq!(pruned.children["src"].tokens, 70);
+        assert!(pruned.children["src"].children.is_empty());
+    }
+
+    #[test]
+    fn test_chunk_size_is_configurable() {
+        let enc = get_encoder("cl100k_base").unwrap();
+        let text = "fn main() { println!(\"hello world\"); }\n".repeat(200);
+        let exact = enc.count_special(&text);
+        // A chunk larger than the text encodes it in one go
+        assert_eq!(count_tokens_in_text_chunked(&enc, &text, text.len()), exact);
+        // Zero is clamped to one byte rather than looping forever
+        let zero = count_tokens_in_text_chunked(&enc, &text, 0);
+        assert_eq!(zero, count_tokens_in_text_chunked(&enc, &text, 1));
+        // Small chunks lose merges at boundaries but stay in the same ballpark
+        let small = count_tokens_in_text_chunked(&enc, &text, 64);
+        assert!(small.abs_diff(exact) * 10 < exact, "{small} vs {exact}");
+    }
+
+    #[test]
+    fn test_is_git_url() {
+        assert!(is_git_url("https://github.com/zxch3n/loctok"));
+        assert!(is_git_url("git@github.com:zxch3n/loctok.git"));
+        assert!(is_git_url("ssh://git@example.com/repo"));
+        assert!(is_git_url("file:///tmp/repo"));
+        assert!(!is_git_url("src/lib.rs"));
+        assert!(!is_git_url("git@notes"));
+        assert!(!is_git_url("."));
+    }
+
+    #[test]
+    fn test_normalize_whitespace() {
+        assert_eq!(
+            normalize_whitespace("fn a() {\n\t\tlet  x =\t1;   \r\n\n    }  "),
+            "fn a() {\n let x = 1;\r\n\n }"
+        );
+        assert_eq!(normalize_whitespace(""), "");
+
+        let text = "fn a() {\n        let x = 1;        \n}\n";
+        let opts = Options {
+            normalize_whitespace: true,
+            ..Options::default()
+        };
+        let plain = count_str("a.rs", text, &Options::default()).unwrap();
+        let normalized = count_str("a.rs", text, &opts).unwrap();
+        assert!(normalized.tokens < plain.tokens);
+        // Only tokens change
+        assert_eq!(
+            (normalized.lines, normalized.chars),
+            (plain.lines, plain.chars)
+        );
+    }
+
+    #[test]
+    fn test_encoder_for_picks_the_backend() {
+        let text = "<|endoftext|> hello world";
+        let exact = tiktoken_encoder("cl100k_base")
+            .unwrap()
+            .encode_with_special_tokens(text)
+            .len();
+        assert_eq!(
+            encoder_for(&Options::default())
+                .unwrap()
+                .count_special(text),
+            exact
+        );
+
+        // Without special tokens the marker is spelled out as several ordinary tokens
+        let no_special = Options {
+            allow_special: false,
+            ..Default::default()
+        };
+        let ordinary = tiktoken_encoder("cl100k_base")
+            .unwrap()
+            .encode_ordinary(text)
+            .len();
+        assert!(ordinary > exact);
+        assert_eq!(
+            count_tokens_in_text(&encoder_for(&no_special).unwrap(), text),
+            ordinary
+        );
+
+        let opts = Options {
+            estimate: true,
+            encoding: "no-such-encoding".into(),
+            ..Default::default()
+        };
+        let estimate = encoder_for(&opts).unwrap();
+        assert_eq!(estimate.count_special(text), estimate_tokens(text));
+        assert_eq!(count_tokens_in_text(&estimate, text), estimate_tokens(text));
+    }
+
+    #[test]
+    fn test_line_endings_count_the_same() {
+        let lf = "fn a() {}\n\n  \nlet x = 1;\n";
+        assert_eq!(count_non_empty_lines(lf), 2);
+        assert_eq!(count_non_empty_lines(&lf.replace('\n', "\r\n")), 2);
+        assert_eq!(count_non_empty_lines(&lf.replace('\n', "\r")), 2);
+        assert_eq!(count_non_empty_lines("a\r\n\r\nb\nc\r"), 3);
+
+        assert_eq!(count_line_totals(lf), (4, 2));
+        assert_eq!(count_line_totals(&lf.replace('\n', "\r\n")), (4, 2));
+        assert_eq!(count_line_totals(&lf.replace('\n', "\r")), (4, 2));
+        assert_eq!(count_line_totals("a\r\n\r\nb\nc\r"), (4, 1));
+        // An unterminated last line counts; empty text has no lines
+        assert_eq!(count_line_totals("a\nb"), (2, 0));
+        assert_eq!(count_line_totals(""), (0, 0));
+    }
+
+    #[test]
+    fn test_copy_output_marks_missing_final_newline() {
+        let render = |text: &str| {
+            build_copy_output(
+                Path::new("."),
+                &[(PathBuf::from("f.txt"), text.to_string())],
+            )
+        };
+        assert!(render("x\ny").contains("2 | y\n\\ No newline at end of file\n"));
+        assert!(!render("x\ny\n").contains("No newline"));
+        // A trailing blank line is kept as its own numbered line
+        assert!(render("x\n\n").contains("1 | x\n2 |\n\n"));
+        assert!(!render("").contains("No newline"));
+    }
+
+    #[test]
+    fn test_copy_output_xml_and_markdown() {
+        let inputs = vec![
+            (PathBuf::from("a.md"), "use ```rust``` fences".to_string()),
+            (PathBuf::from("dir/b.rs"), "fn b() {}\n".to_string()),
+        ];
+        let style = CopyStyle {
+            format: CopyFormat::Xml,
+            tree: false,
+            ..CopyStyle::default()
+        };
+        let xml = build_copy_output_as(Path::new("."), &inputs, &style);
+        assert_eq!(
+            xml,
+            "\
+<documents>
+<document index=\"1\">
+<source>a.md</source>
+<document_contents>
+use ```rust``` fences
+</document_contents>
+</document>
+<document index=\"2\">
+<source>dir/b.rs</source>
+<document_contents>
+fn b() {}
+</document_contents>
+</document>
+</documents>
+"
+        );
+
+        let style = CopyStyle {
+            format: CopyFormat::Markdown,
+            ..CopyStyle::default()
+        };
+        let md = build_copy_output_as(Path::new("."), &inputs, &style);
+        assert!(
+            md.starts_with("├── dir\n│   └── b.rs\n└── a.md\n\n"),
+            "{md}"
+        );
+        assert!(md.contains("### a.md\n\n````markdown\nuse ```rust``` fences\n````\n"));
+        assert!(md.contains("### dir/b.rs\n\n```rust\nfn b() {}\n```\n"));
+        assert_eq!(fence_info("C++"), "cpp");
+        assert_eq!(fence_info("Perl/Prolog"), "");
+    }
+
+    #[test]
+    fn test_copy_output_path_prefix() {
+        let inputs = vec![
+            (PathBuf::from("a.txt"), "a\n".to_string()),
+            (PathBuf::from("src/lib.rs"), "b\n".to_string()),
+        ];
+        let style = CopyStyle {
+            path_prefix: Some("myrepo/".to_string()),
+            ..CopyStyle::default()
+        };
+        let out = build_copy_output_as(Path::new("."), &inputs, &style);
+        assert!(out.starts_with("└── myrepo\n    ├── src\n    │   └── lib.rs\n    └── a.txt\n\n"));
+        assert!(out.contains("\n/myrepo/src/lib.rs:\n"));
+        assert!(out.contains("\n/myrepo/a.txt:\n"));
+    }
+
+    #[test]
+    fn test_copy_tree_annotates_tokens() {
+        let inputs = vec![
+            (PathBuf::from("a.txt"), "a\n".to_string()),
+            (PathBuf::from("src/lib.rs"), "b\n".to_string()),
+            (PathBuf::from("src/main.rs"), "c\n".to_string()),
+        ];
+        let style = CopyStyle {
+            path_prefix: Some("myrepo".to_string()),
+            tree_tokens: Some(
+                [
+                    (PathBuf::from("a.txt"), 1),
+                    (PathBuf::from("src/lib.rs"), 1200),
+                    (PathBuf::from("src/main.rs"), 3),
+                ]
+                .into(),
+            ),
+            ..CopyStyle::default()
+        };
+        let out = build_copy_output_as(Path::new("."), &inputs, &style);
+        assert!(out.starts_with(
+            "└── myrepo (1,204 tokens)\n    ├── src (1,203 tokens)\n    │   ├── lib.rs (1,200 tokens)\n"
+        ));
+        assert!(out.contains("    └── a.txt (1 token)\n\n"));
+        // Section headers keep the bare paths
+        assert!(out.contains("\n/myrepo/src/lib.rs:\n"));
+    }
+
+    #[test]
+    fn test_truncate_lines() {
+        let text = "1\n2\n3\n4\n5";
+        assert_eq!(truncate_lines(text, 5, 0), None);
+        assert_eq!(truncate_lines(text, 3, 2), None);
+        assert_eq!(
+            truncate_lines(text, 2, 0).unwrap(),
+            "1\n2\n… (truncated, 3 more lines)\n"
+        );
+        assert_eq!(
+            truncate_lines(text, 1, 1).unwrap(),
+            "1\n… (truncated, 3 more lines)\n5"
+        );
+        assert_eq!(
+            truncate_lines(text, 0, 0).unwrap(),
+            "… (truncated, 5 more lines)\n"
+        );
+    }
+
+    #[test]
+    fn test_strip_comments_keeps_strings() {
+        let rust = "\
+// header
+fn main() {
+    let url = \"http://x\"; // trailing
+    /* block /* nested */
+       still */
+    let c = '/';
+    let s: &'static str = \"/* not a comment */\";
+}
+";
+        assert_eq!(
+            strip_comments(Path::new("main.rs"), rust).unwrap(),
+            "\
+fn main() {
+    let url = \"http://x\";
+    let c = '/';
+    let s: &'static str = \"/* not a comment */\";
+}
+"
+        );
+
+        let py = "#!/usr/bin/env python\n# note\nx = '#1'  # count\ns = \"\"\"doc # kept\"\"\"\n";
+        assert_eq!(
+            strip_comments(Path::new("a.py"), py).unwrap(),
+            "#!/usr/bin/env python\nx = '#1'\ns = \"\"\"doc # kept\"\"\"\n"
+        );
+        // `#` mid-word is not a shell comment
+        assert_eq!(
+            strip_comments(Path::new("a.sh"), "echo $# a#b # c\n").unwrap(),
+            "echo $# a#b\n"
+        );
+        assert_eq!(
+            strip_comments(Path::new("a.c"), "int/*x*/a;\n").unwrap(),
+            "int a;\n"
+        );
+        assert!(strip_comments(Path::new("README.md"), "# title\n").is_none());
+    }
+
+    #[test]
+    fn test_strip_comments_by_path() {
+        // Table names like "C#/Smalltalk" reach `comment_syntax` as their shown name
+        assert_eq!(
+            strip_comments(Path::new("a.cs"), "int a; // note\n").unwrap(),
+            "int a;\n"
+        );
+        assert_eq!(
+            strip_comments(Path::new("a.hpp"), "/* x */\nint a;\n").unwrap(),
+            "int a;\n"
+        );
+        assert_eq!(
+            strip_comments(Path::new("a.ts"), "let a = 1; // note\n").unwrap(),
+            "let a = 1;\n"
+        );
+        // Qt Linguist `.ts` is XML where `//` is plain text
+        let qt = "<?xml version=\"1.0\"?>\n<TS><source>http://x</source></TS>\n";
+        assert!(strip_comments(Path::new("q.ts"), qt).is_none());
+        // ...but TSX starting with markup is still TypeScript
+        assert_eq!(
+            strip_comments(Path::new("a.tsx"), "<a/>; // note\n").unwrap(),
+            "<a/>;\n"
+        );
+    }
+
     #[test]
     fn test_build_copy_output() {
         // Given relative paths and content
@@ -1486,6 +4959,7 @@ mod tests {
 1 | line1
 2 |
 3 | line2
+\\ No newline at end of file
 
 
 --------------------------------------------------------------------------------
@@ -1493,6 +4967,7 @@ mod tests {
 --------------------------------------------------------------------------------
 1 | x
 2 | y
+\\ No newline at end of file
 
 
 --------------------------------------------------------------------------------
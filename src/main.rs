@@ -1,25 +1,252 @@
-use std::io::{self, IsTerminal, Write};
+use std::io::{self, IsTerminal, Read, Write};
 use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Mutex;
 use std::time::Instant;
 
 use anyhow::{Context, Result};
-use clap::{ArgAction, Parser, Subcommand, ValueEnum};
+use clap::builder::PossibleValuesParser;
+use clap::parser::ValueSource;
+use clap::{ArgAction, ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressState, ProgressStyle};
 use loctok::{
-    aggregate_by_language, build_copy_output, collect_filtered_texts, count_tokens_in_path,
-    count_tokens_in_path_with_progress, Options,
+    aggregate_by_extension, aggregate_by_language, build_copy_output_as, count_archive, count_file,
+    count_history, count_staged, count_str, count_strs, count_summary, count_summary_with_progress,
+    count_tokens_and_collect_texts, count_tokens_in_path, count_tokens_in_path_with_progress,
+    default_cache_path, filtered_paths, is_archive, is_git_url, known_languages,
+    language_from_path, largest_files, normalize_whitespace, shallow_clone, truncate_lines,
+    unmatched_extensions, CopyStyle, Options, Warning, WarningCallback, ENCODINGS,
 };
 use num_format::{Locale, ToFormattedString};
-use tabled::settings::{object::Columns, Alignment, Modify, Style};
-use tabled::{Table, Tabled};
+use tabled::builder::Builder;
+use tabled::settings::{object::Columns, style::HorizontalLine, Alignment, Modify, Style};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
-#[derive(Copy, Clone, Debug, ValueEnum)]
+/// Like `print!`, but into the `--output` file when one was given.
+macro_rules! out {
+    ($($arg:tt)*) => {
+        write_output(format_args!($($arg)*))
+    };
+}
+
+/// Like `println!`, but into the `--output` file when one was given.
+macro_rules! outln {
+    () => {
+        out!("\n")
+    };
+    ($($arg:tt)*) => {{
+        out!($($arg)*);
+        out!("\n");
+    }};
+}
+
+/// The `--output` file; report output goes to stdout while this is unset.
+struct OutputFile {
+    path: PathBuf,
+    writer: io::BufWriter<std::fs::File>,
+    bytes: usize,
+    // First write error, reported once output is finished instead of panicking mid-report
+    error: Option<io::Error>,
+    quiet: bool,
+}
+
+static OUTPUT: Mutex<Option<OutputFile>> = Mutex::new(None);
+
+/// Create (truncating) `path` and send all further report output there.
+fn open_output(path: &Path, quiet: bool) -> Result<()> {
+    let file = std::fs::File::create(path)
+        .with_context(|| format!("failed to create {}", path.display()))?;
+    *OUTPUT.lock().unwrap() = Some(OutputFile {
+        path: path.to_path_buf(),
+        writer: io::BufWriter::new(file),
+        bytes: 0,
+        error: None,
+        quiet,
+    });
+    Ok(())
+}
+
+fn write_output(args: std::fmt::Arguments) {
+    let mut output = OUTPUT.lock().unwrap();
+    let Some(out) = output.as_mut() else {
+        print!("{args}");
+        return;
+    };
+    if out.error.is_some() {
+        return;
+    }
+    let text = args.to_string();
+    match out.writer.write_all(text.as_bytes()) {
+        Ok(()) => out.bytes += text.len(),
+        Err(err) => out.error = Some(err),
+    }
+}
+
+/// Flush the `--output` file, if any, and confirm on stderr what was written.
+fn finish_output() -> Result<()> {
+    let Some(mut out) = OUTPUT.lock().unwrap().take() else {
+        return Ok(());
+    };
+    if let Some(err) = out.error.take() {
+        return Err(err).with_context(|| format!("failed to write {}", out.path.display()));
+    }
+    out.writer
+        .flush()
+        .with_context(|| format!("failed to write {}", out.path.display()))?;
+    if !out.quiet {
+        eprintln!("Wrote {} bytes to {}", out.bytes, out.path.display());
+    }
+    Ok(())
+}
+
+/// Whether report output lands on a terminal, i.e. stdout is one and `--output` is unset.
+fn output_is_terminal() -> bool {
+    OUTPUT.lock().unwrap().is_none() && io::stdout().is_terminal()
+}
+
+/// Columns available to the report: `--width`, else the terminal's width when printing to
+/// one. `None` when neither is known, e.g. when piped.
+fn output_width(args: &Cli) -> Option<usize> {
+    args.width.map(usize::from).or_else(|| {
+        output_is_terminal()
+            .then(terminal_size::terminal_size)
+            .flatten()
+            .map(|(w, _)| w.0 as usize)
+    })
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
 enum OutputFormat {
     /// Display a table of lines of code and tokens of code, grouped by language
     Table,
     /// Get all files and their token counts
     Json,
+    /// Same report as `json`, serialized as YAML
+    Yaml,
     /// Display the file tree and each file/folder's lines of code and tokens of code
     Tree,
+    /// One horizontal bar per language, proportional to its token share
+    Bars,
+    /// The file tree as nested JSON (`name`, `kind`, metrics, `children`)
+    TreeJson,
+    /// A treemap of files and directories sized by tokens and colored by language (SVG)
+    Svg,
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+enum CopyFormat {
+    /// Numbered lines under a dashed `/<path>:` header
+    Default,
+    /// `<document>` tags with `<source>` and `<document_contents>`, for Claude-style prompts
+    Xml,
+    /// `### path` headings with each file in a language-tagged fenced code block
+    Markdown,
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+enum CopyOrder {
+    /// Alphabetical by path
+    Path,
+    /// Smallest files first, so the model reads small context before the big files
+    TokensAsc,
+    /// Largest files first
+    TokensDesc,
+}
+
+impl From<CopyFormat> for loctok::CopyFormat {
+    fn from(format: CopyFormat) -> Self {
+        match format {
+            CopyFormat::Default => loctok::CopyFormat::Default,
+            CopyFormat::Xml => loctok::CopyFormat::Xml,
+            CopyFormat::Markdown => loctok::CopyFormat::Markdown,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+enum GroupBy {
+    /// One row per language
+    Language,
+    /// One row per lowercased file extension (or file name without one)
+    Extension,
+}
+
+/// Kinds of `skipped` entries `--fail-on-skipped` can be limited to.
+#[derive(Copy, Clone, Debug, ValueEnum)]
+enum SkipReason {
+    /// A directory entry could not be walked
+    Walk,
+    /// File metadata could not be read
+    Metadata,
+    /// Over the size limit
+    TooLarge,
+    /// Under `--min-size`
+    TooSmall,
+    /// File contents could not be read
+    Read,
+    /// Empty with `--exclude-empty`
+    Empty,
+    /// Looks binary despite being UTF-8
+    SuspectedBinary,
+    /// Marked `linguist-vendored` in .gitattributes
+    Vendored,
+    /// Marked `linguist-generated` in .gitattributes
+    Generated,
+}
+
+impl SkipReason {
+    fn matches(self, warning: &Warning) -> bool {
+        matches!(
+            (self, warning),
+            (SkipReason::Walk, Warning::Walk { .. })
+                | (SkipReason::Metadata, Warning::Metadata { .. })
+                | (SkipReason::TooLarge, Warning::TooLarge { .. })
+                | (SkipReason::TooSmall, Warning::TooSmall { .. })
+                | (SkipReason::Read, Warning::Read { .. })
+                | (SkipReason::Empty, Warning::Empty { .. })
+                | (SkipReason::SuspectedBinary, Warning::SuspectedBinary { .. })
+                | (SkipReason::Vendored, Warning::Vendored { .. })
+                | (SkipReason::Generated, Warning::Generated { .. })
+        )
+    }
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+enum ColorChoice {
+    /// Colorize only when stdout is a terminal and `NO_COLOR` is unset
+    Auto,
+    /// Always emit ANSI colors
+    Always,
+    /// Never emit ANSI colors
+    Never,
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+enum ProgressFormat {
+    /// Human-readable status line
+    Human,
+    /// One NDJSON object per update, e.g. {"done":12,"total":40}
+    Json,
+}
+
+/// Destination of `--progress` output.
+#[derive(Clone, Debug)]
+enum ProgressSink {
+    Stderr,
+    Stdout,
+    None,
+    File(PathBuf),
+}
+
+fn parse_progress_sink(s: &str) -> Result<ProgressSink, String> {
+    Ok(match s {
+        "stderr" => ProgressSink::Stderr,
+        "stdout" => ProgressSink::Stdout,
+        "none" => ProgressSink::None,
+        "" => return Err("expected stderr, stdout, none or a file path".to_string()),
+        path => ProgressSink::File(PathBuf::from(path)),
+    })
 }
 
 #[derive(Parser, Debug)]
@@ -29,256 +256,1943 @@ enum OutputFormat {
     about = "Count LOC (lines of code) & TOK (LLM tokens), fast."
 )]
 struct Cli {
-    /// Root path to scan (defaults to current directory)
+    /// Paths to scan (defaults to current directory). Directories are walked; files given
+    /// explicitly (e.g. a shell-expanded glob) are counted as-is, bypassing ignore and --ext filters
     #[arg(default_value = ".")]
-    path: PathBuf,
+    paths: Vec<PathBuf>,
 
-    /// Encoding to use (cl100k_base, o200k_base, p50k_base, p50k_edit, r50k_base)
-    #[arg(long, default_value = "o200k_base", global = true)]
+    /// Count text piped on stdin instead of scanning paths
+    #[arg(long, action = ArgAction::SetTrue, conflicts_with = "paths")]
+    stdin: bool,
+
+    /// Encoding to use
+    #[arg(
+        long,
+        default_value = "o200k_base",
+        global = true,
+        value_parser = PossibleValuesParser::new(ENCODINGS.iter().map(|e| e.name))
+    )]
     encoding: String,
 
+    /// Print the supported encodings and the models that use them, then exit
+    #[arg(long, action = ArgAction::SetTrue)]
+    list_encodings: bool,
+
+    /// Print every recognized language with its file extensions, then exit
+    #[arg(long, action = ArgAction::SetTrue)]
+    list_languages: bool,
+
+    /// Add `elapsed_ms` and `files_per_sec` to JSON and YAML output
+    #[arg(long, action = ArgAction::SetTrue)]
+    stats: bool,
+
+    /// Print the JSON Schema of the `--format json` report, then exit
+    #[arg(long, action = ArgAction::SetTrue)]
+    print_schema: bool,
+
+    /// Locale for thousands separators in human-readable output, e.g. "de" (1.234) or "fr"
+    #[arg(long, value_name = "NAME", default_value = "en", value_parser = parse_locale, global = true)]
+    locale: Locale,
+
+    /// Pick the encoding for a model instead, e.g. "gpt-4o" or "gpt-3.5-turbo"
+    #[arg(long, value_name = "MODEL", value_parser = parse_model, global = true, conflicts_with = "encoding")]
+    model: Option<&'static str>,
+
+    /// Count with a tiktoken-format vocabulary file (`<base64 token> <rank>` per line) instead,
+    /// e.g. for a fine-tuned model
+    #[arg(long, value_name = "PATH", global = true, conflicts_with_all = ["encoding", "model"])]
+    encoding_file: Option<PathBuf>,
+
+    /// Count with a HuggingFace `tokenizer.json` instead, e.g. for Llama or Mistral
+    /// (requires building with `--features hf`)
+    #[arg(long, value_name = "PATH", global = true, conflicts_with_all = ["encoding", "model", "encoding_file"])]
+    hf_tokenizer: Option<PathBuf>,
+
     /// Include hidden files (dotfiles)
     #[arg(long, action = ArgAction::SetTrue, global = true)]
     hidden: bool,
 
-    /// Output format (text or json)
-    #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+    /// Output format (table, json, yaml, tree)
+    #[arg(long, value_enum, default_value_t = OutputFormat::Table, global = true)]
     format: OutputFormat,
 
+    /// Write the report to PATH (truncating it) instead of stdout; progress and warnings
+    /// stay on stderr
+    #[arg(short, long, value_name = "PATH", global = true)]
+    output: Option<PathBuf>,
+
+    /// Also write the scan as a JSON report (the `--format json` output) to PATH, creating or
+    /// overwriting it, e.g. to bootstrap or refresh a committed baseline
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = ["summary_only", "compare_encodings"]
+    )]
+    update_baseline: Option<PathBuf>,
+
+    /// Metric columns to show in table and tree mode, in order (lines, total-lines,
+    /// blank-lines, tokens, bytes, words, chars)
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        default_values_t = [Metric::Lines, Metric::Tokens]
+    )]
+    show: Vec<Metric>,
+
+    /// Rows of the table: per language, or per raw extension (json/yaml add `by_extension`)
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = GroupBy::Language,
+        conflicts_with = "summary_only"
+    )]
+    group_by: GroupBy,
+
     /// Comma-separated list of file extensions to include (e.g., "rs,py,js"). If empty, all files are processed.
+    /// Prefix an entry with `!` to exclude it instead (e.g., "!json,!lock"); excludes win, so
+    /// "rs,!test.rs" keeps `.rs` files except `*.test.rs`.
     #[arg(long, default_value = "", global = true)]
     ext: String,
 
-    /// Show progress while scanning (prints to stderr). Use --progress=false to disable.
+    /// Match `--ext` entries against extensions exactly as on disk, so `C` keeps `a.C`
+    /// but not `b.c` (by default matching ignores case)
+    #[arg(long, action = ArgAction::SetTrue, global = true)]
+    case_sensitive_ext: bool,
+
+    /// Comma-separated languages to count, e.g. "Rust,Python" (case-insensitive)
+    #[arg(long, value_name = "LANGS", global = true)]
+    include_lang: Option<String>,
+
+    /// Comma-separated languages to skip, e.g. "JSON,XML" (case-insensitive)
+    #[arg(long, value_name = "LANGS", global = true)]
+    exclude_lang: Option<String>,
+
+    /// Glob of files to include even when hidden files are skipped (repeatable), e.g. ".env.example"
+    #[arg(long = "always-include", value_name = "GLOB", global = true)]
+    always_include: Vec<String>,
+
+    /// Suppress warnings about skipped files (errors that abort the scan still print)
+    #[arg(long, short, action = ArgAction::SetTrue, global = true)]
+    quiet: bool,
+
+    /// Show progress while scanning (on stderr unless --progress-to says otherwise).
+    /// Use --progress=false to disable.
     #[arg(long, default_value_t = true, global = true)]
     progress: bool,
 
-    /// Subcommands
-    #[command(subcommand)]
-    command: Option<Commands>,
+    /// How progress updates are rendered on stderr (human or json)
+    #[arg(long, value_enum, default_value_t = ProgressFormat::Human, global = true)]
+    progress_format: ProgressFormat,
+
+    /// Where progress goes: stderr, stdout, none, or a file path (overwritten)
+    #[arg(long, value_name = "SINK", default_value = "stderr", value_parser = parse_progress_sink, global = true)]
+    progress_to: ProgressSink,
+
+    /// When to use ANSI colors in output (auto, always, never). `auto` honors `NO_COLOR`.
+    #[arg(long, value_enum, default_value_t = ColorChoice::Auto, global = true)]
+    color: ColorChoice,
+
+    /// Skip common dependency and build directories (node_modules, target, dist, vendor, ...)
+    /// even when they are not gitignored
+    #[arg(long, action = ArgAction::SetTrue, global = true)]
+    exclude_common: bool,
+
+    /// Skip package-manager lock files (Cargo.lock, package-lock.json, yarn.lock,
+    /// poetry.lock, go.sum, ...)
+    #[arg(long, action = ArgAction::SetTrue, global = true)]
+    exclude_lockfiles: bool,
+
+    /// Leave out empty and whitespace-only files (they are still listed under `skipped`)
+    #[arg(long, action = ArgAction::SetTrue, global = true)]
+    exclude_empty: bool,
+
+    /// Skip files smaller than SIZE, e.g. 200, 4KB or 1.5MiB (listed under `skipped`)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, global = true)]
+    min_size: Option<u64>,
+
+    /// Skip files larger than SIZE (default 64MB), e.g. 512KB or 10M
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, global = true)]
+    max_size: Option<u64>,
+
+    /// Count UTF-8 files that look binary (mostly control characters or implausibly many
+    /// tokens per byte); by default they are skipped with a warning
+    #[arg(long, action = ArgAction::SetTrue, global = true)]
+    include_binary: bool,
+
+    /// Count only files tracked by git (untracked files are skipped even if not ignored)
+    #[arg(long, action = ArgAction::SetTrue, global = true)]
+    git_only: bool,
+
+    /// Branch or tag to check out when a path is a repository URL (default: its default branch)
+    #[arg(long = "ref", value_name = "REF")]
+    git_ref: Option<String>,
+
+    /// Keep the clone of a repository URL instead of deleting it after the scan
+    #[arg(long, action = ArgAction::SetTrue)]
+    keep_clone: bool,
+
+    /// Count files marked `linguist-vendored` in .gitattributes (skipped by default)
+    #[arg(long, action = ArgAction::SetTrue, global = true)]
+    include_vendored: bool,
+
+    /// Count files marked `linguist-generated` in .gitattributes (skipped by default)
+    #[arg(long, action = ArgAction::SetTrue, global = true)]
+    include_generated: bool,
+
+    /// In table mode, also list the extensions that fell into the "Others" bucket
+    #[arg(long, action = ArgAction::SetTrue)]
+    explain_others: bool,
+
+    /// Also list the N files with the most tokens (a table, or `largest_files` in json/yaml)
+    #[arg(long, value_name = "N")]
+    largest: Option<usize>,
+
+    /// Only report per-language totals (table, json or yaml), without keeping per-file
+    /// records; uses far less memory on very large trees
+    #[arg(
+        long,
+        action = ArgAction::SetTrue,
+        conflicts_with_all = ["stdin", "explain_others", "tree_depth", "largest"]
+    )]
+    summary_only: bool,
+
+    /// Count with each listed encoding (comma-separated, e.g. cl100k_base,o200k_base) and
+    /// compare tokens per language (table or json); files are read and lines counted once
+    #[arg(
+        long,
+        value_name = "ENCODINGS",
+        value_delimiter = ',',
+        value_parser = PossibleValuesParser::new(ENCODINGS.iter().map(|e| e.name)),
+        conflicts_with_all = [
+            "stdin",
+            "summary_only",
+            "encoding_file",
+            "hf_tokenizer",
+            "estimate",
+            "sample_bytes"
+        ]
+    )]
+    compare_encodings: Vec<String>,
+
+    /// Approximate tokens as chars / 4 instead of running the tokenizer (fast, not exact)
+    #[arg(long, action = ArgAction::SetTrue, global = true)]
+    estimate: bool,
+
+    /// Encode special-token text such as `<|endoftext|>` as ordinary text instead of
+    /// counting it as one special token
+    #[arg(long, action = ArgAction::SetTrue, global = true)]
+    no_special_tokens: bool,
+
+    /// Count tokens as if the code were reformatted: collapse runs of spaces and tabs and drop
+    /// trailing whitespace. Lines of code are unaffected; `copy` pastes the normalized text
+    #[arg(long, action = ArgAction::SetTrue, global = true)]
+    normalize_whitespace: bool,
+
+    /// Count only the first N bytes of larger files and extrapolate by file size (fast,
+    /// approximate)
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..), conflicts_with = "stdin")]
+    sample_bytes: Option<u64>,
+
+    /// Skip counting lines of code (faster on token-only scans); `lines` is 0 in JSON/YAML
+    /// and the lines column is hidden
+    #[arg(long, action = ArgAction::SetTrue, global = true)]
+    no_line_count: bool,
+
+    /// Tokens added per file to simulate chat message framing (included in the total)
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    chat_overhead: usize,
+
+    /// Tokens added once for the surrounding prompt (included in the total)
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    prompt_overhead: usize,
+
+    /// Chunk size in bytes for tokenizing large files in parallel (default 512).
+    /// Very small chunks make counts less exact, since tokens cannot span a chunk boundary.
+    #[arg(long, value_name = "BYTES", value_parser = clap::value_parser!(u64).range(1..), global = true)]
+    chunk_bytes: Option<u64>,
+
+    /// Lines longer than N characters are counted as long (`long_lines` in JSON/YAML)
+    #[arg(long, value_name = "N", default_value_t = loctok::DEFAULT_LONG_LINE_THRESHOLD, global = true)]
+    long_line_threshold: usize,
+
+    /// Sniff file contents to tell apart ambiguous extensions (e.g. `.m` Objective-C vs MATLAB)
+    #[arg(long, action = ArgAction::SetTrue, global = true)]
+    smart_language: bool,
+
+    /// Location of the count cache (defaults to a per-root file under the user cache dir)
+    #[arg(long, value_name = "PATH", global = true)]
+    cache: Option<PathBuf>,
+
+    /// Recount every file without reading or writing the cache
+    #[arg(long, action = ArgAction::SetTrue, global = true, conflicts_with = "cache")]
+    no_cache: bool,
+
+    /// Show only N levels below the root in tree mode; deeper files still count toward the
+    /// totals of the directories shown
+    #[arg(long, value_name = "N")]
+    tree_depth: Option<usize>,
+
+    /// Fit tree and bars output to N columns instead of the terminal width; long tree names
+    /// are shortened in the middle with `…`
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u16).range(20..))]
+    width: Option<u16>,
+
+    /// In tree mode, print counts only for files and the root total; directories are
+    /// still listed, without their subtotals
+    #[arg(long, action = ArgAction::SetTrue)]
+    tree_files_only: bool,
+
+    /// Omit the SUM row (table) and the root total line (tree)
+    #[arg(long, action = ArgAction::SetTrue)]
+    no_total: bool,
+
+    /// Print absolute paths: file and skipped paths in json/yaml, the root in tree modes
+    #[arg(long, action = ArgAction::SetTrue, global = true)]
+    absolute_paths: bool,
+
+    /// Exit with code 1 if the total token count exceeds N (output is still printed)
+    #[arg(long, value_name = "N", global = true)]
+    fail_if_total_over: Option<usize>,
+
+    /// Exit with code 1 if a language's token count exceeds N, e.g. "Rust=50000" (repeatable)
+    #[arg(long, value_name = "LANG=N", value_parser = parse_lang_threshold)]
+    fail_if_lang_over: Vec<LangThreshold>,
+
+    /// Exit with code 1 if any file was skipped, listing each one; optionally only for
+    /// some reasons, e.g. `--fail-on-skipped=read,metadata`
+    #[arg(
+        long,
+        value_enum,
+        value_name = "REASONS",
+        num_args = 0..,
+        value_delimiter = ',',
+        require_equals = true
+    )]
+    fail_on_skipped: Option<Vec<SkipReason>>,
+
+    /// Exit with code 1 if the walk could not read some entry, such as a directory without
+    /// read permission (same as `--fail-on-skipped=walk`); the rest is still counted
+    #[arg(long, action = ArgAction::SetTrue)]
+    fail_on_walk_error: bool,
+
+    /// Read defaults from this config file instead of discovering `loctok.toml`
+    #[arg(long, value_name = "PATH", global = true)]
+    config: Option<PathBuf>,
+
+    /// Ignore `loctok.toml`, the user config file and per-directory `.loctok.toml` files
+    #[arg(long, action = ArgAction::SetTrue, global = true, conflicts_with = "config")]
+    no_config: bool,
+
+    /// Ignore per-directory `.loctok.toml` files, which otherwise narrow the filters for
+    /// their subtree
+    #[arg(long, action = ArgAction::SetTrue, global = true)]
+    no_dir_config: bool,
+
+    /// Subcommands
+    #[command(subcommand)]
+    command: Option<Commands>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Concatenate filtered files into a single clipboard-friendly payload
+    Copy {
+        /// Root path to scan (defaults to current directory)
+        #[arg(default_value = ".")]
+        path: PathBuf,
+        /// Also print the copied content to stdout
+        #[arg(long, action = ArgAction::SetTrue)]
+        show: bool,
+        /// Layout of each file in the payload (default, xml, markdown)
+        #[arg(long, value_enum, default_value_t = CopyFormat::Default)]
+        copy_format: CopyFormat,
+        /// Leave out the file tree at the top of the payload
+        #[arg(long, action = ArgAction::SetTrue)]
+        no_tree: bool,
+        /// Leave the token counts off the file tree lines, for a clean verbatim dump
+        #[arg(long, action = ArgAction::SetTrue)]
+        no_tree_tokens: bool,
+        /// Prepend this to every path in the payload, e.g. "myrepo" gives `/myrepo/src/lib.rs:`
+        #[arg(long, value_name = "PREFIX")]
+        path_prefix: Option<String>,
+        /// Remove comments (never inside strings) from languages with known comment syntax
+        #[arg(long, action = ArgAction::SetTrue)]
+        strip_comments: bool,
+        /// Order of files in the payload (path, tokens-asc, tokens-desc)
+        #[arg(long, value_enum, default_value_t = CopyOrder::Path)]
+        sort: CopyOrder,
+        /// Keep only the first N lines of each file, marking the cut with `… (truncated, M more lines)`
+        #[arg(long, value_name = "N")]
+        max_lines_per_file: Option<usize>,
+        /// With --max-lines-per-file, also keep the last N lines of each file
+        #[arg(long, value_name = "N", requires = "max_lines_per_file")]
+        tail_lines: Option<usize>,
+        /// Count the whole payload, reporting the tokens spent on the tree, paths and line
+        /// numbers next to the file contents, in total and per file
+        #[arg(long, action = ArgAction::SetTrue)]
+        count_overhead: bool,
+    },
+    /// Report the token change of staged files (index vs HEAD), e.g. in a pre-commit hook
+    Staged {
+        /// Directory inside the repository to report on (defaults to current directory)
+        #[arg(default_value = ".")]
+        path: PathBuf,
+    },
+    /// Total tokens at each of the last N commits, oldest first, to see how a repo grew
+    History {
+        /// Directory inside the repository to report on (defaults to current directory)
+        #[arg(default_value = ".")]
+        path: PathBuf,
+        /// Number of first-parent commits of HEAD to count
+        #[arg(long, value_name = "N", default_value_t = 10)]
+        last: usize,
+    },
+}
+
+/// Defaults read from `loctok.toml`; any flag given on the command line wins.
+#[derive(serde::Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct FileConfig {
+    encoding: Option<String>,
+    ext: Option<Vec<String>>,
+    always_include: Option<Vec<String>>,
+    hidden: Option<bool>,
+    format: Option<OutputFormat>,
+}
+
+impl FileConfig {
+    fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read config {}", path.display()))?;
+        toml::from_str(&text).with_context(|| format!("invalid config {}", path.display()))
+    }
+
+    /// The user config (`~/.config/loctok/config.toml`) overlaid with `loctok.toml` in `root`.
+    fn discover(root: &Path) -> Result<Self> {
+        let user = std::env::var_os("XDG_CONFIG_HOME")
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))
+            .map(|dir| dir.join("loctok").join("config.toml"));
+        let mut config = FileConfig::default();
+        for path in user.into_iter().chain([root.join("loctok.toml")]) {
+            if path.is_file() {
+                config = FileConfig::load(&path)?.or(config);
+            }
+        }
+        Ok(config)
+    }
+
+    /// Fields set in `self` take precedence over `base`.
+    fn or(self, base: FileConfig) -> FileConfig {
+        FileConfig {
+            encoding: self.encoding.or(base.encoding),
+            ext: self.ext.or(base.ext),
+            always_include: self.always_include.or(base.always_include),
+            hidden: self.hidden.or(base.hidden),
+            format: self.format.or(base.format),
+        }
+    }
+
+    fn apply(self, args: &mut Cli, matches: &ArgMatches) {
+        let unset = |id: &str| matches.value_source(id) != Some(ValueSource::CommandLine);
+        if let Some(encoding) = self.encoding {
+            if unset("encoding") && unset("model") {
+                args.encoding = encoding;
+            }
+        }
+        if let Some(ext) = self.ext {
+            if unset("ext") {
+                args.ext = ext.join(",");
+            }
+        }
+        if let Some(globs) = self.always_include {
+            if unset("always_include") {
+                args.always_include = globs;
+            }
+        }
+        if let Some(hidden) = self.hidden {
+            if unset("hidden") {
+                args.hidden = hidden;
+            }
+        }
+        if let Some(format) = self.format {
+            if unset("format") {
+                args.format = format;
+            }
+        }
+    }
+}
+
+/// A shallow clone made for a repository URL path; deleted on drop unless `--keep-clone`.
+struct RemoteClone {
+    dir: PathBuf,
+    keep: bool,
+}
+
+impl Drop for RemoteClone {
+    fn drop(&mut self) {
+        if !self.keep {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+}
+
+/// Replace repository URLs among the paths with shallow clones in a temporary directory.
+/// The clones live as long as the returned guards.
+fn clone_remotes(args: &mut Cli) -> Result<Vec<RemoteClone>> {
+    let mut clones = Vec::new();
+    for (i, path) in args.paths.iter_mut().enumerate() {
+        let Some(url) = path.to_str().filter(|p| is_git_url(p)).map(str::to_string) else {
+            continue;
+        };
+        // Named after the repository so the tree root reads `repo`, not a temp name
+        let name = url
+            .trim_end_matches('/')
+            .rsplit(['/', ':'])
+            .next()
+            .map(|n| n.trim_end_matches(".git"))
+            .filter(|n| !n.is_empty())
+            .unwrap_or("repo");
+        let mut clone = RemoteClone {
+            dir: std::env::temp_dir().join(format!("loctok-{}-{i}", std::process::id())),
+            keep: false,
+        };
+        let dest = clone.dir.join(name);
+        if !args.quiet {
+            eprintln!("Cloning {url}");
+        }
+        shallow_clone(&url, args.git_ref.as_deref(), &dest)?;
+        if args.keep_clone {
+            clone.keep = true;
+            eprintln!("Keeping the clone at {}", dest.display());
+        }
+        *path = dest;
+        clones.push(clone);
+    }
+    if clones.is_empty() && (args.git_ref.is_some() || args.keep_clone) {
+        anyhow::bail!("--ref and --keep-clone need a repository URL as the path");
+    }
+    Ok(clones)
+}
+
+/// Directory searched for `loctok.toml`: the scanned directory, or a scanned file's parent.
+fn config_root(args: &Cli) -> &Path {
+    let root: &Path = match (&args.command, args.paths.as_slice()) {
+        (
+            Some(
+                Commands::Copy { path, .. }
+                | Commands::Staged { path }
+                | Commands::History { path, .. },
+            ),
+            _,
+        ) => path,
+        (None, [path]) => path,
+        _ => Path::new("."),
+    };
+    if root.is_file() {
+        root.parent()
+            .filter(|p| !p.as_os_str().is_empty())
+            .unwrap_or(Path::new("."))
+    } else {
+        root
+    }
+}
+
+/// Exit codes: 0 ok, 1 a `--fail-if-*` threshold was exceeded, 2 error.
+fn main() -> ExitCode {
+    match run().and_then(|code| finish_output().map(|()| code)) {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("Error: {err:?}");
+            ExitCode::from(2)
+        }
+    }
+}
+
+fn run() -> Result<ExitCode> {
+    let start = Instant::now();
+    let matches = Cli::command().get_matches();
+    let mut args = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    if let Some(path) = &args.output {
+        open_output(path, args.quiet)?;
+    }
+    if args.list_encodings {
+        for info in ENCODINGS {
+            outln!(
+                "{:<12} {:>8} tokens  {}",
+                info.name,
+                fmt_num(info.token_number, args.locale),
+                info.models.join(", ")
+            );
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+    if args.print_schema {
+        let schema = schemars::schema_for!(Report);
+        outln!("{}", serde_json::to_string_pretty(&schema)?);
+        return Ok(ExitCode::SUCCESS);
+    }
+    if args.list_languages {
+        let langs = known_languages();
+        let width = langs.keys().map(|l| l.width()).max().unwrap_or(0);
+        for (lang, exts) in &langs {
+            let exts: Vec<String> = exts.iter().map(|e| format!(".{e}")).collect();
+            outln!("{lang:<width$}  {}", exts.join(" "));
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+    // Before config discovery, so a `loctok.toml` in the cloned repository applies
+    let _clones = clone_remotes(&mut args)?;
+    if !args.no_config {
+        let config = match &args.config {
+            Some(path) => FileConfig::load(path)?,
+            None => FileConfig::discover(config_root(&args))?,
+        };
+        config.apply(&mut args, &matches);
+    }
+    if let Some(encoding) = args.model {
+        args.encoding = encoding.to_string();
+    }
+    if args.no_line_count {
+        args.show
+            .retain(|m| !matches!(m, Metric::Lines | Metric::TotalLines | Metric::BlankLines));
+        if args.show.is_empty() {
+            args.show.push(Metric::Tokens);
+        }
+    }
+    let locale = args.locale;
+    // Parse ext filter: comma-separated list; case-insensitive unless `--case-sensitive-ext`;
+    // strip leading dots.
+    // `!`-prefixed entries go to the exclude set, which wins over the include set.
+    let (include_exts, exclude_exts) = {
+        let mut include = std::collections::HashSet::new();
+        let mut exclude = std::collections::HashSet::new();
+        for part in args.ext.split(',') {
+            let part = part.trim();
+            let (set, p) = match part.strip_prefix('!') {
+                Some(rest) => (&mut exclude, rest),
+                None => (&mut include, part),
+            };
+            let p = p.trim().trim_start_matches('.');
+            let p = if args.case_sensitive_ext {
+                p.to_string()
+            } else {
+                p.to_ascii_lowercase()
+            };
+            if !p.is_empty() {
+                set.insert(p);
+            }
+        }
+        let non_empty = |set: std::collections::HashSet<String>| (!set.is_empty()).then_some(set);
+        (non_empty(include), non_empty(exclude))
+    };
+
+    let use_color = match args.color {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => {
+            let no_color = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+            !no_color && output_is_terminal()
+        }
+    };
+
+    // With several path arguments the tree is rooted at the current directory
+    let root: &Path = match args.paths.as_slice() {
+        [path] => path,
+        _ => Path::new("."),
+    };
+    let scan_root = match &args.command {
+        Some(
+            Commands::Copy { path, .. }
+            | Commands::Staged { path }
+            | Commands::History { path, .. },
+        ) => path,
+        None => root,
+    };
+    let cache_path = if args.no_cache {
+        None
+    } else {
+        args.cache.clone().or_else(|| default_cache_path(scan_root))
+    };
+
+    let quiet = args.quiet;
+    let opts = Options {
+        encoding: args.encoding.clone(),
+        encoding_file: args.encoding_file.clone(),
+        hf_tokenizer: args.hf_tokenizer.clone(),
+        include_hidden: args.hidden,
+        include_exts,
+        exclude_exts,
+        case_sensitive_ext: args.case_sensitive_ext,
+        dir_configs: !args.no_config && !args.no_dir_config,
+        include_langs: args.include_lang.as_deref().and_then(parse_lang_list),
+        exclude_langs: args.exclude_lang.as_deref().and_then(parse_lang_list),
+        always_include: args.always_include.clone(),
+        quiet: args.quiet,
+        on_warning: Some(WarningCallback::new(move |w: &Warning| {
+            // Files under --min-size are dropped on purpose; `skipped` still lists them
+            if !quiet && !matches!(w, Warning::TooSmall { .. }) {
+                eprintln!("warn: {w}");
+            }
+        })),
+        cache_path,
+        smart_language: args.smart_language,
+        include_vendored: args.include_vendored,
+        include_generated: args.include_generated,
+        git_only: args.git_only,
+        chunk_bytes: args.chunk_bytes.map(|n| n as usize),
+        estimate: args.estimate,
+        allow_special: !args.no_special_tokens,
+        normalize_whitespace: args.normalize_whitespace,
+        long_line_threshold: args.long_line_threshold,
+        exclude_common: args.exclude_common,
+        exclude_lockfiles: args.exclude_lockfiles,
+        exclude_empty: args.exclude_empty,
+        include_binary: args.include_binary,
+        sample_bytes: args.sample_bytes.map(|n| n as usize),
+        count_lines: !args.no_line_count,
+        min_file_bytes: args.min_size,
+        max_file_bytes: args.max_size,
+    };
+
+    // Subcommands return before the baseline is written; say so rather than ignore the flag
+    if args.update_baseline.is_some() {
+        let name = match &args.command {
+            Some(Commands::Copy { .. }) => Some("copy"),
+            Some(Commands::Staged { .. }) => Some("staged"),
+            Some(Commands::History { .. }) => Some("history"),
+            None => None,
+        };
+        if let Some(name) = name {
+            anyhow::bail!("--update-baseline cannot be used with the {name} subcommand");
+        }
+    }
+
+    // Handle subcommands first
+    if let Some(Commands::Copy {
+        path,
+        show,
+        copy_format,
+        no_tree,
+        no_tree_tokens,
+        path_prefix,
+        strip_comments,
+        sort,
+        max_lines_per_file,
+        tail_lines,
+        count_overhead,
+    }) = &args.command
+    {
+        // Copy mode: no progress UI (quiet) to keep stdout clean
+        let (mut result, mut texts) = count_tokens_and_collect_texts(path, &opts)
+            .with_context(|| format!("failed to scan {}", path.display()))?;
+        let before = result.total;
+        let mut rewritten = false;
+        if *strip_comments {
+            for (rel, text) in &mut texts {
+                if let Some(stripped) = loctok::strip_comments(rel, text) {
+                    *text = stripped;
+                    rewritten = true;
+                }
+            }
+        }
+        if let Some(head) = *max_lines_per_file {
+            for (_, text) in &mut texts {
+                if let Some(cut) = truncate_lines(text, head, tail_lines.unwrap_or(0)) {
+                    *text = cut;
+                    rewritten = true;
+                }
+            }
+        }
+        if opts.normalize_whitespace {
+            // Token counts already describe the normalized text
+            for (_, text) in &mut texts {
+                *text = normalize_whitespace(text);
+            }
+        }
+        if rewritten {
+            // Per-file counts now describe the payload text, keyed like `texts`
+            result.files = count_strs(&texts, &opts)?;
+            result.total = result.files.iter().map(|f| f.tokens).sum();
+        }
+        // Keyed like `texts`: relative to the scanned path
+        let tokens: std::collections::HashMap<PathBuf, usize> = result
+            .files
+            .iter()
+            .map(|f| {
+                let rel = f.path.strip_prefix(path).unwrap_or(&f.path);
+                (rel.to_path_buf(), f.tokens)
+            })
+            .collect();
+        if !matches!(sort, CopyOrder::Path) {
+            let key = |rel: &PathBuf| tokens.get(rel).copied().unwrap_or(0);
+            // Stable sort: equal counts stay in path order
+            match sort {
+                CopyOrder::TokensAsc => texts.sort_by_key(|(rel, _)| key(rel)),
+                _ => texts.sort_by_key(|(rel, _)| std::cmp::Reverse(key(rel))),
+            }
+        }
+        let content_tokens: Vec<usize> = texts
+            .iter()
+            .map(|(rel, _)| tokens.get(rel).copied().unwrap_or(0))
+            .collect();
+        let style = CopyStyle {
+            format: (*copy_format).into(),
+            tree: !no_tree,
+            path_prefix: path_prefix.clone(),
+            tree_tokens: (!no_tree_tokens).then_some(tokens),
+            locale,
+        };
+        let payload = build_copy_output_as(path, &texts, &style);
+        // Lines in payload are counted including empty lines, consistent with numbering
+        let sum_lines: usize = texts.iter().map(|(_, t)| t.lines().count()).sum();
+        let mut per_file = String::new();
+        let tokens = if *count_overhead {
+            // Sections are tokenized together here, so the split is approximate at the edges
+            let all = count_str("<payload>", &payload, &opts)?.tokens;
+            // Each file's own header, fence and line numbers: its section rendered alone,
+            // less its content and the wrapper (`<documents>`) every rendering carries. The
+            // tree and the one real wrapper make up the rest of the overhead
+            let section_style = CopyStyle {
+                tree: false,
+                tree_tokens: None,
+                ..style.clone()
+            };
+            let wrapper = build_copy_output_as(path, &[], &section_style);
+            let wrapper = count_str("<wrapper>", &wrapper, &opts)?.tokens;
+            let sections: Vec<(PathBuf, String)> = texts
+                .iter()
+                .map(|(rel, text)| {
+                    let one = [(rel.clone(), text.clone())];
+                    (
+                        rel.clone(),
+                        build_copy_output_as(path, &one, &section_style),
+                    )
+                })
+                .collect();
+            for (section, content) in count_strs(&sections, &opts)?.iter().zip(&content_tokens) {
+                per_file += &format!(
+                    "\n  {}: {} content + {} overhead",
+                    section.path.display(),
+                    fmt_num(*content, locale),
+                    fmt_num(section.tokens.saturating_sub(content + wrapper), locale)
+                );
+            }
+            format!(
+                "{} tokens: {} content + {} overhead",
+                fmt_num(all, locale),
+                fmt_num(result.total, locale),
+                fmt_num(all.saturating_sub(result.total), locale)
+            )
+        } else {
+            format!("{} tokens", fmt_num(result.total, locale))
+        };
+        let mut summary = format!(
+            "{} lines ({}, {} bytes)",
+            sum_lines,
+            tokens,
+            fmt_num(payload.len(), locale)
+        );
+        if *strip_comments || max_lines_per_file.is_some() {
+            let saved = before.saturating_sub(result.total);
+            let how = match (*strip_comments, max_lines_per_file.is_some()) {
+                (true, true) => "stripping comments and truncating",
+                (true, false) => "stripping comments",
+                _ => "truncating",
+            };
+            summary += &format!("; {how} saved {} tokens", fmt_num(saved, locale));
+        }
+        summary += &per_file;
+        match copy_to_clipboard(&payload) {
+            Ok(()) => {
+                if *show {
+                    out!("{}", payload);
+                }
+                println!("Copied {summary}");
+            }
+            // Headless sessions (SSH, CI) have no clipboard; hand the payload over on stdout
+            Err(err) => {
+                eprintln!("warn: {err:#}; writing the payload to stdout instead");
+                out!("{}", payload);
+                eprintln!("Printed {summary}");
+            }
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Some(Commands::Staged { path }) = &args.command {
+        let changes = count_staged(path, &opts)
+            .with_context(|| format!("failed to count staged changes in {}", path.display()))?;
+        let net: i64 = changes.iter().map(|c| c.delta()).sum();
+        match args.format {
+            OutputFormat::Json | OutputFormat::Yaml | OutputFormat::TreeJson => {
+                let report = serde_json::json!({
+                    "encoding": encoding_label(&args),
+                    "estimated": args.estimate,
+                    "net": net,
+                    "files": changes
+                        .iter()
+                        .map(|c| serde_json::json!({
+                            "path": c.path,
+                            "before": c.before,
+                            "after": c.after,
+                            "delta": c.delta()
+                        }))
+                        .collect::<Vec<_>>()
+                });
+                if !matches!(args.format, OutputFormat::Yaml) {
+                    outln!("{}", serde_json::to_string_pretty(&report)?);
+                } else {
+                    out!("{}", serde_yaml::to_string(&report)?);
+                }
+            }
+            OutputFormat::Svg => anyhow::bail!("the staged subcommand does not support svg"),
+            OutputFormat::Table | OutputFormat::Tree | OutputFormat::Bars => {
+                print_staged(&changes, net, locale);
+                if args.estimate {
+                    outln!("{ESTIMATE_NOTE}");
+                }
+            }
+        }
+        // In staged mode the total budget applies to the net growth of the commit
+        if let Some(max) = args.fail_if_total_over {
+            if net > 0 && net as usize > max {
+                eprintln!(
+                    "threshold exceeded: staged tokens +{} > {}",
+                    fmt_num(net as usize, locale),
+                    fmt_num(max, locale)
+                );
+                return Ok(ExitCode::from(1));
+            }
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Some(Commands::History { path, last }) = &args.command {
+        let commits = count_history(path, *last, &opts)
+            .with_context(|| format!("failed to count history of {}", path.display()))?;
+        match args.format {
+            OutputFormat::Json | OutputFormat::Yaml | OutputFormat::TreeJson => {
+                let report = serde_json::json!({
+                    "encoding": encoding_label(&args),
+                    "estimated": args.estimate,
+                    "commits": commits,
+                });
+                if !matches!(args.format, OutputFormat::Yaml) {
+                    outln!("{}", serde_json::to_string_pretty(&report)?);
+                } else {
+                    out!("{}", serde_yaml::to_string(&report)?);
+                }
+            }
+            OutputFormat::Svg => anyhow::bail!("the history subcommand does not support svg"),
+            OutputFormat::Table | OutputFormat::Tree | OutputFormat::Bars => {
+                print_history(&commits, use_color, locale);
+                if args.estimate {
+                    outln!("{ESTIMATE_NOTE}");
+                }
+            }
+        }
+        // In history mode the total budget applies to the newest commit
+        if let (Some(max), Some(newest)) = (args.fail_if_total_over, commits.last()) {
+            if newest.tokens > max {
+                eprintln!(
+                    "threshold exceeded: tokens at {} {} > {}",
+                    newest.commit,
+                    fmt_num(newest.tokens, locale),
+                    fmt_num(max, locale)
+                );
+                return Ok(ExitCode::from(1));
+            }
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if args.summary_only {
+        return run_summary(&args, &opts, root, start, use_color);
+    }
+    if !args.compare_encodings.is_empty() {
+        return run_compare(&args, &opts, root, use_color);
+    }
+
+    let mut result = if args.stdin {
+        let mut text = String::new();
+        io::stdin()
+            .read_to_string(&mut text)
+            .context("failed to read stdin as UTF-8 text")?;
+        let file = count_str("<stdin>", &text, &opts)?;
+        loctok::CountResult {
+            total: file.tokens,
+            files: vec![file],
+            skipped: Vec::new(),
+        }
+    } else if args.paths.len() > 1 {
+        count_paths(&args, &opts)?
+    } else if is_archive(root) {
+        count_archive(root, &opts)?
+    } else if shows_progress(&args) {
+        with_progress(&args, &opts, |opts, cb| {
+            count_tokens_in_path_with_progress(root, opts, Some(&cb))
+        })
+        .with_context(|| format!("failed to scan {}", root.display()))?
+    } else {
+        count_tokens_in_path(root, &opts)
+            .with_context(|| format!("failed to scan {}", root.display()))?
+    };
+    // Message framing the counts above do not see; part of `total` so budgets account for it
+    let overhead = overhead_tokens(&args, result.files.len());
+    result.total += overhead;
+
+    if let Some(path) = &args.update_baseline {
+        let report = build_report(&args, &result, start);
+        let json = serde_json::to_string_pretty(&report)? + "\n";
+        std::fs::write(path, &json)
+            .with_context(|| format!("failed to write baseline {}", path.display()))?;
+        if !args.quiet {
+            eprintln!("Wrote baseline to {}", path.display());
+        }
+    }
+
+    match args.format {
+        OutputFormat::Json => {
+            let report = build_report(&args, &result, start);
+            outln!("{}", serde_json::to_string_pretty(&report)?);
+        }
+        OutputFormat::Yaml => {
+            // Same structure and field names as the JSON output
+            let report = build_report(&args, &result, start);
+            out!("{}", serde_yaml::to_string(&report)?);
+        }
+        OutputFormat::Table => {
+            // Default mode: always show by-language table
+            print_stats(start, result.files.len());
+            let (key_header, rows) = match args.group_by {
+                GroupBy::Language => ("Language", aggregate_by_language(&result.files)),
+                // Extension rows fill the same columns, keyed by extension
+                GroupBy::Extension => (
+                    "Extension",
+                    aggregate_by_extension(&result.files)
+                        .into_iter()
+                        .map(|e| loctok::LangSummary {
+                            language: e.extension,
+                            files: e.files,
+                            lines: e.lines,
+                            total_lines: e.total_lines,
+                            blank_lines: e.blank_lines,
+                            tokens: e.tokens,
+                            bytes: e.bytes,
+                            words: e.words,
+                            chars: e.chars,
+                            lines_pct: e.lines_pct,
+                            tokens_pct: e.tokens_pct,
+                        })
+                        .collect(),
+                ),
+            };
+            print_by_language_table(
+                &rows,
+                key_header,
+                use_color,
+                &args.show,
+                overhead,
+                locale,
+                !args.no_total,
+            );
+            if args.explain_others {
+                print_unmatched_table(&result, use_color, locale);
+            }
+            if let Some(n) = args.largest {
+                print_largest_table(&args, &result, n, use_color, locale);
+            }
+            for note in accuracy_notes(&args) {
+                outln!("{note}");
+            }
+            // println!("Total tokens: {}", fmt_num(result.total));
+            // if let Some(info) = encoding_info(&args.encoding) {
+            //     let models = info.models.join(", ");
+            //     println!(
+            //         "Encoding: {} | Models: {} | Token number: {}",
+            //         args.encoding,
+            //         models,
+            //         fmt_num(info.token_number)
+            //     );
+            // }
+        }
+        OutputFormat::Bars => {
+            print_stats(start, result.files.len());
+            print_bars(&result, use_color, locale, output_width(&args));
+        }
+        OutputFormat::TreeJson => {
+            let tree = output_tree(&args, root, &result.files);
+            outln!("{}", serde_json::to_string_pretty(&tree)?);
+        }
+        OutputFormat::Svg => {
+            let tree = output_tree(&args, root, &result.files);
+            out!("{}", render_treemap_svg(&tree, locale));
+        }
+        OutputFormat::Tree => {
+            print_stats(start, result.files.len());
+            print_tree(
+                &output_tree(&args, root, &result.files),
+                use_color,
+                &args.show,
+                locale,
+                !args.no_total,
+                args.tree_files_only,
+                output_width(&args),
+            );
+            let notes = accuracy_notes(&args);
+            if !notes.is_empty() {
+                outln!("\n{}", notes.join("\n"));
+            }
+            // if let Some(info) = encoding_info(&args.encoding) {
+            //     let models = info.models.join(", ");
+            //     println!(
+            //         "\nEncoding: {} | Models: {} | Token number: {}",
+            //         args.encoding,
+            //         models,
+            //         fmt_num(info.token_number)
+            //     );
+            // }
+        }
+    }
+
+    let langs = aggregate_by_language(&result.files);
+    Ok(check_thresholds(
+        &args,
+        result.total,
+        &langs,
+        &result.skipped,
+    ))
+}
+
+/// `--compare-encodings`: per-language tokens under each encoding. Files are read once
+/// with the first encoding and their texts re-counted with the others.
+fn run_compare(args: &Cli, opts: &Options, root: &Path, use_color: bool) -> Result<ExitCode> {
+    let encodings = &args.compare_encodings;
+    if encodings.len() < 2 {
+        anyhow::bail!("--compare-encodings needs at least two encodings");
+    }
+    if args.paths.len() > 1 {
+        anyhow::bail!("--compare-encodings takes a single path");
+    }
+    if !matches!(args.format, OutputFormat::Table | OutputFormat::Json) {
+        anyhow::bail!("--compare-encodings supports the table and json formats");
+    }
+    let first = Options {
+        encoding: encodings[0].clone(),
+        ..opts.clone()
+    };
+    let (result, texts) = count_tokens_and_collect_texts(root, &first)
+        .with_context(|| format!("failed to scan {}", root.display()))?;
+    let rows = aggregate_by_language(&result.files);
+    // Language -> tokens for each encoding after the first
+    let mut others = Vec::new();
+    for encoding in &encodings[1..] {
+        let opts = Options {
+            encoding: encoding.clone(),
+            ..opts.clone()
+        };
+        let mut by_lang = std::collections::HashMap::new();
+        for file in count_strs(&texts, &opts)? {
+            *by_lang.entry(file.language).or_insert(0usize) += file.tokens;
+        }
+        others.push(by_lang);
+    }
+    let tokens_of = |r: &loctok::LangSummary| -> Vec<usize> {
+        std::iter::once(r.tokens)
+            .chain(
+                others
+                    .iter()
+                    .map(|m| m.get(&r.language).copied().unwrap_or(0)),
+            )
+            .collect()
+    };
+    let mut totals = vec![0usize; encodings.len()];
+    for r in &rows {
+        for (total, n) in totals.iter_mut().zip(tokens_of(r)) {
+            *total += n;
+        }
+    }
+
+    if matches!(args.format, OutputFormat::Json) {
+        let report = serde_json::json!({
+            "encodings": encodings,
+            "total": totals,
+            "by_language": rows
+                .iter()
+                .map(|r| serde_json::json!({
+                    "language": r.language,
+                    "files": r.files,
+                    "lines": r.lines,
+                    "tokens": tokens_of(r)
+                }))
+                .collect::<Vec<_>>()
+        });
+        outln!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(check_thresholds(args, totals[0], &rows, &result.skipped));
+    }
+
+    let locale = args.locale;
+    // Each later encoding's tokens relative to the first, e.g. 0.912 means 8.8% fewer
+    let ratios = |tokens: &[usize]| -> Vec<String> {
+        tokens[1..]
+            .iter()
+            .map(|&n| match tokens[0] {
+                0 => String::new(),
+                base => format!("{:.3}", n as f64 / base as f64),
+            })
+            .collect()
+    };
+    let mut builder = Builder::default();
+    let mut header = vec![
+        "Language".to_string(),
+        "files".to_string(),
+        "lines of code".to_string(),
+    ];
+    header.extend(encodings.iter().cloned());
+    header.extend(
+        encodings[1..]
+            .iter()
+            .map(|e| format!("{e} / {}", encodings[0])),
+    );
+    builder.push_record(header);
+    let mut record = |label: &str, files: usize, lines: usize, tokens: &[usize]| {
+        let mut record = vec![
+            label.to_string(),
+            fmt_num(files, locale),
+            fmt_num(lines, locale),
+        ];
+        record.extend(tokens.iter().map(|&n| fmt_num(n, locale)));
+        record.extend(ratios(tokens));
+        builder.push_record(record);
+    };
+    for r in &rows {
+        record(&r.language, r.files, r.lines, &tokens_of(r));
+    }
+    if !args.no_total {
+        let files = rows.iter().map(|r| r.files).sum();
+        let lines = rows.iter().map(|r| r.lines).sum();
+        record("SUM:", files, lines, &totals);
+    }
+
+    let mut table = builder.build();
+    if use_color {
+        table.with(Style::rounded());
+    } else {
+        table.with(
+            Style::ascii()
+                .remove_horizontal()
+                .horizontals([(1, HorizontalLine::inherit(Style::ascii()))]),
+        );
+    }
+    table.with(Modify::new(Columns::new(1..)).with(Alignment::right()));
+    outln!("{}", table);
+    // Limits apply to the first encoding, the one the scan itself used
+    Ok(check_thresholds(args, totals[0], &rows, &result.skipped))
+}
+
+/// `--summary-only`: by-language totals without keeping a record per file.
+fn run_summary(
+    args: &Cli,
+    opts: &Options,
+    root: &Path,
+    start: Instant,
+    use_color: bool,
+) -> Result<ExitCode> {
+    if args.paths.len() > 1 {
+        anyhow::bail!("--summary-only takes a single path");
+    }
+    if !matches!(
+        args.format,
+        OutputFormat::Table | OutputFormat::Json | OutputFormat::Yaml
+    ) {
+        anyhow::bail!("--summary-only supports the table, json and yaml formats");
+    }
+    let mut summary = if is_archive(root) {
+        count_archive(root, opts).map(|result| loctok::SummaryResult {
+            total: result.total,
+            by_language: aggregate_by_language(&result.files),
+            skipped: result.skipped,
+        })
+    } else if shows_progress(args) {
+        with_progress(args, opts, |opts, cb| {
+            count_summary_with_progress(root, opts, Some(&cb))
+        })
+    } else {
+        count_summary(root, opts)
+    }
+    .with_context(|| format!("failed to scan {}", root.display()))?;
+    let files = summary.by_language.iter().map(|l| l.files).sum();
+    let overhead = overhead_tokens(args, files);
+    summary.total += overhead;
+
+    if matches!(args.format, OutputFormat::Table) {
+        print_stats(start, files);
+        print_by_language_table(
+            &summary.by_language,
+            "Language",
+            use_color,
+            &args.show,
+            overhead,
+            args.locale,
+            !args.no_total,
+        );
+        for note in accuracy_notes(args) {
+            outln!("{note}");
+        }
+    } else {
+        let elapsed = args.stats.then(|| start.elapsed());
+        let report = SummaryReport {
+            encoding: encoding_label(args),
+            estimated: args.estimate,
+            sample_bytes: args.sample_bytes,
+            total: summary.total,
+            content_tokens: summary.total - overhead,
+            by_language: summary.by_language.clone(),
+            skipped: report_skips(args, &summary.skipped),
+            elapsed_ms: elapsed.map(|e| e.as_millis() as u64),
+            files_per_sec: elapsed.map(|e| files as f64 / e.as_secs_f64()),
+        };
+        if matches!(args.format, OutputFormat::Json) {
+            outln!("{}", serde_json::to_string_pretty(&report)?);
+        } else {
+            out!("{}", serde_yaml::to_string(&report)?);
+        }
+    }
+    Ok(check_thresholds(
+        args,
+        summary.total,
+        &summary.by_language,
+        &summary.skipped,
+    ))
+}
+
+fn shows_progress(args: &Cli) -> bool {
+    args.progress && !matches!(args.progress_to, ProgressSink::None)
+}
+
+/// Run `scan` with the `--progress` display on the `--progress-to` sink (a bar on a
+/// terminal, plain or JSON lines otherwise), clearing the bar before returning.
+fn with_progress<T>(
+    args: &Cli,
+    opts: &Options,
+    scan: impl FnOnce(&Options, &(dyn Fn(usize, usize) + Send + Sync)) -> Result<T>,
+) -> Result<T> {
+    use std::sync::{Arc, Mutex};
+
+    let (out, is_tty, target): (Box<dyn Write + Send>, bool, fn() -> ProgressDrawTarget) =
+        match &args.progress_to {
+            ProgressSink::Stdout => (
+                Box::new(io::stdout()),
+                io::stdout().is_terminal(),
+                ProgressDrawTarget::stdout,
+            ),
+            ProgressSink::File(path) => {
+                let file = std::fs::File::create(path).with_context(|| {
+                    format!("failed to create progress file {}", path.display())
+                })?;
+                (Box::new(file), false, ProgressDrawTarget::hidden)
+            }
+            // `none` never gets here; see `shows_progress`
+            ProgressSink::Stderr | ProgressSink::None => (
+                Box::new(io::stderr()),
+                io::stderr().is_terminal(),
+                ProgressDrawTarget::stderr,
+            ),
+        };
+
+    // Throttle progress prints to avoid flooding the terminal
+    struct ProgState {
+        started: Instant,
+        last: Instant,
+        last_done: usize,
+        out: Box<dyn Write + Send>,
+    }
+    let state = Arc::new(Mutex::new(ProgState {
+        started: Instant::now(),
+        last: Instant::now(),
+        last_done: 0,
+        out,
+    }));
+    let progress_format = args.progress_format;
+    let locale = args.locale;
+    // Only human output on a terminal gets a bar; JSON events are always line-delimited
+    let bar = (is_tty && matches!(progress_format, ProgressFormat::Human)).then(|| {
+        let style = ProgressStyle::with_template(
+            "Scanning… {bar:30} {pos}/{len} ({percent}%) {rate} ETA {eta}",
+        )
+        .expect("valid progress template")
+        .with_key(
+            "rate",
+            |state: &ProgressState, w: &mut dyn std::fmt::Write| {
+                let _ = write!(w, "{:.0} files/s", state.per_sec());
+            },
+        );
+        ProgressBar::with_draw_target(Some(0), target()).with_style(style)
+    });
+    let bar_cloned = bar.clone();
+    let progress_cb = move |done: usize, total: usize| {
+        if let Some(bar) = &bar_cloned {
+            // indicatif throttles redraws itself
+            bar.set_length(total as u64);
+            bar.set_position(done as u64);
+            return;
+        }
+        let mut s = state.lock().unwrap();
+        let now = Instant::now();
+        // Print at most every ~200ms or each 1% or on start/end
+        let step = std::cmp::max(1, total / 100);
+        let due = done == 0
+            || done == total
+            || done.saturating_sub(s.last_done) >= step
+            || now.duration_since(s.last).as_millis() >= 200;
+        if due {
+            let pct = if total > 0 {
+                (done as f64 * 100.0 / total as f64).round()
+            } else {
+                100.0
+            };
+            if matches!(progress_format, ProgressFormat::Json) {
+                // One NDJSON event per update for wrappers driving their own UI
+                let _ = writeln!(s.out, "{{\"done\":{},\"total\":{}}}", done, total);
+            } else {
+                // Rate and ETA from the overall elapsed time since the scan started
+                let secs = now.duration_since(s.started).as_secs_f64();
+                let rate = if secs > 0.0 { done as f64 / secs } else { 0.0 };
+                let eta = if rate > 0.0 {
+                    format!(" ETA {}", fmt_eta(total.saturating_sub(done) as f64 / rate))
+                } else {
+                    String::new()
+                };
+                let msg = format!(
+                    "Scanning… {}/{} ({}%) {} files/s{}",
+                    done,
+                    total,
+                    pct as u64,
+                    fmt_num(rate.round() as usize, locale),
+                    eta
+                );
+                // Non-TTY: print each update on its own line
+                let _ = writeln!(s.out, "{}", msg);
+            }
+            s.last = now;
+            s.last_done = done;
+        }
+    };
+
+    // Warnings print above the bar instead of through it
+    let opts = match (&bar, &opts.on_warning) {
+        (Some(bar), Some(inner)) => {
+            let (bar, inner) = (bar.clone(), inner.clone());
+            Options {
+                on_warning: Some(WarningCallback::new(move |w: &Warning| {
+                    bar.suspend(|| (inner.0)(w))
+                })),
+                ..opts.clone()
+            }
+        }
+        _ => opts.clone(),
+    };
+    let res = scan(&opts, &progress_cb);
+
+    // Clear the bar before printing results
+    if let Some(bar) = bar {
+        bar.finish_and_clear();
+    }
+    res
+}
+
+/// Evaluate every `--fail-if-*` rule, reporting each violation on stderr.
+fn check_thresholds(
+    args: &Cli,
+    total: usize,
+    langs: &[loctok::LangSummary],
+    skipped: &[Warning],
+) -> ExitCode {
+    let locale = args.locale;
+    let mut exceeded = false;
+    let fail_on_skipped = match (&args.fail_on_skipped, args.fail_on_walk_error) {
+        (Some(reasons), true) if !reasons.is_empty() => {
+            Some([reasons.as_slice(), &[SkipReason::Walk]].concat())
+        }
+        (Some(reasons), _) => Some(reasons.clone()),
+        (None, true) => Some(vec![SkipReason::Walk]),
+        (None, false) => None,
+    };
+    let walk_errors = skipped
+        .iter()
+        .filter(|w| matches!(w, Warning::Walk { .. }))
+        .count();
+    if walk_errors > 0 && fail_on_skipped.is_none() && !args.quiet {
+        // Each entry was already reported as it happened; this keeps them from scrolling by
+        eprintln!(
+            "warn: unreadable entries skipped by the walk: {} (files below them are not counted)",
+            fmt_num(walk_errors, locale)
+        );
+    }
+    if let Some(max) = args.fail_if_total_over {
+        if total > max {
+            eprintln!(
+                "threshold exceeded: total tokens {} > {}",
+                fmt_num(total, locale),
+                fmt_num(max, locale)
+            );
+            exceeded = true;
+        }
+    }
+    if !args.fail_if_lang_over.is_empty() {
+        for rule in &args.fail_if_lang_over {
+            let tokens = langs
+                .iter()
+                .find(|l| l.language.eq_ignore_ascii_case(&rule.language))
+                .map_or(0, |l| l.tokens);
+            if tokens > rule.max_tokens {
+                eprintln!(
+                    "threshold exceeded: {} tokens {} > {}",
+                    rule.language,
+                    fmt_num(tokens, locale),
+                    fmt_num(rule.max_tokens, locale)
+                );
+                exceeded = true;
+            }
+        }
+    }
+    if let Some(reasons) = &fail_on_skipped {
+        // No listed reasons means any skip counts
+        let failing: Vec<&Warning> = skipped
+            .iter()
+            .filter(|w| reasons.is_empty() || reasons.iter().any(|r| r.matches(w)))
+            .collect();
+        for warning in &failing {
+            eprintln!("skipped: {warning}");
+        }
+        if !failing.is_empty() {
+            eprintln!(
+                "strict scan failed: {} skipped",
+                fmt_num(failing.len(), locale)
+            );
+            exceeded = true;
+        }
+    }
+    if exceeded {
+        ExitCode::from(1)
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+/// Model name (or family prefix) to encoding, checked in order.
+const MODEL_ENCODINGS: &[(&str, &str)] = &[
+    ("gpt-5", "o200k_base"),
+    ("gpt-4.1", "o200k_base"),
+    ("gpt-4o", "o200k_base"),
+    ("o1", "o200k_base"),
+    ("o3", "o200k_base"),
+    ("o4-mini", "o200k_base"),
+    ("gpt-4", "cl100k_base"),
+    ("gpt-3.5-turbo", "cl100k_base"),
+    ("text-embedding-3-small", "cl100k_base"),
+    ("text-embedding-3-large", "cl100k_base"),
+    ("text-embedding-ada-002", "cl100k_base"),
+    ("text-davinci-003", "p50k_base"),
+    ("text-davinci-002", "p50k_base"),
+    ("code-davinci-002", "p50k_base"),
+    ("text-davinci-edit-001", "p50k_edit"),
+    ("code-davinci-edit-001", "p50k_edit"),
+    ("davinci", "r50k_base"),
+    ("gpt2", "r50k_base"),
+];
+
+/// Lowercased language names from a comma-separated list; `None` when it names none.
+fn parse_lang_list(s: &str) -> Option<std::collections::HashSet<String>> {
+    let set: std::collections::HashSet<String> = s
+        .split(',')
+        .map(|part| part.trim().to_lowercase())
+        .filter(|part| !part.is_empty())
+        .collect();
+    (!set.is_empty()).then_some(set)
+}
+
+/// Resolve `--model`; dated or sized variants like "gpt-4o-mini" match their family.
+fn parse_model(s: &str) -> Result<&'static str, String> {
+    let model = s.trim().to_ascii_lowercase();
+    MODEL_ENCODINGS
+        .iter()
+        .find(|(name, _)| {
+            model == *name
+                || model
+                    .strip_prefix(name)
+                    .is_some_and(|rest| rest.starts_with('-'))
+        })
+        .map(|(_, encoding)| *encoding)
+        .ok_or_else(|| {
+            let names: Vec<&str> = MODEL_ENCODINGS.iter().map(|(name, _)| *name).collect();
+            format!("unknown model `{s}`; supported: {}", names.join(", "))
+        })
+}
+
+/// A `LANG=N` per-language token limit.
+#[derive(Clone, Debug)]
+struct LangThreshold {
+    language: String,
+    max_tokens: usize,
+}
+
+fn parse_lang_threshold(s: &str) -> Result<LangThreshold, String> {
+    let (lang, n) = s
+        .rsplit_once('=')
+        .ok_or_else(|| format!("expected LANG=N, got `{s}`"))?;
+    let lang = lang.trim();
+    if lang.is_empty() {
+        return Err(format!("missing language in `{s}`"));
+    }
+    let max_tokens = n
+        .trim()
+        .parse::<usize>()
+        .map_err(|e| format!("invalid token limit in `{s}`: {e}"))?;
+    Ok(LangThreshold {
+        language: lang.to_string(),
+        max_tokens,
+    })
+}
+
+/// Count several path arguments: files directly, directories with the usual walk.
+fn count_paths(args: &Cli, opts: &Options) -> Result<loctok::CountResult> {
+    let mut result = loctok::CountResult::default();
+    for path in &args.paths {
+        if is_archive(path) {
+            result = result.merge(count_archive(path, opts)?);
+            continue;
+        }
+        if path.is_dir() {
+            // Each root keeps its own cache file, as when scanned alone
+            let opts = Options {
+                cache_path: if args.no_cache {
+                    None
+                } else {
+                    args.cache.clone().or_else(|| default_cache_path(path))
+                },
+                ..opts.clone()
+            };
+            let scanned = count_tokens_in_path(path, &opts)
+                .with_context(|| format!("failed to scan {}", path.display()))?;
+            result = result.merge(scanned);
+            continue;
+        }
+        if !path.exists() {
+            anyhow::bail!("{} does not exist", path.display());
+        }
+        // Same filters as a file given alone, which is scanned like a one-file tree
+        if filtered_paths(path, opts)?.is_empty() {
+            continue;
+        }
+        match count_file(path, opts) {
+            Ok(file) => {
+                result.total += file.tokens;
+                result.files.push(file);
+            }
+            // Binary or unreadable files in a glob are skipped like during a walk
+            Err(err) => {
+                let warning = Warning::Read {
+                    path: path.clone(),
+                    message: format!("{err:#}"),
+                };
+                if let Some(cb) = &opts.on_warning {
+                    (cb.0)(&warning);
+                }
+                result.skipped.push(warning);
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// Tokens added by `--chat-overhead` (per file) and `--prompt-overhead` (once).
+fn overhead_tokens(args: &Cli, files: usize) -> usize {
+    args.chat_overhead * files + args.prompt_overhead
+}
+
+/// Footer for human-readable output when `--estimate` is on.
+const ESTIMATE_NOTE: &str = "note: token counts are estimated (chars / 4), not exact";
+
+/// Footers for human-readable scan output when counts are not exact.
+fn accuracy_notes(args: &Cli) -> Vec<String> {
+    let mut notes = Vec::new();
+    if args.estimate {
+        notes.push(ESTIMATE_NOTE.to_string());
+    }
+    if let Some(n) = args.sample_bytes {
+        notes.push(format!(
+            "note: files over {n} bytes were sampled; their counts are extrapolated"
+        ));
+    }
+    notes
+}
+
+/// The encoding named in reports: the tokenizer or vocabulary file when one is given.
+fn encoding_label(args: &Cli) -> String {
+    match args.hf_tokenizer.as_ref().or(args.encoding_file.as_ref()) {
+        Some(path) => path.display().to_string(),
+        None => args.encoding.clone(),
+    }
+}
+
+/// The report printed by `--format json` and `--format yaml`.
+#[derive(serde::Serialize, schemars::JsonSchema)]
+struct Report {
+    /// Encoding name, or the vocabulary / tokenizer file counted with
+    encoding: String,
+    /// Whether tokens are the chars / 4 estimate rather than exact counts
+    estimated: bool,
+    /// Per-file byte limit from `--sample-bytes`; counts of larger files are extrapolated
+    sample_bytes: Option<u64>,
+    /// All tokens, including chat and prompt overhead
+    total: usize,
+    /// Tokens of the files alone
+    content_tokens: usize,
+    chat_overhead: ChatOverhead,
+    prompt_overhead: usize,
+    files: Vec<ReportFile>,
+    by_language: Vec<loctok::LangSummary>,
+    /// Totals per lowercased extension, with `--group-by extension`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    by_extension: Option<Vec<loctok::ExtSummary>>,
+    skipped: Vec<ReportSkip>,
+    /// Files counted as "Others", keyed by extension (or file name when there is none)
+    unmatched_extensions: std::collections::BTreeMap<String, ReportUnmatched>,
+    /// The files with the most tokens, largest first, with `--largest N`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    largest_files: Option<Vec<ReportLargest>>,
+    /// Wall time from startup to the report, with `--stats`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    elapsed_ms: Option<u64>,
+    /// Files counted per second of `elapsed_ms`, with `--stats`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    files_per_sec: Option<f64>,
+}
+
+/// The `--summary-only` counterpart of `Report`, without per-file entries.
+#[derive(serde::Serialize)]
+struct SummaryReport {
+    encoding: String,
+    estimated: bool,
+    sample_bytes: Option<u64>,
+    total: usize,
+    content_tokens: usize,
+    by_language: Vec<loctok::LangSummary>,
+    skipped: Vec<ReportSkip>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    elapsed_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    files_per_sec: Option<f64>,
+}
+
+#[derive(serde::Serialize, schemars::JsonSchema)]
+struct ChatOverhead {
+    per_file: usize,
+    tokens: usize,
+}
+
+#[derive(serde::Serialize, schemars::JsonSchema)]
+struct ReportFile {
+    path: PathBuf,
+    tokens: usize,
+    lines: usize,
+    total_lines: usize,
+    blank_lines: usize,
+    words: usize,
+    chars: usize,
+    max_line_len: usize,
+    long_lines: usize,
+    /// Counts were extrapolated from the first `sample_bytes` of the file
+    sampled: bool,
 }
 
-#[derive(Subcommand, Debug)]
-enum Commands {
-    /// Concatenate filtered files into a single clipboard-friendly payload
-    Copy {
-        /// Root path to scan (defaults to current directory)
-        #[arg(default_value = ".")]
-        path: PathBuf,
-        /// Also print the copied content to stdout
-        #[arg(long, action = ArgAction::SetTrue)]
-        show: bool,
-    },
+#[derive(serde::Serialize, schemars::JsonSchema)]
+struct ReportSkip {
+    /// Missing for walk errors that have no single path
+    path: Option<PathBuf>,
+    reason: String,
 }
 
-fn main() -> Result<()> {
-    let start = Instant::now();
-    let args = Cli::parse();
-    // Helper: map encoding name to token number and model families
-    struct EncodingInfo {
-        token_number: usize,
-        models: &'static [&'static str],
-    }
-    fn encoding_info(enc: &str) -> Option<EncodingInfo> {
-        match enc {
-            "o200k_base" => Some(EncodingInfo {
-                token_number: 200_000,
-                models: &["GPT-4o", "GPT-4.1", "o1", "o3", "o4"],
-            }),
-            "cl100k_base" => Some(EncodingInfo {
-                token_number: 100_000,
-                models: &["ChatGPT", "text-embedding-ada-002"],
-            }),
-            "p50k_base" => Some(EncodingInfo {
-                token_number: 50_000,
-                models: &["Code models", "text-davinci-002", "text-davinci-003"],
-            }),
-            "p50k_edit" => Some(EncodingInfo {
-                token_number: 50_000,
-                models: &["text-davinci-edit-001", "code-davinci-edit-001"],
-            }),
-            "r50k_base" => Some(EncodingInfo {
-                token_number: 50_000,
-                models: &["GPT-3 (davinci)"],
-            }),
-            _ => None,
-        }
-    }
-    // Parse ext filter: comma-separated list; case-insensitive; strip leading dots
-    let include_exts = {
-        let s = args.ext.trim();
-        if s.is_empty() {
-            None
-        } else {
-            let mut set = std::collections::HashSet::new();
-            for part in s.split(',') {
-                let p = part.trim().trim_start_matches('.').to_ascii_lowercase();
-                if !p.is_empty() {
-                    set.insert(p);
-                }
-            }
-            if set.is_empty() {
-                None
-            } else {
-                Some(set)
-            }
-        }
-    };
+#[derive(serde::Serialize, schemars::JsonSchema)]
+struct ReportLargest {
+    path: PathBuf,
+    language: String,
+    lines: usize,
+    tokens: usize,
+}
 
-    let opts = Options {
-        encoding: args.encoding.clone(),
-        include_hidden: args.hidden,
-        include_exts,
-    };
+#[derive(serde::Serialize, schemars::JsonSchema)]
+struct ReportUnmatched {
+    files: usize,
+    tokens: usize,
+}
 
-    // Handle subcommands first
-    if let Some(Commands::Copy { path, show }) = &args.command {
-        // Copy mode: no progress UI (quiet) to keep stdout clean
-        let result = count_tokens_in_path(path, &opts)
-            .with_context(|| format!("failed to scan {}", path.display()))?;
-        let texts = collect_filtered_texts(path, &opts)?;
-        let payload = build_copy_output(path, &texts);
-        copy_to_clipboard(&payload)?;
-        if *show {
-            print!("{}", payload);
+/// `path` as printed: canonicalized with `--absolute-paths` when it exists, else unchanged.
+fn output_path(args: &Cli, path: &Path) -> PathBuf {
+    if args.absolute_paths {
+        if let Ok(abs) = std::fs::canonicalize(path) {
+            return abs;
         }
-        // Lines in payload are counted including empty lines, consistent with numbering
-        let sum_lines: usize = texts.iter().map(|(_, t)| t.lines().count()).sum();
-        println!(
-            "Copied {} lines ({} tokens, {} bytes)",
-            sum_lines,
-            fmt_num(result.total),
-            fmt_num(payload.len())
-        );
-        return Ok(());
     }
+    path.to_path_buf()
+}
+
+/// The tree of `files` under `root` as rendered, cut to `--tree-depth`. With
+/// `--absolute-paths` the root is labeled with its full path.
+fn output_tree(args: &Cli, root: &Path, files: &[loctok::FileCount]) -> TreeNode {
+    // Built from the paths as scanned so grouping is unaffected; only the label changes
+    let mut tree = build_tree(root, files);
+    if let Some(depth) = args.tree_depth {
+        tree.prune(depth);
+    }
+    if args.absolute_paths {
+        tree.name = output_path(args, root).display().to_string();
+    }
+    tree
+}
 
-    let result = if args.progress {
-        use std::sync::{Arc, Mutex};
-        use std::time::Instant;
+fn report_skips(args: &Cli, skipped: &[Warning]) -> Vec<ReportSkip> {
+    skipped
+        .iter()
+        .map(|w| ReportSkip {
+            path: w.path().map(|p| output_path(args, p)),
+            reason: w.to_string(),
+        })
+        .collect()
+}
 
-        // Throttle progress prints to avoid flooding the terminal
-        struct ProgState {
-            last: Instant,
-            last_done: usize,
-            last_len: usize,
-        }
-        let state = Arc::new(Mutex::new(ProgState {
-            last: Instant::now(),
-            last_done: 0,
-            last_len: 0,
-        }));
-        let state_cloned = state.clone();
-        let is_tty = io::stderr().is_terminal();
-        let progress_cb = move |done: usize, total: usize| {
-            let mut s = state_cloned.lock().unwrap();
-            let now = Instant::now();
-            // Print at most every ~200ms or each 1% or on start/end
-            let step = std::cmp::max(1, total / 100);
-            let due = done == 0
-                || done == total
-                || done.saturating_sub(s.last_done) >= step
-                || now.duration_since(s.last).as_millis() >= 200;
-            if due {
-                let pct = if total > 0 {
-                    (done as f64 * 100.0 / total as f64).round()
-                } else {
-                    100.0
+fn build_report(args: &Cli, result: &loctok::CountResult, start: Instant) -> Report {
+    let overhead = overhead_tokens(args, result.files.len());
+    let elapsed = args.stats.then(|| start.elapsed());
+    Report {
+        encoding: encoding_label(args),
+        estimated: args.estimate,
+        sample_bytes: args.sample_bytes,
+        total: result.total,
+        content_tokens: result.total - overhead,
+        chat_overhead: ChatOverhead {
+            per_file: args.chat_overhead,
+            tokens: args.chat_overhead * result.files.len(),
+        },
+        prompt_overhead: args.prompt_overhead,
+        files: result
+            .files
+            .iter()
+            .map(|f| ReportFile {
+                path: output_path(args, &f.path),
+                tokens: f.tokens,
+                lines: f.lines,
+                total_lines: f.total_lines,
+                blank_lines: f.blank_lines,
+                words: f.words,
+                chars: f.chars,
+                max_line_len: f.max_line_len,
+                long_lines: f.long_lines,
+                sampled: f.sampled,
+            })
+            .collect(),
+        by_language: aggregate_by_language(&result.files),
+        by_extension: (args.group_by == GroupBy::Extension)
+            .then(|| aggregate_by_extension(&result.files)),
+        skipped: report_skips(args, &result.skipped),
+        unmatched_extensions: unmatched_extensions(&result.files)
+            .into_iter()
+            .map(|u| {
+                let counts = ReportUnmatched {
+                    files: u.files,
+                    tokens: u.tokens,
                 };
-                if is_tty {
-                    // In-place update on a single line; clear any leftovers
-                    let msg = format!("Scanning… {}/{} files ({}%)", done, total, pct as u64);
-                    let pad = s.last_len.saturating_sub(msg.chars().count());
-                    // Use CR, then message, then spaces to clear previous, no newline
-                    eprint!("\r{}{}", msg, " ".repeat(pad));
-                    let _ = io::stderr().flush();
-                    s.last_len = msg.chars().count();
-                } else {
-                    // Non-TTY: print each update on its own line
-                    eprintln!("Scanning… {}/{} files ({}%)", done, total, pct as u64);
-                }
-                s.last = now;
-                s.last_done = done;
-            }
-        };
-
-        let res = count_tokens_in_path_with_progress(&args.path, &opts, Some(&progress_cb))
-            .with_context(|| format!("failed to scan {}", args.path.display()))?;
+                (u.extension, counts)
+            })
+            .collect(),
+        largest_files: args.largest.map(|n| {
+            largest_files(&result.files, n)
+                .into_iter()
+                .map(|f| ReportLargest {
+                    path: output_path(args, &f.path),
+                    language: f.language.clone(),
+                    lines: f.lines,
+                    tokens: f.tokens,
+                })
+                .collect()
+        }),
+        elapsed_ms: elapsed.map(|e| e.as_millis() as u64),
+        files_per_sec: elapsed.map(|e| result.files.len() as f64 / e.as_secs_f64()),
+    }
+}
 
-        // Clear the progress line before printing results
-        if is_tty {
-            if let Ok(mut s) = state.lock() {
-                if s.last_len > 0 {
-                    eprint!("\r{:width$}\r", "", width = s.last_len);
-                    let _ = io::stderr().flush();
-                }
-            }
-        }
-        res
+/// One row per commit, oldest first, with the token change since the previous row.
+fn print_history(commits: &[loctok::CommitCount], use_color: bool, locale: Locale) {
+    let mut builder = Builder::default();
+    builder.push_record([
+        "commit",
+        "date",
+        "files",
+        "token count",
+        "change",
+        "summary",
+    ]);
+    let mut previous: Option<usize> = None;
+    for c in commits {
+        let change = previous.map_or_else(String::new, |p| {
+            let d = c.tokens as i64 - p as i64;
+            let sign = if d < 0 { '-' } else { '+' };
+            format!("{sign}{}", fmt_num(d.unsigned_abs() as usize, locale))
+        });
+        previous = Some(c.tokens);
+        builder.push_record([
+            c.commit.chars().take(8).collect(),
+            c.date.chars().take(10).collect(),
+            fmt_num(c.files, locale),
+            fmt_num(c.tokens, locale),
+            change,
+            c.summary.clone(),
+        ]);
+    }
+    let mut table = builder.build();
+    if use_color {
+        table.with(Style::rounded());
     } else {
-        count_tokens_in_path(&args.path, &opts)
-            .with_context(|| format!("failed to scan {}", args.path.display()))?
-    };
+        table.with(
+            Style::ascii()
+                .remove_horizontal()
+                .horizontals([(1, HorizontalLine::inherit(Style::ascii()))]),
+        );
+    }
+    table.with(Modify::new(Columns::new(2..5)).with(Alignment::right()));
+    outln!("{}", table);
+}
 
-    match args.format {
-        OutputFormat::Json => {
-            let json = serde_json::json!({
-                "encoding": args.encoding,
-                "total": result.total,
-                "files": result
-                    .files
-                    .iter()
-                    .map(|f| serde_json::json!({
-                        "path": f.path,
-                        "tokens": f.tokens,
-                        "lines": f.lines
-                    }))
-                    .collect::<Vec<_>>(),
-                "by_language": aggregate_by_language(&result.files)
-            });
-            println!("{}", serde_json::to_string_pretty(&json)?);
-        }
-        OutputFormat::Table => {
-            // Default mode: always show by-language table
-            let elapsed = start.elapsed();
-            println!(
-                "{:?} ({:.2} files/s)\n",
-                elapsed,
-                result.files.len() as f64 / elapsed.as_secs_f64()
-            );
-            print_by_language_table(&result);
-            // println!("Total tokens: {}", fmt_num(result.total));
-            // if let Some(info) = encoding_info(&args.encoding) {
-            //     let models = info.models.join(", ");
-            //     println!(
-            //         "Encoding: {} | Models: {} | Token number: {}",
-            //         args.encoding,
-            //         models,
-            //         fmt_num(info.token_number)
-            //     );
-            // }
-        }
-        OutputFormat::Tree => {
-            let elapsed = start.elapsed();
-            println!(
-                "{:?} ({:.2} files/s)\n",
-                elapsed,
-                result.files.len() as f64 / elapsed.as_secs_f64()
-            );
-            print_tree(&args.path, &result.files);
-            // if let Some(info) = encoding_info(&args.encoding) {
-            //     let models = info.models.join(", ");
-            //     println!(
-            //         "\nEncoding: {} | Models: {} | Token number: {}",
-            //         args.encoding,
-            //         models,
-            //         fmt_num(info.token_number)
-            //     );
-            // }
-        }
+/// Per-file signed token deltas followed by the net change.
+fn print_staged(changes: &[loctok::StagedChange], net: i64, locale: Locale) {
+    let signed = |d: i64| {
+        let sign = if d < 0 { '-' } else { '+' };
+        format!("{sign}{}", fmt_num(d.unsigned_abs() as usize, locale))
+    };
+    let width = changes
+        .iter()
+        .map(|c| signed(c.delta()).chars().count())
+        .chain(std::iter::once(signed(net).chars().count()))
+        .max()
+        .unwrap_or(0);
+    for c in changes {
+        outln!("{:>width$}  {}", signed(c.delta()), c.path.display());
     }
+    outln!("{:>width$}  total", signed(net));
+}
 
-    Ok(())
+/// Print elapsed time and scan rate to stderr so stdout only carries the report.
+fn print_stats(start: Instant, files: usize) {
+    let elapsed = start.elapsed();
+    eprintln!(
+        "{:?} ({:.2} files/s)\n",
+        elapsed,
+        files as f64 / elapsed.as_secs_f64()
+    );
 }
 
 fn copy_to_clipboard(text: &str) -> Result<()> {
@@ -369,168 +2283,361 @@ fn copy_to_clipboard(text: &str) -> Result<()> {
     anyhow::bail!("failed to copy to clipboard: no supported clipboard tool found")
 }
 
-fn print_by_language_table(result: &loctok::CountResult) {
-    #[derive(Tabled)]
-    struct Row {
-        #[tabled(rename = "Language")]
-        language: String,
-        #[tabled(rename = "lines of code")]
-        loc: String,
-        #[tabled(rename = "token count")]
-        tokens: String,
+/// `overhead` gets its own tokens-only row and is included in the SUM row. `key_header`
+/// names the first column, which holds each row's `language`.
+fn print_by_language_table(
+    rows: &[loctok::LangSummary],
+    key_header: &str,
+    use_color: bool,
+    show: &[Metric],
+    overhead: usize,
+    locale: Locale,
+    show_total: bool,
+) {
+    let mut builder = Builder::default();
+    let mut header = vec![key_header.to_string(), "files".to_string()];
+    for m in show {
+        header.push(m.header().to_string());
+        // Lines and tokens are followed by their share of the SUM row
+        match m {
+            Metric::Lines => header.push("% lines".to_string()),
+            Metric::Tokens => header.push("% tokens".to_string()),
+            _ => {}
+        }
+    }
+    builder.push_record(header);
+
+    let mut sum = loctok::LangSummary {
+        language: "SUM:".to_string(),
+        ..loctok::LangSummary::default()
+    };
+    for r in rows {
+        sum.files += r.files;
+        sum.lines += r.lines;
+        sum.total_lines += r.total_lines;
+        sum.blank_lines += r.blank_lines;
+        sum.tokens += r.tokens;
+        sum.bytes += r.bytes;
+        sum.words += r.words;
+        sum.chars += r.chars;
+    }
+    sum.tokens += overhead;
+
+    // `None` leaves a metric's cells blank, e.g. lines of the overhead row
+    let record = |label: &str, files: Option<usize>, value: &dyn Fn(Metric) -> Option<usize>| {
+        let mut record = vec![
+            label.to_string(),
+            files.map_or_else(String::new, |n| fmt_num(n, locale)),
+        ];
+        for &m in show {
+            let v = value(m);
+            record.push(v.map_or_else(String::new, |v| fmt_num(v, locale)));
+            let whole = match m {
+                Metric::Lines => sum.lines,
+                Metric::Tokens => sum.tokens,
+                _ => continue,
+            };
+            record.push(v.map_or_else(String::new, |v| fmt_pct(v, whole)));
+        }
+        record
+    };
+    for r in rows {
+        builder.push_record(record(&r.language, Some(r.files), &|m| {
+            Some(m.of_summary(r))
+        }));
+    }
+    if overhead > 0 {
+        builder.push_record(record("(overhead)", None, &|m| {
+            matches!(m, Metric::Tokens).then_some(overhead)
+        }));
+    }
+    if show_total {
+        builder.push_record(record(&sum.language, Some(sum.files), &|m| {
+            Some(m.of_summary(&sum))
+        }));
+    }
+
+    let mut table = builder.build();
+    // Fall back to plain ASCII borders when output is not meant for a styled terminal
+    if use_color {
+        table.with(Style::rounded());
+    } else {
+        // Plain ASCII with a single header rule, mirroring the rounded layout
+        table.with(
+            Style::ascii()
+                .remove_horizontal()
+                .horizontals([(1, HorizontalLine::inherit(Style::ascii()))]),
+        );
     }
+    // Every column but the language name holds numbers
+    table.with(Modify::new(Columns::new(1..)).with(Alignment::right()));
+    outln!("{}", table);
+}
+
+/// `--format bars`: a token-share bar per language, sized to the terminal width.
+fn print_bars(result: &loctok::CountResult, use_color: bool, locale: Locale, width: Option<usize>) {
+    // Eighths of a cell, so short bars still show their relative size
+    const PARTIAL: [&str; 8] = ["", "▏", "▎", "▍", "▌", "▋", "▊", "▉"];
+    // Fixed width when piped so output does not depend on the invoking terminal
+    const FALLBACK_WIDTH: usize = 80;
 
     let rows = aggregate_by_language(&result.files);
-    let mut table_rows: Vec<Row> = rows
-        .iter()
-        .map(|r| Row {
-            language: r.language.clone(),
-            loc: fmt_num(r.lines),
-            tokens: fmt_num(r.tokens),
-        })
-        .collect();
+    let total: usize = rows.iter().map(|r| r.tokens).sum();
+    let width = width.unwrap_or(FALLBACK_WIDTH);
 
-    let sum_lines: usize = rows.iter().map(|r| r.lines).sum();
-    let sum_tokens: usize = rows.iter().map(|r| r.tokens).sum();
-    table_rows.push(Row {
-        language: "SUM:".to_string(),
-        loc: fmt_num(sum_lines),
-        tokens: fmt_num(sum_tokens),
-    });
+    let label_w = rows.iter().map(|r| r.language.width()).max().unwrap_or(0);
+    let num_w = rows
+        .iter()
+        .map(|r| fmt_num(r.tokens, locale).width())
+        .max()
+        .unwrap_or(0);
+    // label, bar, tokens and "100.0%", separated by two spaces
+    let bar_w = width.saturating_sub(label_w + num_w + 6 + 3 * 2).max(10);
 
-    let mut table = Table::new(table_rows);
-    table.with(Style::rounded());
-    table.with(Modify::new(Columns::single(1)).with(Alignment::right())); // loc
-    table.with(Modify::new(Columns::single(2)).with(Alignment::right())); // tokens
-    println!("{}", table);
+    for r in &rows {
+        let eighths = (r.tokens * bar_w * 8).checked_div(total).unwrap_or(0);
+        let bar = format!("{}{}", "█".repeat(eighths / 8), PARTIAL[eighths % 8]);
+        let pad = " ".repeat(bar_w.saturating_sub(bar.chars().count()));
+        let bar = if use_color {
+            format!("\x1b[36m{bar}\x1b[0m")
+        } else {
+            bar
+        };
+        outln!(
+            "{:<label_w$}  {bar}{pad}  {:>num_w$}  {:>6}",
+            r.language,
+            fmt_num(r.tokens, locale),
+            fmt_pct(r.tokens, total),
+        );
+    }
 }
 
-fn fmt_num(n: usize) -> String {
-    (n as u64).to_formatted_string(&Locale::en)
+/// Secondary table for `--explain-others`: what ended up in the "Others" bucket.
+fn print_unmatched_table(result: &loctok::CountResult, use_color: bool, locale: Locale) {
+    let rows = unmatched_extensions(&result.files);
+    if rows.is_empty() {
+        return;
+    }
+
+    let mut builder = Builder::default();
+    builder.push_record(["Others: extension", "files", "token count"]);
+    for r in &rows {
+        builder.push_record([
+            r.extension.clone(),
+            fmt_num(r.files, locale),
+            fmt_num(r.tokens, locale),
+        ]);
+    }
+
+    let mut table = builder.build();
+    if use_color {
+        table.with(Style::rounded());
+    } else {
+        table.with(
+            Style::ascii()
+                .remove_horizontal()
+                .horizontals([(1, HorizontalLine::inherit(Style::ascii()))]),
+        );
+    }
+    table.with(Modify::new(Columns::new(1..)).with(Alignment::right()));
+    outln!("\n{}", table);
 }
 
-// ----- Tree mode -----
-use std::collections::BTreeMap;
-use std::path::Path;
+/// `--largest N`: the files with the most tokens, largest first.
+fn print_largest_table(
+    args: &Cli,
+    result: &loctok::CountResult,
+    n: usize,
+    use_color: bool,
+    locale: Locale,
+) {
+    let rows = largest_files(&result.files, n);
+    if rows.is_empty() {
+        return;
+    }
+
+    let mut builder = Builder::default();
+    builder.push_record(["Largest files", "language", "lines of code", "token count"]);
+    for f in rows {
+        builder.push_record([
+            output_path(args, &f.path).display().to_string(),
+            f.language.clone(),
+            fmt_num(f.lines, locale),
+            fmt_num(f.tokens, locale),
+        ]);
+    }
 
-#[derive(Debug, Clone)]
-enum NodeKind {
-    Dir,
-    File,
+    let mut table = builder.build();
+    if use_color {
+        table.with(Style::rounded());
+    } else {
+        table.with(
+            Style::ascii()
+                .remove_horizontal()
+                .horizontals([(1, HorizontalLine::inherit(Style::ascii()))]),
+        );
+    }
+    table.with(Modify::new(Columns::new(2..)).with(Alignment::right()));
+    outln!("\n{}", table);
 }
 
-#[derive(Debug, Clone)]
-struct TreeNode {
-    name: String,
-    kind: NodeKind,
-    lines: usize,
-    tokens: usize,
-    children: BTreeMap<String, TreeNode>,
+/// Metric columns selectable (and ordered) with `--show`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+enum Metric {
+    /// Non-empty lines of code
+    Lines,
+    /// All lines, blank ones included (like an editor or `wc -l`)
+    TotalLines,
+    /// Empty or whitespace-only lines
+    BlankLines,
+    /// Token count for the selected encoding
+    Tokens,
+    /// File size in bytes
+    Bytes,
+    /// Whitespace-separated words
+    Words,
+    /// Unicode characters
+    Chars,
 }
 
-impl TreeNode {
-    fn new_dir(name: String) -> Self {
-        Self {
-            name,
-            kind: NodeKind::Dir,
-            lines: 0,
-            tokens: 0,
-            children: BTreeMap::new(),
+impl Metric {
+    /// Column header in the by-language table.
+    fn header(self) -> &'static str {
+        match self {
+            Metric::Lines => "lines of code",
+            Metric::TotalLines => "total lines",
+            Metric::BlankLines => "blank lines",
+            Metric::Tokens => "token count",
+            Metric::Bytes => "bytes",
+            Metric::Words => "words",
+            Metric::Chars => "chars",
         }
     }
 
-    fn new_file(name: String, lines: usize, tokens: usize) -> Self {
-        Self {
-            name,
-            kind: NodeKind::File,
-            lines,
-            tokens,
-            children: BTreeMap::new(),
+    /// Short column header in tree mode.
+    fn tree_header(self) -> &'static str {
+        match self {
+            Metric::Lines => "LOC",
+            Metric::TotalLines => "TOTAL",
+            Metric::BlankLines => "BLANK",
+            Metric::Tokens => "TOK",
+            Metric::Bytes => "BYTES",
+            Metric::Words => "WORDS",
+            Metric::Chars => "CHARS",
         }
     }
-}
 
-fn rel_to_root(path: &Path, root_abs: &Path, root_arg: &Path) -> PathBuf {
-    // Prefer absolute root prefix; fall back to provided arg prefix; else filename
-    if let Ok(p) = path.strip_prefix(root_abs) {
-        return p.to_path_buf();
+    fn of_summary(self, s: &loctok::LangSummary) -> usize {
+        match self {
+            Metric::Lines => s.lines,
+            Metric::TotalLines => s.total_lines,
+            Metric::BlankLines => s.blank_lines,
+            Metric::Tokens => s.tokens,
+            Metric::Bytes => s.bytes as usize,
+            Metric::Words => s.words,
+            Metric::Chars => s.chars,
+        }
     }
-    if let Ok(p) = path.strip_prefix(root_arg) {
-        return p.to_path_buf();
+
+    fn of_node(self, n: &TreeNode) -> usize {
+        match self {
+            Metric::Lines => n.lines,
+            Metric::TotalLines => n.total_lines,
+            Metric::BlankLines => n.blank_lines,
+            Metric::Tokens => n.tokens,
+            Metric::Bytes => n.bytes as usize,
+            Metric::Words => n.words,
+            Metric::Chars => n.chars,
+        }
     }
-    path.file_name()
-        .map(|s| PathBuf::from(s))
-        .unwrap_or_else(|| path.to_path_buf())
 }
 
-fn build_tree(root: &Path, files: &[loctok::FileCount]) -> TreeNode {
-    let root_abs = std::fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
-    let root_name = root
-        .file_name()
-        .map(|s| s.to_string_lossy().to_string())
-        .unwrap_or_else(|| root.display().to_string());
+fn fmt_num(n: usize, locale: Locale) -> String {
+    (n as u64).to_formatted_string(&locale)
+}
 
-    let mut root_node = TreeNode::new_dir(root_name);
+/// Parse `--locale` into a `num_format` locale, e.g. "en", "de", "fr".
+fn parse_locale(s: &str) -> Result<Locale, String> {
+    Locale::from_name(s)
+        .map_err(|_| format!("unknown locale `{s}`, expected a name like en, de or fr"))
+}
 
-    for f in files {
-        let rel = rel_to_root(&f.path, &root_abs, root);
-        let mut cur = &mut root_node;
-        let mut comps = rel.components().peekable();
-        while let Some(comp) = comps.next() {
-            let name = comp.as_os_str().to_string_lossy().to_string();
-            let is_last = comps.peek().is_none();
-            if is_last {
-                // file
-                cur.children
-                    .insert(name.clone(), TreeNode::new_file(name, f.lines, f.tokens));
-            } else {
-                // dir
-                cur = cur
-                    .children
-                    .entry(name.clone())
-                    .or_insert_with(|| TreeNode::new_dir(name));
-            }
+/// Parse `--min-size` / `--max-size`: bytes with an optional binary unit, e.g. "300",
+/// "4KB", "1.5MiB" or "2g" (1KB = 1024 bytes).
+fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let shift = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 0,
+        "k" | "kb" | "kib" => 10,
+        "m" | "mb" | "mib" => 20,
+        "g" | "gb" | "gib" => 30,
+        _ => {
+            return Err(format!(
+                "unknown size unit in `{s}`, expected B, KB, MB or GB"
+            ))
         }
-    }
+    };
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size `{s}`, expected e.g. 4096, 4KB or 1.5MB"))?;
+    Ok((number * (1u64 << shift) as f64).round() as u64)
+}
 
-    // Accumulate directory totals
-    fn accumulate(node: &mut TreeNode) {
-        if matches!(node.kind, NodeKind::Dir) {
-            node.lines = 0;
-            node.tokens = 0;
-            // We want dirs printed before files; BTreeMap groups by key, so we just sum all
-            for child in node.children.values_mut() {
-                accumulate(child);
-                node.lines += child.lines;
-                node.tokens += child.tokens;
-            }
-        }
+/// `part` as a share of `whole` with one decimal, e.g. `42.5%`.
+fn fmt_pct(part: usize, whole: usize) -> String {
+    let pct = if whole == 0 {
+        0.0
+    } else {
+        part as f64 * 100.0 / whole as f64
+    };
+    format!("{pct:.1}%")
+}
+
+/// Render a remaining-time estimate compactly, e.g. `4s`, `2m05s`, `1h03m`.
+fn fmt_eta(secs: f64) -> String {
+    let secs = secs.ceil() as u64;
+    if secs < 60 {
+        format!("{}s", secs)
+    } else if secs < 3600 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{}h{:02}m", secs / 3600, (secs % 3600) / 60)
     }
-    accumulate(&mut root_node);
-    root_node
 }
 
-fn print_tree(root: &Path, files: &[loctok::FileCount]) {
-    let tree = build_tree(root, files);
+// ----- Tree mode -----
+use loctok::{build_tree, NodeKind, TreeNode};
+use std::path::Path;
 
-    // Compute widths for formatted numbers for nicer alignment
-    fn compute_widths(node: &TreeNode, max_loc: &mut usize, max_tok: &mut usize) {
-        let loc_s = fmt_num(node.lines);
-        let tok_s = fmt_num(node.tokens);
-        *max_loc = (*max_loc).max(loc_s.len());
-        *max_tok = (*max_tok).max(tok_s.len());
+fn print_tree(
+    tree: &TreeNode,
+    use_color: bool,
+    metrics: &[Metric],
+    locale: Locale,
+    show_total: bool,
+    files_only: bool,
+    width: Option<usize>,
+) {
+    // Column widths start at the header width and grow with formatted numbers
+    fn compute_widths(node: &TreeNode, metrics: &[Metric], locale: Locale, widths: &mut [usize]) {
+        for (w, m) in widths.iter_mut().zip(metrics) {
+            *w = (*w).max(vis_len(&fmt_num(m.of_node(node), locale)));
+        }
         for child in node.children.values() {
-            compute_widths(child, max_loc, max_tok);
+            compute_widths(child, metrics, locale, widths);
         }
     }
-    let mut max_loc = 0usize;
-    let mut max_tok = 0usize;
-    compute_widths(&tree, &mut max_loc, &mut max_tok);
+    let mut widths: Vec<usize> = metrics.iter().map(|m| m.tree_header().len()).collect();
+    compute_widths(tree, metrics, locale, &mut widths);
 
     // Determine the maximum label width (prefix + name + optional slash for dirs)
-    // Using character count (approx. display width) to avoid byte-length issues
+    // Terminal columns, so CJK and emoji names count as double width
     fn vis_len(s: &str) -> usize {
-        s.chars().count()
+        UnicodeWidthStr::width(s)
     }
     fn compute_label_widths(
         node: &TreeNode,
@@ -558,127 +2665,131 @@ fn print_tree(root: &Path, files: &[loctok::FileCount]) {
             .collect();
         dirs.sort_by(|a, b| a.name.cmp(&b.name));
         files.sort_by(|a, b| a.name.cmp(&b.name));
-        let ordered = dirs
-            .into_iter()
-            .chain(files.into_iter())
-            .collect::<Vec<_>>();
+        let ordered = dirs.into_iter().chain(files).collect::<Vec<_>>();
 
+        let count = ordered.len();
         for (idx, child) in ordered.into_iter().enumerate() {
-            let is_first = idx == 0;
-            let branch = if is_first { "┌── " } else { "├── " };
+            let is_last = idx + 1 == count;
+            let branch = if is_last { "└── " } else { "├── " };
             let child_line_prefix = format!("{}{}", child_prefix, branch);
-            let next_prefix = format!("{}{}", child_prefix, if is_first { "    " } else { "│   " });
+            let next_prefix = format!("{}{}", child_prefix, if is_last { "    " } else { "│   " });
             compute_label_widths(child, &child_line_prefix, &next_prefix, max_label);
         }
     }
-    let mut max_label = 0usize;
-    compute_label_widths(&tree, "", "", &mut max_label);
+    let mut max_label = 4usize; // len("Name")
+    compute_label_widths(tree, "", "", &mut max_label);
+    let gap = "    "; // spacing between columns
+
+    // Narrow the label column to fit; names that no longer fit are shortened in the middle
+    if let Some(width) = width {
+        let counts_width: usize = widths.iter().map(|w| gap.len() + w).sum();
+        max_label = max_label.min(width.saturating_sub(counts_width).max(4));
+    }
 
-    // Simple ANSI colors; no external deps
-    fn color_bold(s: &str) -> String {
+    // Simple ANSI colors; no external deps. No-ops when color is disabled.
+    fn color_bold(s: &str, use_color: bool) -> String {
+        if !use_color {
+            return s.to_string();
+        }
         format!("\x1b[1m{}\x1b[0m", s)
     }
-    fn color_dir(s: &str) -> String {
+    fn color_dir(s: &str, use_color: bool) -> String {
+        if !use_color {
+            return s.to_string();
+        }
         // bright blue
         format!("\x1b[1;34m{}\x1b[0m", s)
     }
 
-    // Print header
-    let header_name = color_bold("Name");
-    let header_loc_plain = "LOC";
-    let header_tok_plain = "TOK";
-    let header_loc = color_bold(header_loc_plain);
-    let header_tok = color_bold(header_tok_plain);
-    let gap = "    "; // spacing between columns
-    let pad_label = if max_label > 4 { max_label - 4 } else { 0 }; // 4 == len("Name")
-    let pad_loc = if max_loc > header_loc_plain.len() {
-        max_loc - header_loc_plain.len()
-    } else {
-        0
-    };
-    let pad_tok = if max_tok > header_tok_plain.len() {
-        max_tok - header_tok_plain.len()
-    } else {
-        0
-    };
-    println!(
-        "{}{}{}{}{}{}{}{}",
-        header_name,
-        " ".repeat(pad_label),
-        gap,
-        " ".repeat(pad_loc),
-        header_loc,
+    // Shared layout for every printed line
+    struct Layout<'a> {
+        gap: &'a str,
+        max_label: usize,
+        metrics: &'a [Metric],
+        widths: Vec<usize>,
+        use_color: bool,
+        locale: Locale,
+        show_total: bool,
+        // Leave the metric cells of directories below the root blank
+        files_only: bool,
+    }
+    let layout = Layout {
         gap,
-        " ".repeat(pad_tok),
-        header_tok
+        max_label,
+        metrics,
+        widths,
+        use_color,
+        locale,
+        show_total,
+        files_only,
+    };
+
+    // Print header
+    let mut header = format!(
+        "{}{}",
+        color_bold("Name", use_color),
+        " ".repeat(max_label - 4)
     );
-    let total_width = max_label + gap.len() + max_loc + gap.len() + max_tok;
-    println!("{}", "-".repeat(total_width));
+    for (m, w) in metrics.iter().zip(&layout.widths) {
+        let h = m.tree_header();
+        header.push_str(layout.gap);
+        header.push_str(&" ".repeat(w - h.len()));
+        header.push_str(&color_bold(h, use_color));
+    }
+    outln!("{}", header);
+    let total_width = max_label
+        + layout
+            .widths
+            .iter()
+            .map(|w| layout.gap.len() + w)
+            .sum::<usize>();
+    outln!("{}", "-".repeat(total_width));
 
     // Helper to print one line (with colors, dir slash, and vertical alignment)
-    fn line_with_counts(
-        prefix: &str,
-        name: &str,
-        is_dir: bool,
-        lines: usize,
-        tokens: usize,
-        gap: &str,
-        max_label: usize,
-        max_loc: usize,
-        max_tok: usize,
-    ) {
+    fn line_with_counts(prefix: &str, node: &TreeNode, layout: &Layout, counts: bool) {
+        let is_dir = matches!(node.kind, NodeKind::Dir);
         let display_name = if is_dir {
-            format!("{}/", name)
+            format!("{}/", node.name)
         } else {
-            name.to_string()
+            node.name.clone()
         };
+        // Keep at least a couple of columns even when the prefix alone overflows
+        let room = layout.max_label.saturating_sub(vis_len(prefix)).max(2);
+        let display_name = truncate_middle(&display_name, room);
         let colored_name = if is_dir {
-            color_dir(&display_name)
+            color_dir(&display_name, layout.use_color)
         } else {
             display_name.clone()
         };
         let label_len = vis_len(prefix) + vis_len(&display_name);
-        let pad_label = if max_label > label_len {
-            max_label - label_len
-        } else {
-            0
-        };
-        let loc_s = fmt_num(lines);
-        let tok_s = fmt_num(tokens);
-        let pad_loc = if max_loc > loc_s.len() {
-            max_loc - loc_s.len()
-        } else {
-            0
-        };
-        let pad_tok = if max_tok > tok_s.len() {
-            max_tok - tok_s.len()
-        } else {
-            0
-        };
-        println!(
-            "{}{}{}{}{}{}{}{}{}",
+        let mut line = format!(
+            "{}{}{}",
             prefix,
             colored_name,
-            " ".repeat(pad_label),
-            gap,
-            " ".repeat(pad_loc),
-            loc_s,
-            gap,
-            " ".repeat(pad_tok),
-            tok_s
+            " ".repeat(layout.max_label.saturating_sub(label_len))
         );
+        if !counts {
+            outln!("{}", line.trim_end());
+            return;
+        }
+        for (m, w) in layout.metrics.iter().zip(&layout.widths) {
+            let v = fmt_num(m.of_node(node), layout.locale);
+            line.push_str(layout.gap);
+            line.push_str(&" ".repeat(w.saturating_sub(vis_len(&v))));
+            line.push_str(&v);
+        }
+        outln!("{}", line);
     }
 
-    // Post-order print: children first, then the node itself.
-    fn print_node_post(
-        node: &TreeNode,
-        line_prefix: String,
-        child_prefix: String,
-        gap: &str,
-        max_label: usize,
-        max_loc: usize,
-        max_tok: usize,
-    ) {
+    // Pre-order print, like `tree`: the node itself, then its children.
+    fn print_node(node: &TreeNode, line_prefix: String, child_prefix: String, layout: &Layout) {
+        // Only the root has an empty prefix; its line is the grand total
+        let is_root = line_prefix.is_empty();
+        if layout.show_total || !is_root {
+            let counts = is_root || !layout.files_only || matches!(node.kind, NodeKind::File);
+            line_with_counts(&line_prefix, node, layout, counts);
+        }
+
         // dirs first, then files
         let mut dirs: Vec<&TreeNode> = node
             .children
@@ -692,50 +2803,212 @@ fn print_tree(root: &Path, files: &[loctok::FileCount]) {
             .collect();
         dirs.sort_by(|a, b| a.name.cmp(&b.name));
         files.sort_by(|a, b| a.name.cmp(&b.name));
-        let ordered = dirs
-            .into_iter()
-            .chain(files.into_iter())
-            .collect::<Vec<_>>();
+        let ordered = dirs.into_iter().chain(files).collect::<Vec<_>>();
 
-        let len = ordered.len();
+        let count = ordered.len();
         for (idx, child) in ordered.into_iter().enumerate() {
-            let is_first = idx == 0;
-            let branch = if is_first { "┌── " } else { "├── " };
+            let is_last = idx + 1 == count;
+            let branch = if is_last { "└── " } else { "├── " };
             let child_line_prefix = format!("{}{}", child_prefix, branch);
-            let next_prefix = format!("{}{}", child_prefix, if is_first { "    " } else { "│   " });
-            print_node_post(
-                child,
-                child_line_prefix,
-                next_prefix,
-                gap,
-                max_label,
-                max_loc,
-                max_tok,
-            );
+            let next_prefix = format!("{}{}", child_prefix, if is_last { "    " } else { "│   " });
+            print_node(child, child_line_prefix, next_prefix, layout);
         }
+    }
 
-        // Print the node itself last
-        line_with_counts(
-            &line_prefix,
-            &node.name,
-            matches!(node.kind, NodeKind::Dir),
-            node.lines,
-            node.tokens,
-            gap,
-            max_label,
-            max_loc,
-            max_tok,
-        );
+    // Kick off from root with empty prefixes
+    print_node(tree, String::new(), String::new(), &layout);
+}
+
+/// Shorten `s` to at most `max` terminal columns by replacing its middle with `…`, keeping
+/// the start and the end (e.g. the extension) readable.
+fn truncate_middle(s: &str, max: usize) -> String {
+    if s.width() <= max {
+        return s.to_string();
     }
+    let keep = max.saturating_sub(1);
+    let take = |chars: &mut dyn Iterator<Item = char>, budget: usize| {
+        let mut used = 0;
+        let mut out = Vec::new();
+        for c in chars {
+            used += c.width().unwrap_or(0);
+            if used > budget {
+                break;
+            }
+            out.push(c);
+        }
+        out
+    };
+    let head = take(&mut s.chars(), keep.div_ceil(2));
+    let mut tail = take(&mut s.chars().rev(), keep / 2);
+    tail.reverse();
+    head.into_iter().chain(['…']).chain(tail).collect()
+}
 
-    // Kick off from root with empty prefixes so root appears last
-    print_node_post(
-        &tree,
-        String::new(),
-        String::new(),
-        gap,
-        max_label,
-        max_loc,
-        max_tok,
+/// Canvas size of `--format svg`, in SVG user units.
+const TREEMAP_WIDTH: f64 = 1200.0;
+const TREEMAP_HEIGHT: f64 = 800.0;
+
+#[derive(Clone, Copy, Debug)]
+struct Rect {
+    x: f64,
+    y: f64,
+    w: f64,
+    h: f64,
+}
+
+/// Squarified treemap layout (Bruls, Huizing & van Wijk): split `rect` into one rectangle
+/// per weight, in order, keeping aspect ratios close to 1. `weights` must be sorted in
+/// descending order and positive.
+fn squarify(weights: &[f64], rect: Rect) -> Vec<Rect> {
+    let total: f64 = weights.iter().sum();
+    // Nothing to split, or no room left inside a tiny parent
+    if total <= 0.0 || rect.w <= 0.0 || rect.h <= 0.0 {
+        return Vec::new();
+    }
+    // Work in areas so a row's thickness is simply its area over the side it spans
+    let scale = rect.w * rect.h / total;
+    let areas: Vec<f64> = weights.iter().map(|w| w * scale).collect();
+    // Worst aspect ratio of a row of `areas` laid along a side of length `side`
+    let worst = |row: &[f64], side: f64| {
+        let sum: f64 = row.iter().sum();
+        let max = row.iter().copied().fold(f64::MIN, f64::max);
+        let min = row.iter().copied().fold(f64::MAX, f64::min);
+        (side * side * max / (sum * sum)).max(sum * sum / (side * side * min))
+    };
+
+    let mut out = Vec::with_capacity(areas.len());
+    let mut free = rect;
+    let mut start = 0;
+    while start < areas.len() {
+        let side = free.w.min(free.h);
+        let mut end = start + 1;
+        while end < areas.len()
+            && worst(&areas[start..=end], side) <= worst(&areas[start..end], side)
+        {
+            end += 1;
+        }
+        let row = &areas[start..end];
+        let thickness = row.iter().sum::<f64>() / side;
+        let mut offset = 0.0;
+        for area in row {
+            let len = area / thickness;
+            // The row runs along the shorter side of the free space
+            out.push(if free.w >= free.h {
+                Rect {
+                    x: free.x,
+                    y: free.y + offset,
+                    w: thickness,
+                    h: len,
+                }
+            } else {
+                Rect {
+                    x: free.x + offset,
+                    y: free.y,
+                    w: len,
+                    h: thickness,
+                }
+            });
+            offset += len;
+        }
+        if free.w >= free.h {
+            free.x += thickness;
+            free.w -= thickness;
+        } else {
+            free.y += thickness;
+            free.h -= thickness;
+        }
+        start = end;
+    }
+    out
+}
+
+/// Stable fill color per language, spread around the hue wheel by a name hash.
+fn language_color(language: &str) -> String {
+    if language == "Others" {
+        return "hsl(0, 0%, 70%)".to_string();
+    }
+    let hash = language.bytes().fold(0xcbf29ce484222325u64, |h, b| {
+        (h ^ b as u64).wrapping_mul(0x100000001b3)
+    });
+    format!("hsl({}, 55%, 60%)", hash % 360)
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// `--format svg`: a squarified treemap of `tree` sized by tokens. Files are filled with
+/// their language's color; directories (and directories cut off by `--tree-depth`) are
+/// outlined, and every rectangle has a tooltip with its path and token count.
+fn render_treemap_svg(tree: &TreeNode, locale: Locale) -> String {
+    // Room kept around a directory's children so its outline stays visible
+    const PAD: f64 = 2.0;
+    // Labels are drawn only where roughly this many units per character fit
+    const CHAR_WIDTH: f64 = 7.0;
+    const LABEL_HEIGHT: f64 = 14.0;
+
+    fn render(node: &TreeNode, path: &str, rect: Rect, locale: Locale, svg: &mut String) {
+        let title = format!("{path}: {} tokens", fmt_num(node.tokens, locale));
+        let is_leaf = node.children.is_empty();
+        let (fill, stroke) = match (node.kind, is_leaf) {
+            (NodeKind::File, _) => (
+                language_color(&language_from_path(Path::new(&node.name))),
+                "#fff",
+            ),
+            // Pruned directory: its files are not known individually
+            (NodeKind::Dir, true) => ("hsl(0, 0%, 85%)".to_string(), "#fff"),
+            (NodeKind::Dir, false) => ("none".to_string(), "#333"),
+        };
+        svg.push_str(&format!(
+            "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"{fill}\" stroke=\"{stroke}\"><title>{}</title></rect>\n",
+            rect.x,
+            rect.y,
+            rect.w,
+            rect.h,
+            xml_escape(&title)
+        ));
+        if is_leaf {
+            if rect.w > CHAR_WIDTH * node.name.chars().count() as f64 + 4.0 && rect.h > LABEL_HEIGHT
+            {
+                svg.push_str(&format!(
+                    "<text x=\"{:.1}\" y=\"{:.1}\">{}</text>\n",
+                    rect.x + 3.0,
+                    rect.y + LABEL_HEIGHT - 2.0,
+                    xml_escape(&node.name)
+                ));
+            }
+            return;
+        }
+
+        let mut children: Vec<&TreeNode> =
+            node.children.values().filter(|c| c.tokens > 0).collect();
+        children.sort_by(|a, b| b.tokens.cmp(&a.tokens).then_with(|| a.name.cmp(&b.name)));
+        let inner = Rect {
+            x: rect.x + PAD,
+            y: rect.y + PAD,
+            w: (rect.w - 2.0 * PAD).max(0.0),
+            h: (rect.h - 2.0 * PAD).max(0.0),
+        };
+        let weights: Vec<f64> = children.iter().map(|c| c.tokens as f64).collect();
+        for (child, rect) in children.iter().zip(squarify(&weights, inner)) {
+            let child_path = format!("{path}/{}", child.name);
+            render(child, &child_path, rect, locale, svg);
+        }
+    }
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{TREEMAP_WIDTH}\" height=\"{TREEMAP_HEIGHT}\" viewBox=\"0 0 {TREEMAP_WIDTH} {TREEMAP_HEIGHT}\" font-family=\"sans-serif\" font-size=\"11\">\n"
     );
+    let canvas = Rect {
+        x: 0.0,
+        y: 0.0,
+        w: TREEMAP_WIDTH,
+        h: TREEMAP_HEIGHT,
+    };
+    render(tree, &tree.name, canvas, locale, &mut svg);
+    svg.push_str("</svg>\n");
+    svg
 }
@@ -1,7 +1,10 @@
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use loctok::{count_tokens_in_path, count_tokens_in_text, get_encoder, Options};
+use loctok::{
+    collect_filtered_texts, count_file, count_tokens_and_collect_texts, count_tokens_in_path,
+    count_tokens_in_text, filtered_paths, get_encoder, Options,
+};
 
 #[test]
 fn counts_tokens_and_respects_gitignore() {
@@ -68,6 +71,68 @@ fn ext_filter_includes_only_specified_extensions() {
         .all(|f| f.path.ends_with("kept.txt") || f.path.ends_with("kept2.txt")));
 }
 
+#[test]
+fn negated_ext_entries_exclude_and_win_over_includes() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    for name in ["lib.rs", "lib.test.rs", "app.py", "data.json", "Cargo.lock"] {
+        fs::write(dir.path().join(name), "x = 1\n").unwrap();
+    }
+    let names = |ext: &str| -> Vec<String> {
+        let out = assert_cmd::Command::cargo_bin("loctok")
+            .expect("bin")
+            .arg(dir.path())
+            .args(["--format", "json", "--no-cache", "--ext", ext])
+            .output()
+            .expect("run");
+        assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+        let report: serde_json::Value = serde_json::from_slice(&out.stdout).expect("json");
+        report["files"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|f| {
+                let path = PathBuf::from(f["path"].as_str().unwrap());
+                path.file_name().unwrap().to_string_lossy().into_owned()
+            })
+            .collect()
+    };
+
+    assert_eq!(names("!json,!LOCK"), ["app.py", "lib.rs", "lib.test.rs"]);
+    assert_eq!(names("rs,py,!test.rs"), ["app.py", "lib.rs"]);
+    assert_eq!(names("rs,!rs"), Vec::<String>::new());
+}
+
+#[test]
+fn case_sensitive_ext_tells_upper_and_lower_case_apart() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    for name in ["a.C", "b.c"] {
+        fs::write(dir.path().join(name), "int x;\n").unwrap();
+    }
+    let names = |extra: &[&str]| -> Vec<String> {
+        let out = assert_cmd::Command::cargo_bin("loctok")
+            .expect("bin")
+            .arg(dir.path())
+            .args(["--format", "json", "--no-cache", "--ext", "C"])
+            .args(extra)
+            .output()
+            .expect("run");
+        assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+        let report: serde_json::Value = serde_json::from_slice(&out.stdout).expect("json");
+        report["files"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|f| {
+                let path = PathBuf::from(f["path"].as_str().unwrap());
+                path.file_name().unwrap().to_string_lossy().into_owned()
+            })
+            .collect()
+    };
+
+    assert_eq!(names(&[]), ["a.C", "b.c"]);
+    assert_eq!(names(&["--case-sensitive-ext"]), ["a.C"]);
+}
+
 #[test]
 fn ext_filter_excludes_non_matching_extensions() {
     use std::collections::HashSet;
@@ -84,3 +149,2415 @@ fn ext_filter_excludes_non_matching_extensions() {
     assert_eq!(result.total, 0);
     assert_eq!(result.files.len(), 0);
 }
+
+#[test]
+fn lang_filters_match_language_names_case_insensitively() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    for name in ["a.cpp", "b.cxx", "c.cc", "d.py", "e.json"] {
+        fs::write(dir.path().join(name), "x\n").unwrap();
+    }
+    let names = |opts: &Options| -> Vec<String> {
+        let result = count_tokens_in_path(dir.path(), opts).expect("count ok");
+        let mut names: Vec<String> = result
+            .files
+            .iter()
+            .map(|f| f.path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    };
+
+    let opts = Options {
+        include_langs: Some(["c++".to_string()].into_iter().collect()),
+        ..Options::default()
+    };
+    assert_eq!(names(&opts), ["a.cpp", "b.cxx", "c.cc"]);
+
+    let opts = Options {
+        exclude_langs: Some(["c++".to_string(), "json".to_string()].into_iter().collect()),
+        ..Options::default()
+    };
+    assert_eq!(names(&opts), ["d.py"]);
+}
+
+#[test]
+fn table_timing_goes_to_stderr() {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let out = assert_cmd::Command::cargo_bin("loctok")
+        .expect("bin")
+        .arg("--no-cache")
+        .arg(&root)
+        .output()
+        .expect("run");
+    assert!(out.status.success());
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(!stdout.contains("files/s"));
+    assert!(stdout.contains("SUM:"));
+    assert!(stderr.contains("files/s"));
+}
+
+#[test]
+fn tree_color_flag_controls_ansi_escapes() {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let run = |color: &str| {
+        let out = assert_cmd::Command::cargo_bin("loctok")
+            .expect("bin")
+            .arg("--no-cache")
+            .arg(&root)
+            .args(["--format", "tree", "--color", color])
+            .env_remove("NO_COLOR")
+            .output()
+            .expect("run");
+        assert!(out.status.success());
+        String::from_utf8_lossy(&out.stdout).to_string()
+    };
+    assert!(run("always").contains('\x1b'));
+    assert!(!run("never").contains('\x1b'));
+    // Piped stdout is not a TTY, so auto disables color
+    assert!(!run("auto").contains('\x1b'));
+}
+
+#[test]
+fn always_include_surfaces_matching_hidden_files() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let root = dir.path();
+    fs::write(root.join("visible.txt"), "hello").unwrap();
+    fs::write(root.join(".env.example"), "KEY=value").unwrap();
+    fs::write(root.join(".secret"), "nope").unwrap();
+    fs::create_dir_all(root.join(".github/workflows")).unwrap();
+    fs::write(root.join(".github/workflows/ci.yml"), "on: push").unwrap();
+    fs::write(root.join(".github/CODEOWNERS"), "* @me").unwrap();
+
+    let opts = Options {
+        always_include: vec![
+            ".env.example".to_string(),
+            ".github/workflows/*.yml".to_string(),
+        ],
+        ..Options::default()
+    };
+    let result = count_tokens_in_path(root, &opts).expect("count ok");
+    let mut names: Vec<String> = result
+        .files
+        .iter()
+        .map(|f| {
+            f.path
+                .strip_prefix(root)
+                .unwrap()
+                .to_string_lossy()
+                .replace('\\', "/")
+        })
+        .collect();
+    names.sort();
+    assert_eq!(
+        names,
+        vec![".env.example", ".github/workflows/ci.yml", "visible.txt"]
+    );
+}
+
+#[test]
+fn json_progress_emits_ndjson_events() {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let out = assert_cmd::Command::cargo_bin("loctok")
+        .expect("bin")
+        .arg("--no-cache")
+        .arg(&root)
+        .args(["--format", "json", "--progress-format", "json"])
+        .output()
+        .expect("run");
+    assert!(out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    let events: Vec<serde_json::Value> = stderr
+        .lines()
+        .filter(|l| l.starts_with('{'))
+        .map(|l| serde_json::from_str(l).expect("valid json event"))
+        .collect();
+    assert!(!events.is_empty());
+    let last = events.last().unwrap();
+    assert_eq!(last["done"], last["total"]);
+}
+
+#[test]
+fn progress_to_redirects_or_silences_progress() {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let dir = tempfile::tempdir().expect("tempdir");
+    let log = dir.path().join("progress.log");
+    let out = assert_cmd::Command::cargo_bin("loctok")
+        .expect("bin")
+        .arg("--no-cache")
+        .arg(&root)
+        .args(["--format", "json", "--progress-format", "json", "--progress-to"])
+        .arg(&log)
+        .output()
+        .expect("run");
+    assert!(out.status.success());
+    assert!(!String::from_utf8_lossy(&out.stderr).contains("\"done\""));
+    let logged = std::fs::read_to_string(&log).expect("progress file");
+    assert!(logged.lines().any(|l| l.starts_with("{\"done\":")));
+
+    let out = assert_cmd::Command::cargo_bin("loctok")
+        .expect("bin")
+        .arg("--no-cache")
+        .arg(&root)
+        .args(["--progress-format", "json", "--progress-to", "none"])
+        .output()
+        .expect("run");
+    assert!(out.status.success());
+    assert!(!String::from_utf8_lossy(&out.stderr).contains("\"done\""));
+    assert!(!String::from_utf8_lossy(&out.stdout).contains("\"done\""));
+}
+
+#[test]
+fn spawn_count_streams_every_file() {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let opts = Options::default();
+    let (handle, rx) = loctok::spawn_count(&root, &opts);
+    let mut streamed: Vec<_> = rx.iter().map(|f| (f.path, f.tokens)).collect();
+    handle.join().expect("scan thread").expect("scan ok");
+    streamed.sort();
+
+    let expected = count_tokens_in_path(&root, &opts).expect("count ok");
+    let mut expected: Vec<_> = expected.files.into_iter().map(|f| (f.path, f.tokens)).collect();
+    expected.sort();
+    assert_eq!(streamed, expected);
+}
+
+#[test]
+fn repeated_scans_produce_identical_json() {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let run = || {
+        let out = assert_cmd::Command::cargo_bin("loctok")
+            .expect("bin")
+            .arg(&root)
+            .args(["--format", "json", "--no-cache"])
+            .output()
+            .expect("run");
+        assert!(out.status.success());
+        out.stdout
+    };
+    assert_eq!(run(), run());
+
+    let res = count_tokens_in_path(&root, &Options::default()).expect("count ok");
+    assert!(res.files.windows(2).all(|w| w[0].path < w[1].path));
+}
+
+#[test]
+fn combined_scan_matches_separate_calls() {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let opts = Options::default();
+
+    let (result, texts) = count_tokens_and_collect_texts(&root, &opts).expect("scan ok");
+    let expected_texts = collect_filtered_texts(&root, &opts).expect("collect ok");
+    let expected = count_tokens_in_path(&root, &opts).expect("count ok");
+
+    assert_eq!(texts, expected_texts);
+    assert_eq!(result.total, expected.total);
+    assert_eq!(result.files.len(), expected.files.len());
+    assert_eq!(
+        texts.iter().map(|(p, _)| p.clone()).collect::<Vec<_>>(),
+        vec![PathBuf::from("kept.txt"), PathBuf::from("nested/kept2.txt")]
+    );
+}
+
+#[test]
+fn count_file_counts_a_single_file() {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/kept.txt");
+    let text = fs::read_to_string(&path).expect("read kept");
+    let encoder = get_encoder("cl100k_base").expect("encoder");
+
+    let fc = count_file(&path, &Options::default()).expect("count ok");
+    assert_eq!(fc.path, path);
+    assert_eq!(fc.tokens, count_tokens_in_text(&encoder, &text));
+    assert_eq!(fc.language, "Text");
+    assert_eq!(fc.bytes, text.len() as u64);
+    assert_eq!(fc.words, text.split_whitespace().count());
+    assert_eq!(fc.chars, text.chars().count());
+}
+
+#[test]
+fn count_file_rejects_non_utf8() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let path = dir.path().join("blob.bin");
+    fs::write(&path, [0xff, 0xfe, 0x00, 0x80]).unwrap();
+    assert!(count_file(&path, &Options::default()).is_err());
+}
+
+#[test]
+fn yaml_format_matches_json_fields() {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let run = |format: &str| {
+        let out = assert_cmd::Command::cargo_bin("loctok")
+            .expect("bin")
+            .arg("--no-cache")
+            .arg(&root)
+            .args(["--format", format, "--progress-format", "json"])
+            .output()
+            .expect("run");
+        assert!(out.status.success());
+        String::from_utf8_lossy(&out.stdout).to_string()
+    };
+    let json: serde_json::Value = serde_json::from_str(&run("json")).expect("json");
+    let yaml: serde_json::Value = serde_yaml::from_str(&run("yaml")).expect("yaml");
+    assert_eq!(json, yaml);
+}
+
+#[test]
+fn threshold_flags_set_exit_code() {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let run = |args: &[&str]| {
+        assert_cmd::Command::cargo_bin("loctok")
+            .expect("bin")
+            .arg("--no-cache")
+            .arg(&root)
+            .args(["--format", "json", "--progress-format", "json"])
+            .args(args)
+            .output()
+            .expect("run")
+    };
+    assert_eq!(run(&["--fail-if-total-over", "1000000"]).status.code(), Some(0));
+
+    let out = run(&["--fail-if-total-over", "0", "--fail-if-lang-over", "text=0"]);
+    assert_eq!(out.status.code(), Some(1));
+    // Report is still printed and every violated rule is reported
+    assert!(serde_json::from_slice::<serde_json::Value>(&out.stdout).is_ok());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("total tokens"));
+    assert!(stderr.contains("text tokens"));
+
+    assert_eq!(run(&["--encoding", "nope"]).status.code(), Some(2));
+}
+
+#[test]
+fn large_files_are_counted_via_mmap() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let path = dir.path().join("big.sql");
+    // Just over the 1 MiB mmap threshold
+    let text = "select 1;\n".repeat(110_000);
+    fs::write(&path, &text).unwrap();
+
+    let encoder = get_encoder("cl100k_base").expect("encoder");
+    let fc = count_file(&path, &Options::default()).expect("count ok");
+    assert_eq!(fc.tokens, count_tokens_in_text(&encoder, &text));
+    assert_eq!(fc.lines, 110_000);
+    assert_eq!(fc.bytes, text.len() as u64);
+
+    // Non-UTF-8 content above the threshold is still rejected
+    let bin = dir.path().join("big.bin");
+    let mut bytes = text.into_bytes();
+    bytes.push(0xff);
+    fs::write(&bin, bytes).unwrap();
+    assert!(count_file(&bin, &Options::default()).is_err());
+}
+
+#[test]
+fn cache_reuses_counts_until_file_changes() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let root = dir.path().join("src");
+    fs::create_dir_all(&root).unwrap();
+    let file = root.join("a.txt");
+    fs::write(&file, "hello world").unwrap();
+    let cache = dir.path().join("cache.json");
+
+    let opts = Options {
+        cache_path: Some(cache.clone()),
+        ..Options::default()
+    };
+    let first = count_tokens_in_path(&root, &opts).expect("count ok");
+    assert!(first.total > 0);
+
+    // Tamper with the cached count: an unchanged file must reuse it
+    let mut json: serde_json::Value = serde_json::from_slice(&fs::read(&cache).unwrap()).unwrap();
+    let key = file.to_string_lossy().to_string();
+    json["entries"][&key]["tokens"] = 999.into();
+    fs::write(&cache, serde_json::to_vec(&json).unwrap()).unwrap();
+    assert_eq!(count_tokens_in_path(&root, &opts).unwrap().total, 999);
+
+    // A size change invalidates the entry
+    fs::write(&file, "hello world, again").unwrap();
+    let recount = count_tokens_in_path(&root, &opts).unwrap();
+    assert_ne!(recount.total, 999);
+    assert!(recount.total > first.total);
+}
+
+#[test]
+fn show_selects_and_orders_columns() {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let out = assert_cmd::Command::cargo_bin("loctok")
+        .expect("bin")
+        .arg(&root)
+        .args(["--show", "bytes,tokens", "--color", "never", "--no-cache"])
+        .output()
+        .expect("run");
+    assert!(out.status.success());
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    let header = stdout.lines().nth(1).expect("header row");
+    let bytes_at = header.find("bytes").expect("bytes column");
+    let tokens_at = header.find("token count").expect("tokens column");
+    assert!(bytes_at < tokens_at);
+    assert!(!header.contains("lines of code"));
+}
+
+#[test]
+fn unmatched_extensions_explain_others_bucket() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    fs::write(dir.path().join("a.zzq"), "one two\n").unwrap();
+    fs::write(dir.path().join("b.ZZQ"), "three\n").unwrap();
+    fs::write(dir.path().join("NOTICE_X"), "four\n").unwrap();
+    fs::write(dir.path().join("main.rs"), "fn main() {}\n").unwrap();
+
+    let out = assert_cmd::Command::cargo_bin("loctok")
+        .expect("bin")
+        .arg(dir.path())
+        .args(["--format", "json", "--no-cache"])
+        .output()
+        .expect("run");
+    assert!(out.status.success());
+    let v: serde_json::Value = serde_json::from_slice(&out.stdout).expect("json");
+    let unmatched = v["unmatched_extensions"].as_object().expect("map");
+    assert_eq!(unmatched.len(), 2);
+    assert_eq!(unmatched[".zzq"]["files"], 2);
+    assert_eq!(unmatched["NOTICE_X"]["files"], 1);
+
+    assert_cmd::Command::cargo_bin("loctok")
+        .expect("bin")
+        .arg(dir.path())
+        .args(["--explain-others", "--no-cache"])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains(".zzq"));
+}
+
+#[test]
+fn gitattributes_linguist_markers_skip_files() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    fs::create_dir_all(dir.path().join("vendor/lib")).unwrap();
+    fs::write(
+        dir.path().join(".gitattributes"),
+        "vendor/** linguist-vendored\n*.pb.rs linguist-generated\nvendor/lib/keep.rs -linguist-vendored\n",
+    )
+    .unwrap();
+    fs::write(dir.path().join("main.rs"), "fn main() {}\n").unwrap();
+    fs::write(dir.path().join("msg.pb.rs"), "struct Msg;\n").unwrap();
+    fs::write(dir.path().join("vendor/lib/dep.rs"), "fn dep() {}\n").unwrap();
+    fs::write(dir.path().join("vendor/lib/keep.rs"), "fn keep() {}\n").unwrap();
+
+    let names = |opts: &Options| -> Vec<String> {
+        filtered_paths(dir.path(), opts)
+            .unwrap()
+            .iter()
+            .map(|p| p.strip_prefix(dir.path()).unwrap().to_string_lossy().replace('\\', "/"))
+            .collect()
+    };
+    assert_eq!(names(&Options::default()), ["main.rs", "vendor/lib/keep.rs"]);
+
+    let opts = Options {
+        include_vendored: true,
+        include_generated: true,
+        ..Options::default()
+    };
+    assert_eq!(
+        names(&opts),
+        ["main.rs", "msg.pb.rs", "vendor/lib/dep.rs", "vendor/lib/keep.rs"]
+    );
+}
+
+#[test]
+fn gitattributes_linguist_skips_are_listed() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    fs::create_dir_all(dir.path().join("vendor")).unwrap();
+    fs::write(
+        dir.path().join(".gitattributes"),
+        "vendor/** linguist-vendored\n*.pb.rs linguist-generated\n",
+    )
+    .unwrap();
+    fs::write(dir.path().join("main.rs"), "fn main() {}\n").unwrap();
+    fs::write(dir.path().join("msg.pb.rs"), "struct Msg;\n").unwrap();
+    fs::write(dir.path().join("vendor/dep.rs"), "fn dep() {}\n").unwrap();
+
+    let result = count_tokens_in_path(dir.path(), &Options::default()).unwrap();
+    assert_eq!(result.files.len(), 1);
+    assert_eq!(result.skipped.len(), 2);
+    assert!(matches!(
+        &result.skipped[0],
+        loctok::Warning::Generated { path } if path.ends_with("msg.pb.rs")
+    ));
+    assert!(matches!(
+        &result.skipped[1],
+        loctok::Warning::Vendored { path } if path.ends_with("vendor/dep.rs")
+    ));
+
+    // Listed for --fail-on-skipped, but not warned about on stderr
+    let run = |extra: &[&str]| {
+        assert_cmd::Command::cargo_bin("loctok")
+            .expect("bin")
+            .arg(dir.path())
+            .arg("--no-cache")
+            .args(extra)
+            .output()
+            .unwrap()
+    };
+    let out = run(&[]);
+    assert!(out.status.success());
+    assert!(!String::from_utf8_lossy(&out.stderr).contains("warn:"));
+    let out = run(&["--fail-on-skipped=vendored"]);
+    assert_eq!(out.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("skipping vendored file"), "{stderr}");
+    assert!(!stderr.contains("msg.pb.rs"), "{stderr}");
+}
+
+#[test]
+fn ancestor_gitignore_applies_when_scanning_a_subdirectory() {
+    for with_git in [true, false] {
+        let dir = tempfile::tempdir().expect("tempdir");
+        fs::create_dir_all(dir.path().join("src/gen")).unwrap();
+        fs::write(dir.path().join(".gitignore"), "src/gen/\n*.log\n").unwrap();
+        fs::write(dir.path().join("src/lib.rs"), "fn a() {}\n").unwrap();
+        fs::write(dir.path().join("src/gen/out.rs"), "fn b() {}\n").unwrap();
+        fs::write(dir.path().join("src/debug.log"), "noise\n").unwrap();
+        if with_git {
+            let status = std::process::Command::new("git")
+                .arg("-C")
+                .arg(dir.path())
+                .args(["init", "-q"])
+                .status()
+                .expect("git");
+            assert!(status.success());
+        }
+
+        let paths = filtered_paths(dir.path().join("src"), &Options::default()).unwrap();
+        assert_eq!(paths, [dir.path().join("src/lib.rs")], "with_git={with_git}");
+    }
+}
+
+#[test]
+fn git_only_lists_tracked_files() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let git = |args: &[&str]| {
+        let status = std::process::Command::new("git")
+            .arg("-C")
+            .arg(dir.path())
+            .args(args)
+            .status()
+            .expect("git");
+        assert!(status.success());
+    };
+    fs::write(dir.path().join("tracked.rs"), "fn a() {}\n").unwrap();
+    fs::write(dir.path().join("scratch.rs"), "fn b() {}\n").unwrap();
+    git(&["init", "-q"]);
+    git(&["add", "tracked.rs"]);
+
+    let opts = Options {
+        git_only: true,
+        ..Options::default()
+    };
+    let paths = filtered_paths(dir.path(), &opts).unwrap();
+    assert_eq!(paths, [dir.path().join("tracked.rs")]);
+
+    // Outside a repository: warn and fall back to the walker
+    let plain = tempfile::tempdir().expect("tempdir");
+    fs::write(plain.path().join("a.rs"), "fn a() {}\n").unwrap();
+    let warned = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let flag = warned.clone();
+    let opts = Options {
+        git_only: true,
+        on_warning: Some(loctok::WarningCallback::new(move |_| {
+            flag.store(true, std::sync::atomic::Ordering::SeqCst)
+        })),
+        ..Options::default()
+    };
+    assert_eq!(filtered_paths(plain.path(), &opts).unwrap().len(), 1);
+    assert!(warned.load(std::sync::atomic::Ordering::SeqCst));
+}
+
+#[test]
+fn staged_reports_token_deltas() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let git = |args: &[&str]| {
+        let status = std::process::Command::new("git")
+            .arg("-C")
+            .arg(dir.path())
+            .args(["-c", "user.name=t", "-c", "user.email=t@example.com"])
+            .args(args)
+            .status()
+            .expect("git");
+        assert!(status.success());
+    };
+    fs::write(dir.path().join("a.rs"), "fn a() {}\n").unwrap();
+    fs::write(dir.path().join("old.txt"), "to be removed\n").unwrap();
+    git(&["init", "-q"]);
+    git(&["add", "."]);
+    git(&["commit", "-qm", "init"]);
+
+    fs::write(dir.path().join("a.rs"), "fn a() { let x = 1; }\n").unwrap();
+    fs::write(dir.path().join("new.md"), "hello world\n").unwrap();
+    fs::write(dir.path().join("unstaged.md"), "not staged\n").unwrap();
+    git(&["rm", "-q", "old.txt"]);
+    git(&["add", "a.rs", "new.md"]);
+
+    let changes = loctok::count_staged(dir.path(), &Options::default()).unwrap();
+    let paths: Vec<_> = changes.iter().map(|c| c.path.to_string_lossy().to_string()).collect();
+    assert_eq!(paths, ["a.rs", "new.md", "old.txt"]);
+    assert!(changes[0].before > 0 && changes[0].after > changes[0].before);
+    assert_eq!(changes[1].before, 0);
+    assert_eq!(changes[2].after, 0);
+    assert!(changes[2].delta() < 0);
+
+    assert_cmd::Command::cargo_bin("loctok")
+        .expect("bin")
+        .arg("--no-cache")
+        .current_dir(dir.path())
+        .args(["staged", "--fail-if-total-over", "1"])
+        .assert()
+        .code(1)
+        .stdout(predicates::str::contains("total"));
+}
+
+#[test]
+fn repository_url_is_cloned_and_counted() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let git = |args: &[&str]| {
+        let status = std::process::Command::new("git")
+            .arg("-C")
+            .arg(dir.path())
+            .args(["-c", "user.name=t", "-c", "user.email=t@example.com"])
+            .args(args)
+            .status()
+            .expect("git");
+        assert!(status.success());
+    };
+    git(&["init", "-q"]);
+    fs::write(dir.path().join("a.rs"), "fn a() {}\n").unwrap();
+    git(&["add", "."]);
+    git(&["commit", "-qm", "add a"]);
+    git(&["tag", "v1"]);
+    fs::write(dir.path().join("b.rs"), "fn b() {}\n").unwrap();
+    git(&["add", "."]);
+    git(&["commit", "-qm", "add b"]);
+    let url = format!("file://{}", dir.path().display());
+
+    let run = |extra: &[&str]| {
+        let out = assert_cmd::Command::cargo_bin("loctok")
+            .expect("bin")
+            .arg(&url)
+            .args(["--format", "json", "--no-cache", "--progress-to", "none"])
+            .args(extra)
+            .output()
+            .expect("run");
+        assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+        let report: serde_json::Value = serde_json::from_slice(&out.stdout).expect("json");
+        let files = report["files"].as_array().unwrap().len();
+        (files, String::from_utf8(out.stderr).unwrap())
+    };
+
+    assert_eq!(run(&[]).0, 2);
+
+    let (files, stderr) = run(&["--ref", "v1", "--keep-clone"]);
+    assert_eq!(files, 1);
+    let kept = stderr
+        .lines()
+        .find_map(|l| l.strip_prefix("Keeping the clone at "))
+        .expect("clone path");
+    assert!(Path::new(kept).join("a.rs").is_file());
+    assert!(!Path::new(kept).join("b.rs").exists());
+    fs::remove_dir_all(Path::new(kept).parent().unwrap()).unwrap();
+}
+
+#[test]
+fn history_counts_tokens_at_each_commit() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let git = |args: &[&str]| {
+        let status = std::process::Command::new("git")
+            .arg("-C")
+            .arg(dir.path())
+            .args(["-c", "user.name=t", "-c", "user.email=t@example.com"])
+            .args(args)
+            .status()
+            .expect("git");
+        assert!(status.success());
+    };
+    let tokens = |text: &str| count_tokens_in_text(&get_encoder("o200k_base").unwrap(), text);
+    let (a, b) = ("fn a() {}\n", "fn b() { let x = 1; }\n");
+    git(&["init", "-q"]);
+    fs::write(dir.path().join("a.rs"), a).unwrap();
+    git(&["add", "."]);
+    git(&["commit", "-qm", "add a"]);
+    fs::write(dir.path().join("b.rs"), b).unwrap();
+    fs::write(dir.path().join(".hidden.rs"), b).unwrap();
+    git(&["add", "."]);
+    git(&["commit", "-qm", "add b"]);
+    git(&["rm", "-q", "a.rs"]);
+    git(&["commit", "-qm", "drop a"]);
+    // Uncommitted changes are not part of any commit
+    fs::write(dir.path().join("b.rs"), "changed\n").unwrap();
+
+    let commits = loctok::count_history(dir.path(), 10, &Options::default()).unwrap();
+    let series: Vec<_> = commits
+        .iter()
+        .map(|c| (c.summary.as_str(), c.files, c.tokens))
+        .collect();
+    assert_eq!(
+        series,
+        [
+            ("add a", 1, tokens(a)),
+            ("add b", 2, tokens(a) + tokens(b)),
+            ("drop a", 1, tokens(b)),
+        ]
+    );
+    assert_eq!(loctok::count_history(dir.path(), 1, &Options::default()).unwrap().len(), 1);
+
+    let out = assert_cmd::Command::cargo_bin("loctok")
+        .expect("bin")
+        .arg("--no-cache")
+        .current_dir(dir.path())
+        .args(["history", "--last", "2", "--format", "json"])
+        .output()
+        .expect("run");
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    let report: serde_json::Value = serde_json::from_slice(&out.stdout).expect("json");
+    assert_eq!(report["commits"].as_array().unwrap().len(), 2);
+    assert_eq!(report["commits"][1]["summary"], "drop a");
+    assert_eq!(report["commits"][1]["commit"].as_str().unwrap().len(), 40);
+
+    // --fail-if-total-over checks the newest commit only
+    let limit = |max: usize| {
+        assert_cmd::Command::cargo_bin("loctok")
+            .expect("bin")
+            .arg("--no-cache")
+            .current_dir(dir.path())
+            .args(["history", "--last", "2", "--fail-if-total-over"])
+            .arg(max.to_string())
+            .output()
+            .expect("run")
+    };
+    assert!(limit(tokens(b)).status.success());
+    let out = limit(tokens(b) - 1);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("threshold exceeded"));
+}
+
+#[test]
+fn skipped_files_are_reported_in_result() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    fs::write(dir.path().join("ok.rs"), "fn ok() {}\n").unwrap();
+    // Sparse file: over the size limit without writing 64MB to disk
+    let big = dir.path().join("huge.txt");
+    fs::File::create(&big).unwrap().set_len(65 * 1024 * 1024).unwrap();
+
+    let opts = Options {
+        quiet: true,
+        ..Options::default()
+    };
+    let res = count_tokens_in_path(dir.path(), &opts).unwrap();
+    assert_eq!(res.files.len(), 1);
+    assert_eq!(res.skipped.len(), 1);
+    assert!(matches!(res.skipped[0], loctok::Warning::TooLarge { .. }));
+    assert_eq!(res.skipped[0].path(), Some(big.as_path()));
+}
+
+#[test]
+fn size_bounds_skip_small_and_large_files() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    fs::write(dir.path().join("stub.rs"), "fn a() {}\n").unwrap();
+    fs::write(dir.path().join("mid.rs"), "fn b() {}\n".repeat(20)).unwrap();
+    fs::write(dir.path().join("big.rs"), "fn c() {}\n".repeat(200)).unwrap();
+
+    let opts = Options {
+        min_file_bytes: Some(100),
+        max_file_bytes: Some(1000),
+        quiet: true,
+        ..Options::default()
+    };
+    let res = count_tokens_in_path(dir.path(), &opts).unwrap();
+    assert_eq!(res.files.len(), 1);
+    assert!(res.files[0].path.ends_with("mid.rs"));
+    assert!(matches!(
+        &res.skipped[..],
+        [
+            loctok::Warning::TooLarge { bytes: 2000, .. },
+            loctok::Warning::TooSmall { bytes: 10, .. }
+        ]
+    ));
+
+    // Human-readable sizes; files under the minimum are not reported as warnings
+    let out = assert_cmd::Command::cargo_bin("loctok")
+        .expect("bin")
+        .arg(dir.path())
+        .args(["--format", "json", "--no-cache", "--min-size", "0.1KB", "--max-size", "1kb"])
+        .output()
+        .expect("run");
+    assert!(out.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&out.stdout).expect("json");
+    assert_eq!(report["files"].as_array().unwrap().len(), 1);
+    assert_eq!(report["skipped"].as_array().unwrap().len(), 2);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("skipping large file (2000 bytes)"), "{stderr}");
+    assert!(!stderr.contains("small file"), "{stderr}");
+}
+
+#[test]
+fn quiet_suppresses_skip_warnings() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    fs::write(dir.path().join("a.rs"), "fn a() {}\n").unwrap();
+    let run = |extra: &[&str]| {
+        let out = assert_cmd::Command::cargo_bin("loctok")
+            .expect("bin")
+            .arg(dir.path())
+            .args(["--no-cache", "--progress-to", "none", "--max-size", "1"])
+            .args(extra)
+            .output()
+            .expect("run");
+        assert!(out.status.success());
+        String::from_utf8(out.stderr).unwrap()
+    };
+
+    let stderr = run(&[]);
+    assert!(stderr.contains("warn: skipping large file"), "{stderr}");
+    for flag in ["-q", "--quiet"] {
+        let stderr = run(&[flag]);
+        assert!(!stderr.contains("warn:"), "{flag}: {stderr}");
+    }
+}
+
+#[test]
+fn fail_on_skipped_exits_nonzero_for_matching_reasons() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    fs::write(dir.path().join("stub.rs"), "fn a() {}\n").unwrap();
+    fs::write(dir.path().join("mid.rs"), "fn b() {}\n".repeat(20)).unwrap();
+
+    let run = |extra: &[&str]| {
+        assert_cmd::Command::cargo_bin("loctok")
+            .expect("bin")
+            .arg(dir.path())
+            .args(["--no-cache", "--progress-to", "none", "--min-size", "100"])
+            .args(extra)
+            .output()
+            .expect("run")
+    };
+
+    let out = run(&["--fail-on-skipped"]);
+    assert_eq!(out.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("skipped: skipping small file (10 bytes)"), "{stderr}");
+    assert!(stderr.contains("stub.rs"), "{stderr}");
+
+    let out = run(&["--fail-on-skipped=too-small,read"]);
+    assert_eq!(out.status.code(), Some(1));
+
+    // Skips for other reasons are tolerated
+    let out = run(&["--fail-on-skipped=read,too-large"]);
+    assert!(out.status.success());
+    assert!(!String::from_utf8_lossy(&out.stderr).contains("skipped:"));
+}
+
+#[cfg(unix)]
+#[test]
+fn unreadable_directory_is_skipped_without_aborting_the_walk() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().expect("tempdir");
+    fs::write(dir.path().join("ok.rs"), "fn ok() {}\n").unwrap();
+    let locked = dir.path().join("locked");
+    fs::create_dir(&locked).unwrap();
+    fs::write(locked.join("hidden.rs"), "fn hidden() {}\n").unwrap();
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+    // Root (e.g. in a container) reads the directory anyway; nothing to simulate then
+    if fs::read_dir(&locked).is_ok() {
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+        return;
+    }
+
+    let run = |extra: &[&str]| {
+        assert_cmd::Command::cargo_bin("loctok")
+            .expect("bin")
+            .arg(dir.path())
+            .args(["--format", "json", "--no-cache", "--progress-to", "none"])
+            .args(extra)
+            .output()
+            .expect("run")
+    };
+
+    let out = run(&[]);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("unreadable entries skipped by the walk: 1"), "{stderr}");
+    let report: serde_json::Value = serde_json::from_slice(&out.stdout).expect("json");
+    let files = report["files"].as_array().unwrap();
+    assert_eq!(files.len(), 1);
+    assert!(files[0]["path"].as_str().unwrap().ends_with("ok.rs"));
+    let skipped = report["skipped"].as_array().unwrap();
+    assert_eq!(skipped.len(), 1);
+    assert!(skipped[0]["path"].as_str().unwrap().ends_with("locked"));
+
+    let out = run(&["--fail-on-walk-error"]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("strict scan failed: 1 skipped"));
+
+    // Let the tempdir clean up
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+#[test]
+fn exclude_empty_moves_blank_files_to_skipped() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    fs::write(dir.path().join("ok.rs"), "fn ok() {}\n").unwrap();
+    fs::write(dir.path().join("empty.rs"), "").unwrap();
+    fs::write(dir.path().join("blank.txt"), "  \n\t\n").unwrap();
+
+    let res = count_tokens_in_path(dir.path(), &Options::default()).unwrap();
+    assert_eq!(res.files.len(), 3);
+
+    let opts = Options {
+        exclude_empty: true,
+        ..Options::default()
+    };
+    let (res, texts) = loctok::count_tokens_and_collect_texts(dir.path(), &opts).unwrap();
+    assert_eq!(res.files.len(), 1);
+    assert_eq!(texts.len(), 1);
+    let skipped: Vec<_> = res
+        .skipped
+        .iter()
+        .map(|w| w.path().unwrap().file_name().unwrap().to_owned())
+        .collect();
+    assert_eq!(skipped, ["blank.txt", "empty.rs"]);
+    assert!(res
+        .skipped
+        .iter()
+        .all(|w| matches!(w, loctok::Warning::Empty { .. })));
+}
+
+#[test]
+fn utf8_binary_files_are_skipped_unless_included() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    fs::write(dir.path().join("ok.rs"), "fn ok() {}\n").unwrap();
+    // Valid UTF-8, but mostly control characters like a packed record file
+    let blob: String = (0..4096u32)
+        .map(|i| char::from_u32(if i % 3 == 0 { 0x41 + i % 26 } else { 1 + i % 8 }).unwrap())
+        .collect();
+    fs::write(dir.path().join("records.dat"), &blob).unwrap();
+
+    let opts = Options {
+        quiet: true,
+        ..Options::default()
+    };
+    let res = count_tokens_in_path(dir.path(), &opts).unwrap();
+    assert_eq!(res.files.len(), 1);
+    assert_eq!(res.skipped.len(), 1);
+    assert!(matches!(
+        res.skipped[0],
+        loctok::Warning::SuspectedBinary { .. }
+    ));
+    assert_eq!(
+        res.skipped[0].path(),
+        Some(dir.path().join("records.dat").as_path())
+    );
+
+    let opts = Options {
+        include_binary: true,
+        ..opts
+    };
+    let res = count_tokens_in_path(dir.path(), &opts).unwrap();
+    assert_eq!(res.files.len(), 2);
+    assert!(res.skipped.is_empty());
+}
+
+#[test]
+fn sample_bytes_extrapolates_large_files() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let big = "fn step(x: u32) -> u32 { x + 1 }\n".repeat(300);
+    fs::write(dir.path().join("big.rs"), &big).unwrap();
+    fs::write(dir.path().join("small.rs"), "fn ok() {}\n").unwrap();
+
+    let exact = count_tokens_in_path(dir.path(), &Options::default()).unwrap();
+    let opts = Options {
+        sample_bytes: Some(1000),
+        ..Options::default()
+    };
+    let sampled = count_tokens_in_path(dir.path(), &opts).unwrap();
+    let by_name = |res: &loctok::CountResult, name: &str| {
+        res.files
+            .iter()
+            .find(|f| f.path.ends_with(name))
+            .cloned()
+            .unwrap()
+    };
+
+    let (big_exact, big_sampled) = (by_name(&exact, "big.rs"), by_name(&sampled, "big.rs"));
+    assert!(big_sampled.sampled && !big_exact.sampled);
+    assert_eq!(big_sampled.bytes, big.len() as u64);
+    let diff = big_sampled.tokens.abs_diff(big_exact.tokens);
+    assert!(diff * 20 < big_exact.tokens, "{big_sampled:?} vs {big_exact:?}");
+    assert!(big_sampled.lines.abs_diff(300) * 20 < 300);
+
+    let small = by_name(&sampled, "small.rs");
+    assert!(!small.sampled);
+    assert_eq!(small.tokens, by_name(&exact, "small.rs").tokens);
+
+    let out = assert_cmd::Command::cargo_bin("loctok")
+        .expect("bin")
+        .arg(dir.path())
+        .args(["--format", "json", "--no-cache", "--sample-bytes", "1000"])
+        .output()
+        .expect("run");
+    assert!(out.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&out.stdout).expect("json");
+    assert_eq!(report["sample_bytes"], 1000);
+    let sampled_paths: Vec<_> = report["files"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|f| f["sampled"] == true)
+        .map(|f| f["path"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(sampled_paths.len(), 1);
+    assert!(sampled_paths[0].ends_with("big.rs"));
+}
+
+#[test]
+fn no_line_count_zeroes_lines_and_hides_the_column() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    fs::write(dir.path().join("ok.rs"), "fn ok() {}\n").unwrap();
+    fs::write(dir.path().join("empty.rs"), "").unwrap();
+    let opts = Options {
+        count_lines: false,
+        exclude_empty: true,
+        ..Options::default()
+    };
+    let res = count_tokens_in_path(dir.path(), &opts).unwrap();
+    assert_eq!(res.files.len(), 1);
+    assert_eq!(res.files[0].lines, 0);
+    assert!(res.files[0].tokens > 0);
+    assert_eq!(res.skipped.len(), 1);
+
+    let run = |format: &str| {
+        let out = assert_cmd::Command::cargo_bin("loctok")
+            .expect("bin")
+            .arg(dir.path())
+            .args(["--format", format, "--no-cache", "--no-line-count"])
+            .output()
+            .expect("run");
+        assert!(out.status.success());
+        String::from_utf8(out.stdout).unwrap()
+    };
+    let table = run("table");
+    assert!(table.contains("token count"));
+    assert!(!table.contains("lines of code"));
+    let report: serde_json::Value = serde_json::from_str(&run("json")).expect("json");
+    assert!(report["files"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .all(|f| f["lines"] == 0));
+}
+
+#[test]
+fn total_and_blank_lines_are_reported_alongside_loc() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    fs::write(dir.path().join("a.rs"), "fn a() {}\n\n  \nfn b() {}\n").unwrap();
+    fs::write(dir.path().join("b.rs"), "fn c() {}\r\n\r\nfn d() {}").unwrap();
+
+    let res = count_tokens_in_path(dir.path(), &Options::default()).unwrap();
+    let lines: Vec<_> = res
+        .files
+        .iter()
+        .map(|f| (f.lines, f.total_lines, f.blank_lines))
+        .collect();
+    assert_eq!(lines, [(2, 4, 2), (2, 3, 1)]);
+
+    let out = assert_cmd::Command::cargo_bin("loctok")
+        .expect("bin")
+        .arg(dir.path())
+        .args(["--no-cache", "--progress-to", "none", "--show", "total-lines,blank-lines"])
+        .output()
+        .expect("run");
+    assert!(out.status.success());
+    let table = String::from_utf8(out.stdout).unwrap();
+    assert!(table.contains("total lines") && table.contains("blank lines"), "{table}");
+    assert!(!table.contains("lines of code"), "{table}");
+
+    let out = assert_cmd::Command::cargo_bin("loctok")
+        .expect("bin")
+        .arg(dir.path())
+        .args(["--format", "json", "--no-cache"])
+        .output()
+        .expect("run");
+    let report: serde_json::Value = serde_json::from_slice(&out.stdout).expect("json");
+    assert_eq!(report["files"][0]["total_lines"], 4);
+    assert_eq!(report["files"][0]["blank_lines"], 2);
+    assert_eq!(report["by_language"][0]["total_lines"], 7);
+    assert_eq!(report["by_language"][0]["blank_lines"], 3);
+}
+
+#[test]
+fn merged_results_match_a_combined_scan() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    fs::create_dir_all(dir.path().join("a")).unwrap();
+    fs::create_dir_all(dir.path().join("b")).unwrap();
+    fs::write(dir.path().join("a/x.rs"), "fn x() {}\n").unwrap();
+    fs::write(dir.path().join("a/y.md"), "# Title\n\ntext\n").unwrap();
+    fs::write(dir.path().join("b/z.rs"), "fn z() { let v = 2; }\n").unwrap();
+
+    let opts = Options::default();
+    let merged = count_tokens_in_path(dir.path().join("a"), &opts)
+        .unwrap()
+        .merge(count_tokens_in_path(dir.path().join("b"), &opts).unwrap());
+    let combined = count_tokens_in_path(dir.path(), &opts).unwrap();
+
+    assert_eq!(merged.total, combined.total);
+    assert_eq!(merged.files.len(), combined.files.len());
+    let summary = |r: &loctok::CountResult| {
+        loctok::aggregate_by_language(&r.files)
+            .into_iter()
+            .map(|l| (l.language, l.lines, l.tokens))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(summary(&merged), summary(&combined));
+}
+
+#[test]
+fn tree_json_nests_nodes_with_totals() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    fs::create_dir_all(dir.path().join("src")).unwrap();
+    fs::write(dir.path().join("src/a.rs"), "fn a() {}\n").unwrap();
+    fs::write(dir.path().join("b.md"), "hello\n").unwrap();
+
+    let out = assert_cmd::Command::cargo_bin("loctok")
+        .expect("bin")
+        .arg(dir.path())
+        .args(["--format", "tree-json", "--no-cache"])
+        .output()
+        .expect("run");
+    assert!(out.status.success());
+    let tree: serde_json::Value = serde_json::from_slice(&out.stdout).expect("json");
+    assert_eq!(tree["kind"], "dir");
+    assert_eq!(tree["lines"], 2);
+    let children = tree["children"].as_array().expect("children");
+    assert_eq!(children[0]["name"], "b.md");
+    assert_eq!(children[0]["kind"], "file");
+    assert_eq!(children[1]["name"], "src");
+    assert_eq!(children[1]["children"][0]["name"], "a.rs");
+    assert_eq!(children[1]["tokens"], children[1]["children"][0]["tokens"]);
+}
+
+#[test]
+fn tree_depth_prunes_nodes_but_keeps_totals() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    fs::create_dir_all(dir.path().join("src/deep")).unwrap();
+    fs::write(dir.path().join("src/a.rs"), "fn a() {}\n").unwrap();
+    fs::write(dir.path().join("src/deep/b.rs"), "fn b() {}\nfn c() {}\n").unwrap();
+
+    let out = assert_cmd::Command::cargo_bin("loctok")
+        .expect("bin")
+        .arg(dir.path())
+        .args(["--format", "tree-json", "--tree-depth", "1", "--no-cache"])
+        .output()
+        .expect("run");
+    assert!(out.status.success());
+    let tree: serde_json::Value = serde_json::from_slice(&out.stdout).expect("json");
+    let src = &tree["children"][0];
+    assert_eq!(src["name"], "src");
+    assert_eq!(src["lines"], 3);
+    assert_eq!(src["children"].as_array().map(Vec::len), Some(0));
+}
+
+#[test]
+fn tree_uses_conventional_branch_glyphs() {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let out = assert_cmd::Command::cargo_bin("loctok")
+        .expect("bin")
+        .arg(&root)
+        .args(["--format", "tree", "--color", "never", "--no-cache"])
+        .output()
+        .expect("run");
+    assert!(out.status.success());
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    let names: Vec<&str> = stdout
+        .lines()
+        .skip(2)
+        .map(|l| l.trim_end_matches(|c: char| c.is_ascii_digit() || c == ',' || c == ' '))
+        .collect();
+    assert_eq!(
+        names,
+        [
+            "fixtures/",
+            "├── nested/",
+            "│   └── kept2.txt",
+            "└── kept.txt"
+        ]
+    );
+}
+
+#[test]
+fn estimate_mode_skips_the_tokenizer() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let text = "fn main() { println!(\"héllo\"); }\n";
+    fs::write(dir.path().join("a.rs"), text).unwrap();
+    let opts = Options {
+        estimate: true,
+        ..Options::default()
+    };
+    let fc = count_file(dir.path().join("a.rs"), &opts).unwrap();
+    assert_eq!(fc.tokens, text.chars().count().div_ceil(4));
+
+    let out = assert_cmd::Command::cargo_bin("loctok")
+        .expect("bin")
+        .arg(dir.path())
+        .args(["--format", "json", "--estimate", "--no-cache"])
+        .output()
+        .expect("run");
+    let v: serde_json::Value = serde_json::from_slice(&out.stdout).expect("json");
+    assert_eq!(v["estimated"], true);
+    assert_eq!(v["total"], fc.tokens);
+}
+
+#[test]
+fn model_flag_selects_encoding() {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let encoding_for = |model: &str| {
+        let out = assert_cmd::Command::cargo_bin("loctok")
+            .expect("bin")
+            .arg(&root)
+            .args(["--format", "json", "--no-cache", "--model", model])
+            .output()
+            .expect("run");
+        assert!(out.status.success());
+        let v: serde_json::Value = serde_json::from_slice(&out.stdout).expect("json");
+        v["encoding"].as_str().unwrap().to_string()
+    };
+    assert_eq!(encoding_for("gpt-4o"), "o200k_base");
+    assert_eq!(encoding_for("GPT-4o-mini"), "o200k_base");
+    assert_eq!(encoding_for("gpt-4-turbo"), "cl100k_base");
+    assert_eq!(encoding_for("gpt-3.5-turbo"), "cl100k_base");
+
+    assert_cmd::Command::cargo_bin("loctok")
+        .expect("bin")
+        .arg("--no-cache")
+        .args(["--model", "not-a-model"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("supported: gpt-5"));
+}
+
+#[test]
+fn list_encodings_and_invalid_encoding_name_the_options() {
+    let out = assert_cmd::Command::cargo_bin("loctok")
+        .expect("bin")
+        .arg("--no-cache")
+        .arg("--list-encodings")
+        .output()
+        .expect("run");
+    assert!(out.status.success());
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert!(stdout.starts_with("o200k_base"), "{stdout}");
+    assert!(stdout.contains("cl100k_base   100,000 tokens  ChatGPT"), "{stdout}");
+
+    assert_cmd::Command::cargo_bin("loctok")
+        .expect("bin")
+        .arg("--no-cache")
+        .args(["--encoding", "nope"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("r50k_base"));
+
+    // Config files bypass the CLI parser, so the library error lists them too
+    let dir = tempfile::tempdir().expect("tempdir");
+    fs::write(dir.path().join("loctok.toml"), "encoding = \"nope\"\n").unwrap();
+    assert_cmd::Command::cargo_bin("loctok")
+        .expect("bin")
+        .arg(dir.path())
+        .arg("--no-cache")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "Unsupported encoding: nope (supported: o200k_base, cl100k_base",
+        ));
+}
+
+#[test]
+fn list_languages_prints_extensions_per_language() {
+    let out = assert_cmd::Command::cargo_bin("loctok")
+        .expect("bin")
+        .arg("--no-cache")
+        .arg("--list-languages")
+        .output()
+        .expect("run");
+    assert!(out.status.success());
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    let rust = stdout.lines().find(|l| l.starts_with("Rust ")).expect("Rust row");
+    assert!(rust.ends_with(" .rs"), "{rust}");
+    assert!(stdout.lines().any(|l| l.starts_with("TypeScript ") && l.contains(".ts")));
+}
+
+#[test]
+fn print_schema_matches_the_json_report() {
+    let run = |args: &[&str]| {
+        let out = assert_cmd::Command::cargo_bin("loctok")
+            .expect("bin")
+            .args(args)
+            .output()
+            .expect("run");
+        assert!(out.status.success());
+        serde_json::from_slice::<serde_json::Value>(&out.stdout).expect("json")
+    };
+    let schema = run(&["--print-schema"]);
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    // --stats adds the optional fields, so every property appears
+    let report = run(&[
+        root.to_str().unwrap(),
+        "--format",
+        "json",
+        "--no-cache",
+        "--stats",
+        "--group-by",
+        "extension",
+        "--largest",
+        "1",
+    ]);
+
+    let keys = |v: &serde_json::Value| {
+        let mut keys: Vec<String> = v.as_object().unwrap().keys().cloned().collect();
+        keys.sort();
+        keys
+    };
+    assert_eq!(keys(&schema["properties"]), keys(&report));
+    let defs = &schema["definitions"];
+    assert_eq!(keys(&defs["ReportFile"]["properties"]), keys(&report["files"][0]));
+    assert_eq!(
+        keys(&defs["LangSummary"]["properties"]),
+        keys(&report["by_language"][0])
+    );
+    assert_eq!(
+        keys(&defs["ExtSummary"]["properties"]),
+        keys(&report["by_extension"][0])
+    );
+    assert_eq!(
+        keys(&defs["ReportLargest"]["properties"]),
+        keys(&report["largest_files"][0])
+    );
+}
+
+#[test]
+fn largest_lists_top_files_by_tokens() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    fs::write(dir.path().join("small.rs"), "fn a() {}\n").unwrap();
+    fs::write(dir.path().join("big.py"), "x = 1\n".repeat(50)).unwrap();
+    fs::write(dir.path().join("mid.rs"), "fn b() {}\n".repeat(5)).unwrap();
+
+    let res = count_tokens_in_path(dir.path(), &Options::default()).unwrap();
+    let top: Vec<_> = loctok::largest_files(&res.files, 2)
+        .iter()
+        .map(|f| f.path.file_name().unwrap().to_string_lossy().into_owned())
+        .collect();
+    assert_eq!(top, ["big.py", "mid.rs"]);
+    assert_eq!(loctok::largest_files(&res.files, 10).len(), 3);
+
+    let run = |format: &str| {
+        let out = assert_cmd::Command::cargo_bin("loctok")
+            .expect("bin")
+            .arg(dir.path())
+            .args(["--format", format, "--no-cache", "--color", "never", "--largest", "2"])
+            .output()
+            .expect("run");
+        assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+        String::from_utf8(out.stdout).unwrap()
+    };
+    let table = run("table");
+    let listed: Vec<&str> = table.lines().skip_while(|l| !l.contains("Largest files")).collect();
+    assert!(listed.iter().any(|l| l.contains("big.py") && l.contains("Python")), "{table}");
+    assert!(!listed.iter().any(|l| l.contains("small.rs")), "{table}");
+
+    let report: serde_json::Value = serde_json::from_str(&run("json")).expect("json");
+    let largest = report["largest_files"].as_array().unwrap();
+    assert_eq!(largest.len(), 2);
+    assert_eq!(largest[0]["language"], "Python");
+    assert_eq!(largest[0]["lines"], 50);
+}
+
+#[test]
+fn compare_encodings_matches_separate_scans() {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let run = |args: &[&str]| {
+        let out = assert_cmd::Command::cargo_bin("loctok")
+            .expect("bin")
+            .arg(&root)
+            .args(["--format", "json", "--no-cache"])
+            .args(args)
+            .output()
+            .expect("run");
+        assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+        serde_json::from_slice::<serde_json::Value>(&out.stdout).expect("json")
+    };
+    let compared = run(&["--compare-encodings", "cl100k_base,p50k_base"]);
+    assert_eq!(compared["encodings"], serde_json::json!(["cl100k_base", "p50k_base"]));
+    for (i, encoding) in ["cl100k_base", "p50k_base"].iter().enumerate() {
+        let single = run(&["--encoding", encoding]);
+        assert_eq!(compared["total"][i], single["total"], "{encoding}");
+        assert_eq!(
+            compared["by_language"][0]["lines"],
+            single["by_language"][0]["lines"]
+        );
+    }
+
+    let out = assert_cmd::Command::cargo_bin("loctok")
+        .expect("bin")
+        .arg("--no-cache")
+        .arg(&root)
+        .args(["--compare-encodings", "cl100k_base"])
+        .output()
+        .expect("run");
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("at least two encodings"));
+
+    // Thresholds still apply, against the first encoding
+    for format in ["json", "table"] {
+        let out = assert_cmd::Command::cargo_bin("loctok")
+            .expect("bin")
+            .arg("--no-cache")
+            .arg(&root)
+            .args(["--compare-encodings", "cl100k_base,p50k_base", "--format", format])
+            .args(["--fail-if-total-over", "1"])
+            .output()
+            .expect("run");
+        assert_eq!(out.status.code(), Some(1), "{format}");
+        assert!(String::from_utf8_lossy(&out.stderr).contains("threshold exceeded"));
+    }
+}
+
+#[test]
+fn svg_format_writes_a_treemap() {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let dir = tempfile::tempdir().expect("tempdir");
+    let svg_path = dir.path().join("repo.svg");
+    let out = assert_cmd::Command::cargo_bin("loctok")
+        .expect("bin")
+        .arg(&root)
+        .args(["--format", "svg", "--no-cache", "-o"])
+        .arg(&svg_path)
+        .output()
+        .expect("run");
+    assert!(out.status.success());
+    let svg = fs::read_to_string(&svg_path).expect("svg written");
+    assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\""));
+    assert!(svg.ends_with("</svg>\n"));
+    assert!(!svg.contains("NaN"));
+    // The root, `nested/`, and one filled rectangle per file
+    assert_eq!(svg.matches("<rect").count(), 4);
+    assert!(svg.contains("/kept.txt: 3 tokens</title>"));
+    assert!(svg.contains("/nested/kept2.txt: 3 tokens</title>"));
+}
+
+#[test]
+fn output_flag_writes_the_report_to_a_file() {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let dir = tempfile::tempdir().expect("tempdir");
+    let path = dir.path().join("report.json");
+    fs::write(&path, "stale contents that are longer than nothing").unwrap();
+    let run = |format: &str| {
+        assert_cmd::Command::cargo_bin("loctok")
+            .expect("bin")
+            .arg(&root)
+            .args(["--format", format, "--no-cache", "--progress-to", "none", "-o"])
+            .arg(&path)
+            .output()
+            .expect("run")
+    };
+
+    let out = run("json");
+    assert!(out.status.success());
+    assert!(out.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("Wrote") && stderr.contains("report.json"), "{stderr}");
+    let report: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&path).unwrap()).expect("json");
+    assert_eq!(report["files"].as_array().unwrap().len(), 2);
+
+    // Tables are written without color codes
+    let out = run("table");
+    assert!(out.status.success());
+    assert!(out.stdout.is_empty());
+    let table = fs::read_to_string(&path).unwrap();
+    assert!(table.contains("SUM:") && !table.contains('\x1b'), "{table}");
+}
+
+#[test]
+fn update_baseline_writes_the_json_report_alongside_normal_output() {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let dir = tempfile::tempdir().expect("tempdir");
+    let path = dir.path().join("baseline.json");
+    fs::write(&path, "stale").unwrap();
+    let run = |extra: &[&str]| {
+        assert_cmd::Command::cargo_bin("loctok")
+            .expect("bin")
+            .arg(&root)
+            .args(["--no-cache", "--progress-to", "none"])
+            .args(extra)
+            .output()
+            .expect("run")
+    };
+
+    let out = run(&["--color", "never", "--update-baseline", path.to_str().unwrap()]);
+    assert!(out.status.success());
+    assert!(String::from_utf8_lossy(&out.stdout).contains("SUM:"));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("Wrote baseline to"));
+
+    // Byte-for-byte the report `--format json` prints
+    let json = run(&["--format", "json"]);
+    assert_eq!(fs::read_to_string(&path).unwrap(), String::from_utf8(json.stdout).unwrap());
+
+    // Subcommands never write it, so the flag is refused instead of silently ignored
+    for sub in ["copy", "staged", "history"] {
+        let out = assert_cmd::Command::cargo_bin("loctok")
+            .expect("bin")
+            .args(["--no-cache", "--update-baseline", path.to_str().unwrap(), sub])
+            .arg(&root)
+            .output()
+            .expect("run");
+        assert!(!out.status.success(), "{sub}");
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        assert!(stderr.contains("cannot be used with the"), "{stderr}");
+    }
+}
+
+#[test]
+fn absolute_paths_only_change_rendered_paths() {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let abs_root = fs::canonicalize(&root).unwrap();
+    let run = |format: &str, absolute: bool| {
+        let mut cmd = assert_cmd::Command::cargo_bin("loctok").expect("bin");
+        cmd.current_dir(&root)
+            .args([".", "--format", format, "--no-cache", "--progress-to", "none"]);
+        if absolute {
+            cmd.arg("--absolute-paths");
+        }
+        let out = cmd.output().expect("run");
+        assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+        String::from_utf8(out.stdout).unwrap()
+    };
+
+    let report: serde_json::Value = serde_json::from_str(&run("json", true)).expect("json");
+    let paths: Vec<PathBuf> = report["files"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|f| PathBuf::from(f["path"].as_str().unwrap()))
+        .collect();
+    assert_eq!(paths, [abs_root.join("kept.txt"), abs_root.join("nested/kept2.txt")]);
+    let relative: serde_json::Value = serde_json::from_str(&run("json", false)).expect("json");
+    assert_eq!(report["by_language"], relative["by_language"]);
+
+    let tree: serde_json::Value = serde_json::from_str(&run("tree-json", true)).expect("json");
+    assert_eq!(tree["name"], abs_root.display().to_string());
+    assert_eq!(tree["children"][0]["name"], "kept.txt");
+    assert_eq!(tree["children"][1]["children"][0]["name"], "kept2.txt");
+}
+
+#[test]
+fn group_by_extension_splits_a_language() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    fs::write(dir.path().join("a.xml"), "<a>one</a>\n").unwrap();
+    fs::write(dir.path().join("b.XML"), "<b>two</b>\n").unwrap();
+    fs::write(dir.path().join("c.props"), "<Project>three</Project>\n").unwrap();
+    fs::write(dir.path().join("Makefile"), "all:\n\ttrue\n").unwrap();
+    let res = count_tokens_in_path(dir.path(), &Options::default()).unwrap();
+
+    let langs = loctok::aggregate_by_language(&res.files);
+    let xml = langs.iter().find(|l| l.language == "XML").unwrap();
+    assert_eq!(xml.files, 3);
+
+    let exts = loctok::aggregate_by_extension(&res.files);
+    let mut keys: Vec<(&str, usize)> = exts
+        .iter()
+        .map(|e| (e.extension.as_str(), e.files))
+        .collect();
+    keys.sort();
+    assert_eq!(keys, [(".props", 1), (".xml", 2), ("Makefile", 1)]);
+    let total: usize = exts.iter().map(|e| e.tokens).sum();
+    assert_eq!(total, res.total);
+
+    let out = assert_cmd::Command::cargo_bin("loctok")
+        .expect("bin")
+        .arg(dir.path())
+        .args(["--no-cache", "--group-by", "extension"])
+        .output()
+        .expect("run");
+    assert!(out.status.success());
+    let table = String::from_utf8(out.stdout).unwrap();
+    assert!(table.contains("Extension"));
+    assert!(table.contains(".props"));
+}
+
+#[test]
+fn stats_flag_adds_timing_to_json() {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let report = |stats: bool| {
+        let mut cmd = assert_cmd::Command::cargo_bin("loctok").expect("bin");
+        cmd.arg(&root).args(["--format", "json", "--no-cache"]);
+        if stats {
+            cmd.arg("--stats");
+        }
+        let out = cmd.output().expect("run");
+        assert!(out.status.success());
+        serde_json::from_slice::<serde_json::Value>(&out.stdout).expect("json")
+    };
+    let plain = report(false);
+    assert!(plain.get("elapsed_ms").is_none());
+    let timed = report(true);
+    assert!(timed["elapsed_ms"].is_u64());
+    assert!(timed["files_per_sec"].as_f64().unwrap() > 0.0);
+}
+
+#[test]
+fn count_summary_matches_per_file_aggregation() {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let opts = Options::default();
+    let full = count_tokens_in_path(&root, &opts).expect("count ok");
+    let summary = loctok::count_summary(&root, &opts).expect("summary ok");
+    assert_eq!(summary.total, full.total);
+    let expected = loctok::aggregate_by_language(&full.files);
+    assert_eq!(summary.by_language.len(), expected.len());
+    for (got, want) in summary.by_language.iter().zip(&expected) {
+        assert_eq!(
+            (&got.language, got.files, got.lines, got.tokens),
+            (&want.language, want.files, want.lines, want.tokens)
+        );
+    }
+}
+
+#[test]
+fn summary_only_reports_languages_without_files() {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let out = assert_cmd::Command::cargo_bin("loctok")
+        .expect("bin")
+        .arg(&root)
+        .args(["--summary-only", "--format", "json", "--no-cache"])
+        .output()
+        .expect("run");
+    assert!(out.status.success());
+    let v: serde_json::Value = serde_json::from_slice(&out.stdout).expect("json");
+    assert!(v.get("files").is_none());
+    let langs = v["by_language"].as_array().expect("by_language");
+    let sum: u64 = langs.iter().map(|l| l["tokens"].as_u64().unwrap()).sum();
+    assert_eq!(v["total"].as_u64(), Some(sum));
+
+    assert_cmd::Command::cargo_bin("loctok")
+        .expect("bin")
+        .arg("--no-cache")
+        .arg(&root)
+        .args(["--summary-only", "--format", "tree"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("table, json and yaml"));
+}
+
+#[test]
+fn chat_and_prompt_overhead_are_added_to_total() {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let out = assert_cmd::Command::cargo_bin("loctok")
+        .expect("bin")
+        .arg(&root)
+        .args(["--format", "json", "--no-cache"])
+        .args(["--chat-overhead", "4", "--prompt-overhead", "10"])
+        .output()
+        .expect("run");
+    assert!(out.status.success());
+    let v: serde_json::Value = serde_json::from_slice(&out.stdout).expect("json");
+    let files = v["files"].as_array().unwrap().len() as u64;
+    let content = v["content_tokens"].as_u64().unwrap();
+    assert_eq!(v["chat_overhead"]["tokens"].as_u64(), Some(4 * files));
+    assert_eq!(v["prompt_overhead"], 10);
+    assert_eq!(v["total"].as_u64(), Some(content + 4 * files + 10));
+}
+
+#[test]
+fn tree_columns_align_with_wide_names() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    fs::create_dir_all(dir.path().join("文档")).unwrap();
+    fs::write(dir.path().join("文档/说明.md"), "你好\n").unwrap();
+    fs::write(dir.path().join("a.rs"), "fn a() {}\n").unwrap();
+
+    let out = assert_cmd::Command::cargo_bin("loctok")
+        .expect("bin")
+        .arg(dir.path())
+        .args(["--format", "tree", "--color", "never", "--no-cache"])
+        .output()
+        .expect("run");
+    assert!(out.status.success());
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    let widths: Vec<usize> = stdout
+        .lines()
+        .filter(|l| !l.starts_with('-'))
+        .map(unicode_width::UnicodeWidthStr::width)
+        .collect();
+    assert_eq!(widths.len(), 5);
+    assert!(widths.iter().all(|w| *w == widths[0]), "{stdout}");
+}
+
+#[test]
+fn locale_flag_changes_thousands_separator() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    fs::write(dir.path().join("a.txt"), "word\n".repeat(1500)).unwrap();
+    let run = |locale: &str| {
+        let out = assert_cmd::Command::cargo_bin("loctok")
+            .expect("bin")
+            .arg(dir.path())
+            .args(["--no-cache", "--color", "never", "--locale", locale])
+            .output()
+            .expect("run");
+        assert!(out.status.success());
+        String::from_utf8_lossy(&out.stdout).to_string()
+    };
+    assert!(run("en").contains("1,500"));
+    assert!(run("de").contains("1.500"));
+}
+
+#[test]
+fn no_total_drops_sum_row_and_tree_root() {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let run = |args: &[&str]| {
+        let out = assert_cmd::Command::cargo_bin("loctok")
+            .expect("bin")
+            .arg(&root)
+            .args(["--no-cache", "--color", "never"])
+            .args(args)
+            .output()
+            .expect("run");
+        assert!(out.status.success());
+        String::from_utf8_lossy(&out.stdout).to_string()
+    };
+    assert!(run(&[]).contains("SUM:"));
+    assert!(!run(&["--no-total"]).contains("SUM:"));
+    assert!(run(&["--format", "tree"]).contains("fixtures/"));
+    let tree = run(&["--format", "tree", "--no-total"]);
+    assert!(!tree.contains("fixtures/"));
+    assert!(tree.contains("kept.txt"));
+}
+
+#[test]
+fn tree_files_only_blanks_directory_subtotals() {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let out = assert_cmd::Command::cargo_bin("loctok")
+        .expect("bin")
+        .arg(&root)
+        .args(["--format", "tree", "--tree-files-only", "--no-cache", "--color", "never"])
+        .output()
+        .expect("run");
+    assert!(out.status.success());
+    let tree = String::from_utf8(out.stdout).unwrap();
+    let line = |name: &str| tree.lines().find(|l| l.contains(name)).expect(name).to_string();
+    // The directory stays for structure but has no numbers; files and the root total do
+    assert_eq!(line("nested/"), "├── nested/");
+    assert!(line("kept2.txt").ends_with(" 3"), "{tree}");
+    assert!(line("fixtures/").ends_with(" 6"), "{tree}");
+}
+
+#[test]
+fn width_truncates_long_tree_names_in_the_middle() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let nested = dir.path().join("a_really_long_directory_name/another_long_directory");
+    fs::create_dir_all(&nested).unwrap();
+    fs::write(nested.join("some_extremely_long_file_name_here.rs"), "fn a() {}\n").unwrap();
+    let run = |extra: &[&str]| {
+        let out = assert_cmd::Command::cargo_bin("loctok")
+            .expect("bin")
+            .arg(dir.path())
+            .args(["--format", "tree", "--no-cache", "--color", "never"])
+            .args(extra)
+            .output()
+            .expect("run");
+        assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+        String::from_utf8(out.stdout).unwrap()
+    };
+
+    let narrow = run(&["--width", "40"]);
+    for line in narrow.lines() {
+        assert!(line.chars().count() <= 40, "{line:?}");
+    }
+    let file = narrow.lines().find(|l| l.contains(".rs")).unwrap();
+    assert!(file.contains("some_ex…ere.rs"), "{narrow}");
+    assert!(narrow.contains("another_l…rectory/"), "{narrow}");
+
+    // Piped output without --width keeps full names
+    assert!(run(&[]).contains("some_extremely_long_file_name_here.rs"));
+}
+
+#[test]
+fn explicit_file_arguments_are_counted_directly() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    fs::create_dir_all(dir.path().join("sub")).unwrap();
+    fs::write(dir.path().join("a.rs"), "fn a() {}\n").unwrap();
+    fs::write(dir.path().join("b.rs"), "fn b() {}\n").unwrap();
+    fs::write(dir.path().join("skip.rs"), "fn skip() {}\n").unwrap();
+    fs::write(dir.path().join("sub/c.md"), "# c\n").unwrap();
+    // Explicit files bypass ignore rules, just like a shell glob would
+    fs::write(dir.path().join(".gitignore"), "b.rs\n").unwrap();
+
+    let out = assert_cmd::Command::cargo_bin("loctok")
+        .expect("bin")
+        .current_dir(dir.path())
+        .args(["a.rs", "b.rs", "sub", "--format", "json", "--no-cache"])
+        .output()
+        .expect("run");
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    let v: serde_json::Value = serde_json::from_slice(&out.stdout).expect("json");
+    let mut paths: Vec<String> = v["files"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|f| f["path"].as_str().unwrap().replace('\\', "/"))
+        .collect();
+    paths.sort();
+    assert_eq!(paths, ["a.rs", "b.rs", "sub/c.md"]);
+}
+
+#[test]
+fn explicit_files_pass_the_same_filters_alone_or_together() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    fs::write(dir.path().join("a.rs"), "fn a() {}\n").unwrap();
+    fs::write(dir.path().join("b.json"), "{\"b\": 1}\n").unwrap();
+    let paths = |args: &[&str]| -> Vec<String> {
+        let out = assert_cmd::Command::cargo_bin("loctok")
+            .expect("bin")
+            .current_dir(dir.path())
+            .args(args)
+            .args(["--format", "json", "--no-cache"])
+            .output()
+            .expect("run");
+        assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+        let v: serde_json::Value = serde_json::from_slice(&out.stdout).expect("json");
+        v["files"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|f| f["path"].as_str().unwrap().to_string())
+            .collect()
+    };
+    // The argument count must not change which files pass `--ext` / `--include-lang`
+    for filter in [["--ext", "rs"], ["--include-lang", "rust"]] {
+        assert!(paths(&[&["b.json"], &filter[..]].concat()).is_empty(), "{filter:?}");
+        assert_eq!(paths(&[&["b.json", "a.rs"], &filter[..]].concat()), ["a.rs"], "{filter:?}");
+    }
+    assert_eq!(paths(&["b.json"]), ["b.json"]);
+}
+
+#[test]
+fn stdin_mode_counts_piped_text() {
+    let text = "fn main() {}\n\nlet x = 1;\n";
+    let out = assert_cmd::Command::cargo_bin("loctok")
+        .expect("bin")
+        .arg("--no-cache")
+        .args(["--stdin", "--format", "json", "--encoding", "cl100k_base"])
+        .write_stdin(text)
+        .output()
+        .expect("run");
+    assert!(out.status.success());
+    let v: serde_json::Value = serde_json::from_slice(&out.stdout).expect("json");
+    let encoder = get_encoder("cl100k_base").unwrap();
+    assert_eq!(v["total"], count_tokens_in_text(&encoder, text));
+    assert_eq!(v["files"][0]["lines"], 2);
+}
+
+#[test]
+fn by_language_includes_percentages() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    fs::write(dir.path().join("a.rs"), "fn a() {}\n".repeat(3)).unwrap();
+    fs::write(dir.path().join("b.md"), "# b\n").unwrap();
+
+    let res = count_tokens_in_path(dir.path(), &Options::default()).unwrap();
+    let langs = loctok::aggregate_by_language(&res.files);
+    let rust = langs.iter().find(|l| l.language == "Rust").unwrap();
+    assert_eq!(rust.lines_pct, 75.0);
+    assert_eq!(rust.files, 1);
+    let pct_sum: f64 = langs.iter().map(|l| l.tokens_pct).sum();
+    assert!((pct_sum - 100.0).abs() < 0.2, "{pct_sum}");
+
+    assert_cmd::Command::cargo_bin("loctok")
+        .expect("bin")
+        .arg(dir.path())
+        .args(["--no-cache", "--color", "never"])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("% tokens"))
+        .stdout(predicates::str::contains("75.0%"));
+}
+
+#[test]
+fn bars_format_fills_fixed_width_when_piped() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    fs::write(dir.path().join("a.rs"), "fn a() { let x = 1; }\n".repeat(10)).unwrap();
+    fs::write(dir.path().join("b.md"), "# b\n").unwrap();
+
+    let out = assert_cmd::Command::cargo_bin("loctok")
+        .expect("bin")
+        .arg(dir.path())
+        .args(["--format", "bars", "--no-cache", "--color", "never"])
+        .output()
+        .expect("run");
+    assert!(out.status.success());
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with("Rust"));
+    assert!(lines[0].contains('█'));
+    assert!(lines.iter().all(|l| l.chars().count() == 80), "{stdout}");
+}
+
+#[test]
+fn config_file_sets_defaults_that_flags_override() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    fs::write(dir.path().join("a.rs"), "fn a() {}\n").unwrap();
+    fs::write(dir.path().join("b.py"), "b = 1\n").unwrap();
+    fs::write(
+        dir.path().join("loctok.toml"),
+        "ext = [\"rs\"]\nformat = \"json\"\n",
+    )
+    .unwrap();
+    let home = tempfile::tempdir().expect("tempdir");
+
+    let run = |extra: &[&str]| {
+        let out = assert_cmd::Command::cargo_bin("loctok")
+            .expect("bin")
+            .env("XDG_CONFIG_HOME", home.path())
+            .arg(dir.path())
+            .args(["--no-cache"])
+            .args(extra)
+            .output()
+            .expect("run");
+        assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+        out.stdout
+    };
+    let paths = |stdout: Vec<u8>| -> Vec<String> {
+        let v: serde_json::Value = serde_json::from_slice(&stdout).expect("json");
+        v["files"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|f| f["path"].as_str().unwrap().rsplit('/').next().unwrap().to_string())
+            .collect()
+    };
+
+    assert_eq!(paths(run(&[])), ["a.rs"]);
+    assert_eq!(paths(run(&["--ext", "py"])), ["b.py"]);
+    let table = String::from_utf8(run(&["--no-config"])).unwrap();
+    assert!(table.contains("Python"), "{table}");
+
+    let other = dir.path().join("other.toml");
+    fs::write(&other, "ext = [\"py\"]\nformat = \"json\"\n").unwrap();
+    assert_eq!(paths(run(&["--config", other.to_str().unwrap()])), ["b.py"]);
+}
+
+#[test]
+fn dir_configs_narrow_filters_for_their_subtree() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let files = [
+        "README.md",
+        "main.rs",
+        "frontend/app.ts",
+        "frontend/notes.md",
+        "frontend/style.css",
+        "frontend/legacy/old.ts",
+        "frontend/legacy/view.tsx",
+        "frontend/legacy/view.gen.tsx",
+        "frontend/legacy/sub/deep.tsx",
+    ];
+    for rel in files {
+        let path = dir.path().join(rel);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "x\n").unwrap();
+    }
+    let config = |rel: &str, text: &str| fs::write(dir.path().join(rel), text).unwrap();
+    config(".loctok.toml", "exclude-ext = [\"md\"]\n");
+    // `md` stays excluded and `rs` stays out: a child can only narrow its parents
+    config("frontend/.loctok.toml", "ext = [\"ts\", \"tsx\", \"md\", \"rs\"]\n");
+    config(
+        "frontend/legacy/.loctok.toml",
+        "ext = [\"tsx\", \"css\"]\nexclude = [\"*.gen.tsx\", \"sub/**\"]\n",
+    );
+
+    let rel_paths = |opts: &Options| -> Vec<String> {
+        filtered_paths(dir.path(), opts)
+            .unwrap()
+            .iter()
+            .map(|p| p.strip_prefix(dir.path()).unwrap().to_string_lossy().replace('\\', "/"))
+            .collect()
+    };
+    assert_eq!(
+        rel_paths(&Options::default()),
+        ["frontend/app.ts", "frontend/legacy/view.tsx", "main.rs"]
+    );
+    let all = Options {
+        dir_configs: false,
+        ..Options::default()
+    };
+    assert_eq!(rel_paths(&all).len(), files.len());
+
+    let out = assert_cmd::Command::cargo_bin("loctok")
+        .expect("bin")
+        .arg(dir.path())
+        .args(["--format", "json", "--no-cache", "--no-dir-config"])
+        .output()
+        .expect("run");
+    let report: serde_json::Value = serde_json::from_slice(&out.stdout).expect("json");
+    assert_eq!(report["files"].as_array().unwrap().len(), files.len());
+
+    config("frontend/.loctok.toml", "include = [\"ts\"]\n");
+    let err = filtered_paths(dir.path(), &Options::default()).unwrap_err();
+    assert!(format!("{err:#}").contains("invalid config"), "{err:#}");
+}
+
+#[test]
+fn dir_configs_above_the_root_apply_when_scanning_a_subdirectory() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    for rel in ["frontend/src/app.ts", "frontend/src/app.js", "frontend/src/gen/api.ts"] {
+        let path = dir.path().join(rel);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "x\n").unwrap();
+    }
+    fs::create_dir(dir.path().join(".git")).unwrap();
+    fs::write(
+        dir.path().join("frontend/.loctok.toml"),
+        "ext = [\"ts\"]\nexclude = [\"src/gen/**\"]\n",
+    )
+    .unwrap();
+
+    let src = dir.path().join("frontend/src");
+    let names: Vec<String> = filtered_paths(&src, &Options::default())
+        .unwrap()
+        .iter()
+        .map(|p| p.strip_prefix(&src).unwrap().to_string_lossy().replace('\\', "/"))
+        .collect();
+    assert_eq!(names, ["app.ts"]);
+
+    // Configs above the enclosing repository's root are not consulted
+    fs::write(dir.path().join("frontend/.loctok.toml"), "ext = [\"ts\"]\n").unwrap();
+    fs::create_dir(dir.path().join("frontend/src/.git")).unwrap();
+    assert_eq!(filtered_paths(&src, &Options::default()).unwrap().len(), 3);
+}
+
+#[test]
+fn exclude_common_prunes_noise_directories() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    for rel in ["src/a.rs", "src/target.rs", "node_modules/x/y.js", "pkg/dist/z.js"] {
+        let path = dir.path().join(rel);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "x\n").unwrap();
+    }
+    let names = |opts: &Options| -> Vec<String> {
+        let mut names: Vec<String> = filtered_paths(dir.path(), opts)
+            .unwrap()
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    };
+    assert_eq!(names(&Options::default()).len(), 4);
+    let opts = Options {
+        exclude_common: true,
+        ..Options::default()
+    };
+    // Only directories are matched; a file named like one is kept
+    assert_eq!(names(&opts), ["a.rs", "target.rs"]);
+}
+
+#[test]
+fn exclude_lockfiles_skips_lock_files_only() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    for rel in [
+        "Cargo.lock",
+        "web/package-lock.json",
+        "web/yarn.lock",
+        "web/package.json",
+        "src/lock.rs",
+    ] {
+        let path = dir.path().join(rel);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "x\n").unwrap();
+    }
+    let names = |opts: &Options| -> Vec<String> {
+        let mut names: Vec<String> = filtered_paths(dir.path(), opts)
+            .unwrap()
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    };
+    assert_eq!(names(&Options::default()).len(), 5);
+    let opts = Options {
+        exclude_lockfiles: true,
+        ..Options::default()
+    };
+    assert_eq!(names(&opts), ["lock.rs", "package.json"]);
+}
+
+#[test]
+fn copy_falls_back_to_stdout_without_a_clipboard() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    fs::write(dir.path().join("a.rs"), "fn a() {}\n").unwrap();
+    // An empty PATH hides every clipboard tool
+    let empty = tempfile::tempdir().expect("tempdir");
+    let out = assert_cmd::Command::cargo_bin("loctok")
+        .expect("bin")
+        .env("PATH", empty.path())
+        .args(["copy", "--no-cache"])
+        .arg(dir.path())
+        .output()
+        .expect("run");
+    assert!(out.status.success());
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert!(stdout.contains("/a.rs:\n"), "{stdout}");
+    assert!(!stdout.contains("Copied"));
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(stderr.contains("writing the payload to stdout"), "{stderr}");
+}
+
+#[test]
+fn copy_tree_lines_show_token_counts() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    fs::create_dir(dir.path().join("src")).unwrap();
+    fs::write(dir.path().join("src/a.rs"), "fn a() {}\n").unwrap();
+    fs::write(dir.path().join("src/b.rs"), "fn b() { let x = 1; }\n").unwrap();
+    let empty = tempfile::tempdir().expect("tempdir");
+    let copy = |extra: &[&str]| {
+        let out = assert_cmd::Command::cargo_bin("loctok")
+            .expect("bin")
+            .env("PATH", empty.path())
+            .args(["copy", "--no-cache"])
+            .args(extra)
+            .arg(dir.path())
+            .output()
+            .expect("run");
+        assert!(out.status.success());
+        String::from_utf8(out.stdout).unwrap()
+    };
+
+    let a = loctok::count_str("a.rs", "fn a() {}\n", &Options::default()).unwrap().tokens;
+    let b = loctok::count_str("b.rs", "fn b() { let x = 1; }\n", &Options::default())
+        .unwrap()
+        .tokens;
+    let stdout = copy(&[]);
+    assert!(
+        stdout.starts_with(&format!(
+            "└── src ({} tokens)\n    ├── a.rs ({a} tokens)\n    └── b.rs ({b} tokens)\n",
+            a + b
+        )),
+        "{stdout}"
+    );
+
+    let stdout = copy(&["--no-tree-tokens"]);
+    assert!(stdout.starts_with("└── src\n    ├── a.rs\n    └── b.rs\n"), "{stdout}");
+}
+
+#[test]
+fn normalize_whitespace_lowers_tokens_and_normalizes_copy_output() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let code = "fn a() {\n        let x  =  1;    \n}\n";
+    fs::write(dir.path().join("a.rs"), code).unwrap();
+    let run = |args: &[&str]| {
+        let empty = tempfile::tempdir().expect("tempdir");
+        let out = assert_cmd::Command::cargo_bin("loctok")
+            .expect("bin")
+            .env("PATH", empty.path())
+            .args(args)
+            .args(["--no-cache", "--progress-to", "none"])
+            .arg(dir.path())
+            .output()
+            .expect("run");
+        assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+        String::from_utf8(out.stdout).unwrap()
+    };
+    let totals = |extra: &[&str]| {
+        let report: serde_json::Value =
+            serde_json::from_str(&run(&[&["--format", "json"], extra].concat())).expect("json");
+        (report["total"].as_u64().unwrap(), report["files"][0]["lines"].as_u64().unwrap())
+    };
+
+    let (plain, plain_lines) = totals(&[]);
+    let (normalized, lines) = totals(&["--normalize-whitespace"]);
+    assert!(normalized < plain, "{normalized} >= {plain}");
+    assert_eq!(lines, plain_lines);
+
+    let stdout = run(&["copy", "--no-tree", "--normalize-whitespace"]);
+    assert!(stdout.contains("1 | fn a() {\n2 |  let x = 1;\n3 | }\n"), "{stdout}");
+}
+
+#[test]
+fn copy_strip_comments_reports_savings() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    fs::write(
+        dir.path().join("a.rs"),
+        "// a long comment that costs quite a few tokens\nfn a() {} // and another\n",
+    )
+    .unwrap();
+    fs::write(dir.path().join("notes.md"), "# kept\n").unwrap();
+    let empty = tempfile::tempdir().expect("tempdir");
+    let out = assert_cmd::Command::cargo_bin("loctok")
+        .expect("bin")
+        .env("PATH", empty.path())
+        .args(["copy", "--no-cache", "--strip-comments", "--no-tree"])
+        .arg(dir.path())
+        .output()
+        .expect("run");
+    assert!(out.status.success());
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert!(stdout.contains("1 | fn a() {}\n"), "{stdout}");
+    assert!(!stdout.contains("comment"), "{stdout}");
+    // Markdown has no comment syntax, so `#` headings survive
+    assert!(stdout.contains("1 | # kept\n"), "{stdout}");
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(stderr.contains("stripping comments saved"), "{stderr}");
+}
+
+#[test]
+fn copy_count_overhead_splits_content_and_headers() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    fs::write(dir.path().join("a.rs"), "fn a() {}\n").unwrap();
+    fs::write(dir.path().join("b.txt"), "one\ntwo\nthree\n").unwrap();
+    let empty = tempfile::tempdir().expect("tempdir");
+    let run = |extra: &[&str]| {
+        let out = assert_cmd::Command::cargo_bin("loctok")
+            .expect("bin")
+            .env("PATH", empty.path())
+            .args(["copy", "--no-cache", "--count-overhead"])
+            .args(extra)
+            .arg(dir.path())
+            .output()
+            .expect("run");
+        assert!(out.status.success());
+        String::from_utf8(out.stderr).unwrap()
+    };
+    let nums = |line: &str| -> Vec<usize> {
+        line.split(|c: char| !c.is_ascii_digit())
+            .filter_map(|n| n.parse().ok())
+            .collect()
+    };
+    // "Printed 4 lines (T tokens: C content + O overhead, B bytes)", then one
+    // "  <path>: C content + O overhead" line per file, as (content, overhead)
+    let split = |stderr: &str| -> (Vec<usize>, Vec<(usize, usize)>) {
+        let summary = stderr.lines().find(|l| l.starts_with("Printed")).unwrap();
+        assert!(summary.contains("content +"), "{summary}");
+        let files: Vec<&str> = stderr.lines().filter(|l| l.starts_with("  ")).collect();
+        assert_eq!(files.len(), 2, "{stderr}");
+        assert!(files[0].starts_with("  a.rs: ") && files[1].starts_with("  b.txt: "), "{stderr}");
+        let per_file = files
+            .iter()
+            .map(|l| {
+                let n = nums(l.split_once(": ").unwrap().1);
+                (n[0], n[1])
+            })
+            .collect();
+        (nums(summary), per_file)
+    };
+
+    let stderr = run(&[]);
+    let (total, per_file) = split(&stderr);
+    assert_eq!(total[1], total[2] + total[3], "{stderr}");
+    assert!(total[3] > 0, "{stderr}");
+    assert_eq!(per_file[0].0 + per_file[1].0, total[2], "{stderr}");
+    // Three numbered lines cost more than one; the tree is left to the total
+    assert!(per_file[1].1 > per_file[0].1 && per_file[0].1 > 0, "{stderr}");
+    assert!(per_file[0].1 + per_file[1].1 < total[3], "{stderr}");
+
+    // The `<documents>` wrapper is charged once, to the total, not to every file
+    for extra in [&["--copy-format", "xml"][..], &["--copy-format", "xml", "--no-tree"]] {
+        let stderr = run(extra);
+        let (total, per_file) = split(&stderr);
+        assert!(per_file.iter().all(|&(_, o)| o > 0), "{stderr}");
+        assert!(per_file[0].1 + per_file[1].1 <= total[3], "{stderr}");
+    }
+}
+
+#[test]
+fn copy_sort_orders_files_by_tokens() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    fs::write(dir.path().join("a.txt"), "word ".repeat(20)).unwrap();
+    fs::write(dir.path().join("b.txt"), "word\n").unwrap();
+    fs::write(dir.path().join("c.txt"), "word ".repeat(5)).unwrap();
+    let empty = tempfile::tempdir().expect("tempdir");
+    let sources = |order: &str| -> Vec<String> {
+        let out = assert_cmd::Command::cargo_bin("loctok")
+            .expect("bin")
+            .env("PATH", empty.path())
+            .args(["copy", "--no-cache", "--copy-format", "xml", "--sort", order])
+            .arg(dir.path())
+            .output()
+            .expect("run");
+        assert!(out.status.success());
+        String::from_utf8(out.stdout)
+            .unwrap()
+            .lines()
+            .filter_map(|l| l.strip_prefix("<source>")?.strip_suffix("</source>"))
+            .map(str::to_string)
+            .collect()
+    };
+    assert_eq!(sources("path"), ["a.txt", "b.txt", "c.txt"]);
+    assert_eq!(sources("tokens-asc"), ["b.txt", "c.txt", "a.txt"]);
+    assert_eq!(sources("tokens-desc"), ["a.txt", "c.txt", "b.txt"]);
+}
+
+#[test]
+fn copy_max_lines_per_file_truncates_content() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let body: String = (1..=50).map(|i| format!("line {i}\n")).collect();
+    fs::write(dir.path().join("a.txt"), &body).unwrap();
+    let empty = tempfile::tempdir().expect("tempdir");
+    let out = assert_cmd::Command::cargo_bin("loctok")
+        .expect("bin")
+        .env("PATH", empty.path())
+        .args(["copy", "--no-cache", "--copy-format", "xml", "--no-tree"])
+        .args(["--max-lines-per-file", "2", "--tail-lines", "1"])
+        .arg(dir.path())
+        .output()
+        .expect("run");
+    assert!(out.status.success());
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert!(
+        stdout.contains(
+            "line 1\nline 2\n… (truncated, 47 more lines)\nline 50\n</document_contents>"
+        ),
+        "{stdout}"
+    );
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(stderr.contains("Printed 4 lines"), "{stderr}");
+    assert!(stderr.contains("truncating saved"), "{stderr}");
+}
+
+#[test]
+fn encoding_file_loads_a_custom_vocabulary() {
+    use base64::{engine::general_purpose, Engine as _};
+
+    let dir = tempfile::tempdir().expect("tempdir");
+    let vocab = dir.path().join("tiny.tiktoken");
+    // Every single byte, plus one merge so "ab" is a single token
+    let mut lines: Vec<String> = (0u8..=255)
+        .map(|b| format!("{} {}", general_purpose::STANDARD.encode([b]), b))
+        .collect();
+    lines.push(format!("{} 256", general_purpose::STANDARD.encode("ab")));
+    fs::write(&vocab, lines.join("\n")).unwrap();
+
+    let opts = Options {
+        encoding_file: Some(vocab.clone()),
+        ..Options::default()
+    };
+    assert_eq!(loctok::count_str("x.txt", "abab", &opts).unwrap().tokens, 2);
+    assert_eq!(loctok::count_str("x.txt", "abc", &opts).unwrap().tokens, 2);
+
+    let bad = dir.path().join("bad.tiktoken");
+    fs::write(&bad, "YQ== 0\nnot-a-pair\n").unwrap();
+    let opts = Options {
+        encoding_file: Some(bad),
+        ..Options::default()
+    };
+    let err = count_tokens_in_path(dir.path(), &opts).unwrap_err();
+    assert!(format!("{err:#}").contains("bad.tiktoken:2"), "{err:#}");
+}
+
+#[cfg(feature = "hf")]
+#[test]
+fn hf_tokenizer_counts_with_tokenizer_json() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let path = dir.path().join("tokenizer.json");
+    fs::write(
+        &path,
+        r#"{
+  "version": "1.0",
+  "truncation": null,
+  "padding": null,
+  "added_tokens": [],
+  "normalizer": null,
+  "pre_tokenizer": { "type": "Whitespace" },
+  "post_processor": null,
+  "decoder": null,
+  "model": { "type": "WordLevel", "vocab": { "[UNK]": 0, "hello": 1, "world": 2 }, "unk_token": "[UNK]" }
+}"#,
+    )
+    .unwrap();
+    let opts = Options {
+        hf_tokenizer: Some(path),
+        ..Options::default()
+    };
+    let file = loctok::count_str("x.txt", "hello world hello again", &opts).unwrap();
+    assert_eq!(file.tokens, 4);
+}
+
+#[cfg(not(feature = "hf"))]
+#[test]
+fn hf_tokenizer_requires_the_feature() {
+    let opts = Options {
+        hf_tokenizer: Some(PathBuf::from("tokenizer.json")),
+        ..Options::default()
+    };
+    let err = loctok::count_str("x.txt", "hello", &opts).unwrap_err();
+    assert!(err.to_string().contains("`hf` feature"), "{err}");
+}
+
+#[cfg(feature = "archive")]
+#[test]
+fn archives_are_counted_without_extracting() {
+    use std::io::Write;
+
+    let dir = tempfile::tempdir().expect("tempdir");
+    let files: [(&str, &[u8]); 4] = [
+        ("proj/src/lib.rs", b"pub fn a() {}\n"),
+        ("proj/README.md", b"# hi\n"),
+        ("proj/.hidden.rs", b"fn hidden() {}\n"),
+        ("proj/logo.bin", b"\xff\xfe\x00"),
+    ];
+
+    let zip_path = dir.path().join("code.zip");
+    let mut zip = zip::ZipWriter::new(fs::File::create(&zip_path).unwrap());
+    for (name, text) in files {
+        zip.start_file(name, zip::write::SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(text).unwrap();
+    }
+    zip.finish().unwrap();
+
+    let tgz_path = dir.path().join("code.tar.gz");
+    let gz = flate2::write::GzEncoder::new(
+        fs::File::create(&tgz_path).unwrap(),
+        flate2::Compression::default(),
+    );
+    let mut tar = tar::Builder::new(gz);
+    for (name, text) in files {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(text.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        tar.append_data(&mut header, name, text).unwrap();
+    }
+    tar.into_inner().unwrap().finish().unwrap();
+
+    let opts = Options {
+        include_exts: Some(["rs".to_string()].into_iter().collect()),
+        ..Options::default()
+    };
+    for archive in [&zip_path, &tgz_path] {
+        let result = loctok::count_archive(archive, &opts).expect("count archive");
+        let paths: Vec<&std::path::Path> = result.files.iter().map(|f| f.path.as_path()).collect();
+        assert_eq!(paths, [archive.join("proj/src/lib.rs")], "{archive:?}");
+        assert_eq!(result.files[0].language, "Rust");
+        assert_eq!(
+            result.total,
+            count_tokens_in_text(&get_encoder("o200k_base").unwrap(), "pub fn a() {}\n")
+        );
+    }
+
+    // The CLI routes archive arguments to count_archive
+    let out = assert_cmd::Command::cargo_bin("loctok")
+        .expect("bin")
+        .arg(&zip_path)
+        .args(["--format", "json", "--no-cache"])
+        .output()
+        .expect("run");
+    assert!(out.status.success());
+    let v: serde_json::Value = serde_json::from_slice(&out.stdout).expect("json");
+    assert_eq!(v["files"].as_array().map(Vec::len), Some(2));
+}
+
+#[cfg(not(feature = "archive"))]
+#[test]
+fn archives_require_the_feature() {
+    let err = loctok::count_archive("code.zip", &Options::default()).unwrap_err();
+    assert!(err.to_string().contains("`archive` feature"), "{err}");
+}